@@ -0,0 +1,29 @@
+// Build metadata reported by GET /api/version: the git commit and when the binary was built
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Source tarballs have no .git, the version endpoint then reports "unknown"
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    println!("cargo:rustc-env=SARYCHDB_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=SARYCHDB_BUILD_TIMESTAMP={}", built_at);
+
+    // Re-run when the sources change or HEAD moves, so both values follow the binary
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+}
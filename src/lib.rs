@@ -0,0 +1,20 @@
+//! SarychDB as a library: the search engine, database files and users, without the HTTP server.
+//!
+//! ```
+//! use sarychdb::search::{parallel_search, split_nodes};
+//! use serde_json::json;
+//!
+//! let data = vec![
+//!     json!({"name": "Ada", "lang": "Rust"}),
+//!     json!({"name": "Linus", "lang": "C"}),
+//!     json!({"name": "Grace", "lang": "COBOL"}),
+//! ];
+//! let nodes = split_nodes(&data, 2);
+//! let hits = parallel_search(&nodes, "Rust");
+//! assert_eq!(hits.len(), 1);
+//! assert_eq!(hits[0]["name"], "Ada");
+//! ```
+
+pub mod modules;
+
+pub use modules::{auth, database, search};
@@ -1,7 +1,13 @@
-mod modules;
-
-use modules::server::start_server;
+use sarychdb::modules;
+use modules::auth::{AuthConfig, BCRYPT_MAX_COST, BCRYPT_MIN_COST};
+use modules::database::{DatabaseConfig, EXPIRES_AT_FIELD, IdVersion, PageLimits};
+use modules::config::FileConfig;
+use modules::server::{configure_cors_max_age, configure_max_body_size, configure_op_timeout, start_server};
 use std::env;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 enum Mode {
     Server,
@@ -10,77 +16,213 @@ enum Mode {
 
 struct CliConfig {
     mode: Mode,
+    config_path: Option<String>,
+    data_dir: Option<PathBuf>,
+    host: Option<IpAddr>,
     port: Option<u16>,
     nodes: Option<usize>,
     threads: Option<usize>,
-    silent: bool,
+    parallel_threshold: Option<usize>,
+    page_default: Option<usize>,
+    page_max: Option<usize>,
+    op_timeout: Option<usize>,
+    purge_interval: Option<u64>,
+    cache_cleanup_interval: Option<u64>,
+    user_quota: Option<u64>,
+    bulk_delete_fraction: Option<f64>,
+    compact_storage: Option<bool>,
+    id_version: Option<IdVersion>,
+    max_query_cost: Option<u64>,
+    snapshot_retention: Option<usize>,
+    max_body_size: Option<usize>,
+    cors_max_age: Option<u64>,
+    bcrypt_cost: Option<u32>,
+    bench_file: Option<String>,
+    bench_queries: Option<Vec<String>>,
+    bench_output: Option<BenchOutput>,
+    silent: Option<bool>,
+}
+
+// How benchmark results are reported
+#[derive(Clone, Copy, PartialEq)]
+enum BenchOutput {
+    Text,
+    Json,
+}
+
+impl FromStr for BenchOutput {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "text" => Ok(BenchOutput::Text),
+            "json" => Ok(BenchOutput::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+// Settings for benchmark mode
+struct BenchOptions {
+    file: String,
+    queries: Vec<String>,
+    nodes: Option<usize>,
+    output: BenchOutput,
+}
+
+const DEFAULT_BENCH_FILE: &str = "500MB.json";
+const DEFAULT_BENCH_QUERIES: [&str; 3] = ["T206", "id", "TensorFlow"];
+// Records in the page read by the streamed page benchmark, the default page size
+const BENCH_PAGE_SIZE: usize = 10;
+
+// bcrypt costs below this are only reasonable for tests
+const LOW_BCRYPT_COST: u32 = 10;
+
+// Parse the value following a flag, warning and falling back to the default when missing or invalid
+fn flag_value<T: FromStr>(flag: &str, value: Option<String>) -> Option<T> {
+    match value {
+        Some(value) => match value.parse::<T>() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                eprintln!("⚠️  Invalid value for {}: {} (using default).", flag, value);
+                None
+            }
+        },
+        None => {
+            eprintln!("⚠️  Missing value for {} (using default).", flag);
+            None
+        }
+    }
+}
+
+// Keep a bcrypt cost only when it is inside the range bcrypt accepts
+fn bcrypt_cost_value(source: &str, cost: Option<u32>) -> Option<u32> {
+    match cost {
+        Some(cost) if !(BCRYPT_MIN_COST..=BCRYPT_MAX_COST).contains(&cost) => {
+            eprintln!(
+                "⚠️  {} must be between {} and {} (using default).",
+                source, BCRYPT_MIN_COST, BCRYPT_MAX_COST
+            );
+            None
+        }
+        other => other,
+    }
+}
+
+// Keep a fraction only when it is in (0, 1]
+fn fraction_value(source: &str, fraction: Option<f64>) -> Option<f64> {
+    match fraction {
+        Some(fraction) if !(fraction > 0.0 && fraction <= 1.0) => {
+            eprintln!("⚠️  {} must be greater than 0 and at most 1 (using default).", source);
+            None
+        }
+        other => other,
+    }
+}
+
+// Same as flag_value for counts that must be greater than 0
+fn positive_flag_value(flag: &str, value: Option<String>) -> Option<usize> {
+    match flag_value::<usize>(flag, value) {
+        Some(0) => {
+            eprintln!("⚠️  {} must be greater than 0 (using default).", flag);
+            None
+        }
+        other => other,
+    }
 }
 
 impl CliConfig {
     fn from_args(args: Vec<String>) -> Self {
-        let mut mode = Mode::Server;
-        let mut port = None;
-        let mut nodes = None;
-        let mut threads = None;
-        let mut silent = false;
+        let mut config = CliConfig {
+            mode: Mode::Server,
+            config_path: None,
+            data_dir: None,
+            host: None,
+            port: None,
+            nodes: None,
+            threads: None,
+            parallel_threshold: None,
+            page_default: None,
+            page_max: None,
+            op_timeout: None,
+            purge_interval: None,
+            cache_cleanup_interval: None,
+            user_quota: None,
+            bulk_delete_fraction: None,
+            compact_storage: None,
+            id_version: None,
+            max_query_cost: None,
+            snapshot_retention: None,
+            max_body_size: None,
+            cors_max_age: None,
+            bcrypt_cost: None,
+            bench_file: None,
+            bench_queries: None,
+            bench_output: None,
+            silent: None,
+        };
 
         let mut iter = args.into_iter().skip(1);
         while let Some(arg) = iter.next() {
             match arg.as_str() {
                 "benchmark" => {
-                    mode = Mode::Benchmark;
-                }
-                "--port" => {
-                    if let Some(value) = iter.next() {
-                        match value.parse::<u16>() {
-                            Ok(num) => port = Some(num),
-                            Err(_) => eprintln!(
-                                "⚠️  Invalid value for --port: {} (using default).",
-                                value
-                            ),
-                        }
-                    } else {
-                        eprintln!("⚠️  Missing value for --port (using default).");
-                    }
+                    config.mode = Mode::Benchmark;
                 }
-                "--nodes" => {
-                    if let Some(value) = iter.next() {
-                        match value.parse::<usize>() {
-                            Ok(num) if num > 0 => nodes = Some(num),
-                            Ok(_) => eprintln!(
-                                "⚠️  --nodes must be greater than 0 (using default)."
-                            ),
-                            Err(_) => eprintln!(
-                                "⚠️  Invalid value for --nodes: {} (using default).",
-                                value
-                            ),
-                        }
-                    } else {
-                        eprintln!("⚠️  Missing value for --nodes (using default).");
-                    }
+                "--config" => match iter.next() {
+                    Some(path) => config.config_path = Some(path),
+                    None => eprintln!("⚠️  Missing value for --config (ignoring)."),
+                },
+                "--data-dir" => match iter.next() {
+                    Some(dir) => config.data_dir = Some(PathBuf::from(dir)),
+                    None => eprintln!("⚠️  Missing value for --data-dir (using default)."),
+                },
+                "--host" => config.host = flag_value("--host", iter.next()),
+                "--port" => config.port = flag_value("--port", iter.next()),
+                "--nodes" => config.nodes = positive_flag_value("--nodes", iter.next()),
+                "--threads" => config.threads = positive_flag_value("--threads", iter.next()),
+                "--parallel-threshold" => config.parallel_threshold = positive_flag_value("--parallel-threshold", iter.next()),
+                "--page-default" => config.page_default = positive_flag_value("--page-default", iter.next()),
+                "--page-max" => config.page_max = positive_flag_value("--page-max", iter.next()),
+                "--op-timeout" => config.op_timeout = positive_flag_value("--op-timeout", iter.next()),
+                "--purge-interval" => config.purge_interval = flag_value("--purge-interval", iter.next()),
+                "--cache-cleanup-interval" => config.cache_cleanup_interval = flag_value("--cache-cleanup-interval", iter.next()),
+                "--user-quota" => config.user_quota = flag_value("--user-quota", iter.next()),
+                "--bulk-delete-fraction" => config.bulk_delete_fraction = fraction_value("--bulk-delete-fraction", flag_value("--bulk-delete-fraction", iter.next())),
+                "--compact-storage" => {
+                    config.compact_storage = Some(true);
                 }
-                "--threads" => {
-                    if let Some(value) = iter.next() {
-                        match value.parse::<usize>() {
-                            Ok(num) if num > 0 => threads = Some(num),
-                            Ok(_) => eprintln!(
-                                "⚠️  --threads must be greater than 0 (using default)."
-                            ),
-                            Err(_) => eprintln!(
-                                "⚠️  Invalid value for --threads: {} (using default).",
-                                value
-                            ),
+                "--id-version" => config.id_version = flag_value("--id-version", iter.next()),
+                "--max-query-cost" => config.max_query_cost = flag_value("--max-query-cost", iter.next()),
+                "--snapshot-retention" => config.snapshot_retention = positive_flag_value("--snapshot-retention", iter.next()),
+                "--max-body-size" => config.max_body_size = positive_flag_value("--max-body-size", iter.next()),
+                "--cors-max-age" => config.cors_max_age = flag_value("--cors-max-age", iter.next()),
+                "--bcrypt-cost" => config.bcrypt_cost = bcrypt_cost_value("--bcrypt-cost", flag_value("--bcrypt-cost", iter.next())),
+                "--bench-file" => match iter.next() {
+                    Some(path) => config.bench_file = Some(path),
+                    None => eprintln!("⚠️  Missing value for --bench-file (using default)."),
+                },
+                "--bench-queries" => match iter.next() {
+                    Some(list) => {
+                        let queries: Vec<String> = list
+                            .split(',')
+                            .map(|q| q.trim().to_string())
+                            .filter(|q| !q.is_empty())
+                            .collect();
+                        if queries.is_empty() {
+                            eprintln!("⚠️  --bench-queries needs at least one query (using default).");
+                        } else {
+                            config.bench_queries = Some(queries);
                         }
-                    } else {
-                        eprintln!("⚠️  Missing value for --threads (using default).");
                     }
-                }
+                    None => eprintln!("⚠️  Missing value for --bench-queries (using default)."),
+                },
+                "--bench-output" => config.bench_output = flag_value("--bench-output", iter.next()),
                 "--background" | "--silent" => {
-                    silent = true;
+                    config.silent = Some(true);
                 }
 
                 "--foreground" => {
-                    silent = false;
+                    config.silent = Some(false);
                 }
                 other => {
                     eprintln!("⚠️  Unrecognized argument '{}' - ignoring.", other);
@@ -88,73 +230,299 @@ impl CliConfig {
             }
         }
 
-        CliConfig {
-            mode,
-            port,
-            nodes,
-            threads,
-            silent,
+        config
+    }
+
+    // Fill every setting not given on the command line from the environment, then the config file
+    fn merge_file(&mut self, file: FileConfig) {
+        let env_port = env::var("PORT")
+            .ok()
+            .and_then(|value| value.parse::<u16>().ok());
+        self.port = self.port.or(env_port).or(file.port);
+
+        let env_data_dir = env::var("SARYCH_DATA_DIR")
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from);
+        self.data_dir = self.data_dir.take().or(env_data_dir).or(file.data_dir);
+
+        if self.host.is_none()
+            && let Some(host) = file.host {
+            match host.parse::<IpAddr>() {
+                Ok(ip) => self.host = Some(ip),
+                Err(_) => eprintln!("⚠️  Invalid host in config file: {} (using default).", host),
+            }
         }
+
+        let positive = |name: &str, value: Option<usize>| match value {
+            Some(0) => {
+                eprintln!("⚠️  {} in config file must be greater than 0 (using default).", name);
+                None
+            }
+            other => other,
+        };
+        self.nodes = self.nodes.or(positive("nodes", file.nodes));
+        self.threads = self.threads.or(positive("threads", file.threads));
+        self.parallel_threshold = self.parallel_threshold.or(positive("parallel_threshold", file.parallel_threshold));
+        self.page_default = self.page_default.or(positive("page_default", file.page_default));
+        self.page_max = self.page_max.or(positive("page_max", file.page_max));
+        self.op_timeout = self.op_timeout.or(positive("op_timeout", file.op_timeout));
+        self.purge_interval = self.purge_interval.or(file.purge_interval);
+        self.cache_cleanup_interval = self.cache_cleanup_interval.or(file.cache_cleanup_interval);
+        self.user_quota = self.user_quota.or(file.user_quota);
+        self.bulk_delete_fraction = self.bulk_delete_fraction.or(fraction_value("bulk_delete_fraction in config file", file.bulk_delete_fraction));
+        self.compact_storage = self.compact_storage.or(file.compact_storage);
+        self.id_version = self.id_version.or_else(|| file.id_version.and_then(|version| flag_value("id_version in config file", Some(version))));
+        self.max_query_cost = self.max_query_cost.or(file.max_query_cost);
+        self.snapshot_retention = self.snapshot_retention.or(positive("snapshot_retention", file.snapshot_retention));
+        self.max_body_size = self.max_body_size.or(positive("max_body_size", file.max_body_size));
+        self.cors_max_age = self.cors_max_age.or(file.cors_max_age);
+        self.bcrypt_cost = self.bcrypt_cost.or(bcrypt_cost_value("bcrypt_cost in config file", file.bcrypt_cost));
+        self.bench_file = self.bench_file.take().or(file.bench_file);
+        self.bench_queries = self.bench_queries.take().or(file.bench_queries);
+        self.silent = self.silent.or(file.silent);
     }
 }
 
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
-    let config = CliConfig::from_args(args);
+    let mut config = CliConfig::from_args(args);
+
+    let file_config = match config.config_path.as_deref() {
+        Some(path) => match FileConfig::load(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => FileConfig::default(),
+    };
+    config.merge_file(file_config);
+    let silent = config.silent.unwrap_or(false);
 
-    // Configure thread pool if specified
-    if let Some(threads) = config.threads {
+    // Build the search thread pool before anything runs on it, with --threads / threads or one per CPU
+    {
         use modules::search::configure_thread_pool;
-        configure_thread_pool(Some(threads));
-        if !config.silent {
-            println!("⚙️  Configured thread pool with {} threads", threads);
+        match configure_thread_pool(config.threads) {
+            Ok(threads) => {
+                if !silent {
+                    println!("⚙️  Thread pool: {} threads", threads);
+                }
+            }
+            Err(e) => eprintln!("⚠️  {}", e),
+        }
+    }
+    if let Some(threshold) = config.parallel_threshold {
+        use modules::search::configure_parallel_threshold;
+        configure_parallel_threshold(threshold);
+        if !silent {
+            println!("⚙️  Parallel search from {} records", threshold);
         }
     }
 
     match config.mode {
-        Mode::Benchmark => run_benchmark_mode(config.nodes, config.silent).await,
-        Mode::Server => run_server_mode(config.port, config.silent).await,
+        Mode::Benchmark => {
+            let options = BenchOptions {
+                file: config.bench_file.unwrap_or_else(|| DEFAULT_BENCH_FILE.to_string()),
+                queries: config.bench_queries.unwrap_or_else(|| {
+                    DEFAULT_BENCH_QUERIES.iter().map(|q| q.to_string()).collect()
+                }),
+                nodes: config.nodes,
+                output: config.bench_output.unwrap_or(BenchOutput::Text),
+            };
+            run_benchmark_mode(options, silent).await
+        }
+        Mode::Server => {
+            let mut auth_config = AuthConfig::default();
+            let mut db_config = DatabaseConfig::default();
+            if let Some(dir) = config.data_dir {
+                if !silent {
+                    println!("📂 Data directory: {}", dir.display());
+                }
+                auth_config.data_dir = dir.clone();
+                db_config.data_dir = dir;
+            }
+            db_config.page_limits = page_limits(config.page_default, config.page_max, silent);
+            if let Some(secs) = config.op_timeout {
+                configure_op_timeout(Duration::from_secs(secs as u64));
+                if !silent {
+                    println!("⏱️  Operation timeout: {} s", secs);
+                }
+            }
+            if let Some(secs) = config.purge_interval {
+                db_config.purge_interval = Duration::from_secs(secs);
+                if !silent {
+                    match secs {
+                        0 => println!("🧹 Expired record purge disabled"),
+                        _ => println!("🧹 Expired records purged every {} s", secs),
+                    }
+                }
+            }
+            if let Some(secs) = config.cache_cleanup_interval {
+                db_config.cache_cleanup_interval = Duration::from_secs(secs);
+                if !silent {
+                    match secs {
+                        0 => println!("🗑️  Periodic cache cleanup disabled"),
+                        _ => println!("🗑️  Expired cache entries evicted every {} s", secs),
+                    }
+                }
+            }
+            if let Some(bytes) = config.max_body_size {
+                configure_max_body_size(bytes as u64);
+                if !silent {
+                    println!("📦 Max request body: {} bytes", bytes);
+                }
+            }
+            if let Some(secs) = config.cors_max_age {
+                configure_cors_max_age(Duration::from_secs(secs));
+                if !silent {
+                    println!("🌐 CORS preflight cached for {} s", secs);
+                }
+            }
+            if let Some(fraction) = config.bulk_delete_fraction {
+                db_config.bulk_delete_fraction = fraction;
+                if !silent {
+                    println!("🛡️  Deletes over {}% of a database need confirmBulk", (fraction * 100.0).round());
+                }
+            }
+            if config.compact_storage == Some(true) {
+                db_config.compact_storage = true;
+                if !silent {
+                    println!("🗜️  Database files stored as compact JSON");
+                }
+            }
+            if let Some(version) = config.id_version {
+                db_config.id_version = version;
+                if !silent {
+                    println!("🆔 Generated ids: UUID {}", version.as_str());
+                }
+            }
+            if let Some(cost) = config.max_query_cost {
+                db_config.max_query_cost = Some(cost);
+                if !silent {
+                    println!("🧮 Searches estimated over {} need force", cost);
+                }
+            }
+            if let Some(keep) = config.snapshot_retention {
+                db_config.snapshot_retention = Some(keep);
+                if !silent {
+                    println!("📸 Snapshots kept per database: {}", keep);
+                }
+            }
+            if let Some(bytes) = config.user_quota {
+                db_config.user_quota = Some(bytes);
+                if !silent {
+                    println!("💾 Storage quota: {} bytes per user", bytes);
+                }
+            }
+            if let Some(cost) = config.bcrypt_cost {
+                auth_config.bcrypt_cost = cost;
+                if cost < LOW_BCRYPT_COST {
+                    eprintln!("⚠️  bcrypt cost {} is low: password hashes will be fast to brute-force.", cost);
+                } else if !silent {
+                    println!("🔑 bcrypt cost: {}", cost);
+                }
+            }
+            let host = config.host.unwrap_or(IpAddr::from([127, 0, 0, 1]));
+            run_server_mode(host, config.port.unwrap_or(3030), auth_config, db_config, silent).await
+        }
     }
 }
 
-async fn run_server_mode(port_override: Option<u16>, silent: bool) {
-    let port = port_override
-        .or_else(|| {
-            env::var("PORT")
-                .ok()
-                .and_then(|value| value.parse::<u16>().ok())
-        })
-        .unwrap_or(3030);
+fn page_limits(page_default: Option<usize>, page_max: Option<usize>, silent: bool) -> PageLimits {
+    let defaults = PageLimits::default();
+    let max = page_max.unwrap_or(defaults.max);
+    let mut default = page_default.unwrap_or(defaults.default);
+    if default > max {
+        eprintln!(
+            "⚠️  --page-default ({}) exceeds --page-max ({}) - using {}.",
+            default, max, max
+        );
+        default = max;
+    }
 
+    if !silent {
+        println!("📄 Page size: default {}, max {}", default, max);
+    }
+    PageLimits { default, max }
+}
+
+async fn run_server_mode(host: IpAddr, port: u16, auth_config: AuthConfig, db_config: DatabaseConfig, silent: bool) {
     if !silent {
         println!("🌟 SarychDB - Parallel Database System");
         println!("======================================");
-        println!("🚀 Starting server on port {}", port);
+        println!("🚀 Starting server on {}:{}", host, port);
     }
     
-    start_server(port).await;
+    start_server(host, port, auth_config, db_config, silent).await;
 }
 
-async fn run_benchmark_mode(nodes_override: Option<usize>, silent: bool) {
+async fn run_benchmark_mode(options: BenchOptions, silent: bool) {
     use std::time::Instant;
-    use modules::search::{Item, load_json, split_nodes, centralized_search, sequential_search, parallel_search, smart_search, get_optimal_node_count};
+    use modules::search::{Item, load_json, load_json_mmap, load_json_page, split_nodes, centralized_search, sequential_search, parallel_search, smart_search, get_optimal_node_count, result_fingerprint};
     
+    // JSON output must stay parseable, so the decorative text is dropped
+    let json_output = options.output == BenchOutput::Json;
+    let quiet = silent || json_output;
+
     let optimal_nodes = get_optimal_node_count();
-    let num_nodes = nodes_override.unwrap_or(optimal_nodes);
+    let num_nodes = options.nodes.unwrap_or(optimal_nodes);
     
-    if !silent {
+    if !quiet {
         println!("🔧 CPU has {} optimal cores available", optimal_nodes);
         println!("Running benchmark with {} nodes", num_nodes);
     }
 
-    let data: Vec<Item> = load_json("500MB.json");
-    let nodes = split_nodes(data, num_nodes);
+    if !std::path::Path::new(&options.file).is_file() {
+        eprintln!(
+            "❌ Benchmark dataset '{}' not found. Use --bench-file <path> to point at a JSON array file.",
+            options.file
+        );
+        std::process::exit(1);
+    }
 
-    let queries = ["T206", "id", "TensorFlow"];
+    let start = Instant::now();
+    let data: Vec<Item> = match load_json(&options.file) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    let load_time = start.elapsed().as_millis();
 
-    for &query in &queries {
-        if !silent {
+    // Compare the buffered reader with the memory-mapped loader
+    if !quiet {
+        let start = Instant::now();
+        let mapped = load_json_mmap(&options.file);
+        let mmap_time = start.elapsed().as_millis();
+        println!("\n📂 Load: buffered reader {} ms", load_time);
+        match mapped {
+            Ok(_) => println!("📂 Load: memory map {} ms", mmap_time),
+            Err(e) => println!("📂 Load: memory map failed: {}", e),
+        }
+
+        // What an uncached browse of page 1 costs now that only the page is built
+        let start = Instant::now();
+        let page = load_json_page(&options.file, 0, BENCH_PAGE_SIZE, EXPIRES_AT_FIELD, |_| true);
+        let page_time = start.elapsed().as_millis();
+        match page {
+            Ok(page) => println!(
+                "📄 Page 1 ({} of {} records): full load {} ms, streamed {} ms",
+                page.records.len(), page.total, load_time, page_time
+            ),
+            Err(e) => println!("📄 Page 1: streamed read failed: {}", e),
+        }
+    }
+    let nodes = split_nodes(&data, num_nodes);
+
+    let mut report = Vec::new();
+    let mut mismatches = Vec::new();
+
+    for query in options.queries.iter().map(String::as_str) {
+        if !quiet {
             println!("\n🔎 Benchmark for query: \"{}\"", query);
         }
 
@@ -174,11 +542,80 @@ async fn run_benchmark_mode(nodes_override: Option<usize>, silent: bool) {
         let r4 = smart_search(&nodes, query);
         let t4 = start.elapsed().as_millis();
 
-        if !silent {
+        // Every method must return the same records, whatever their order
+        let expected = result_fingerprint(&r1);
+        let consistent = [&r2, &r3, &r4]
+            .iter()
+            .all(|results| result_fingerprint(results) == expected);
+        if !consistent {
+            mismatches.push(query.to_string());
+        }
+
+        if json_output {
+            let runs = [
+                ("centralized", r1.len(), t1),
+                ("sequential", r2.len(), t2),
+                ("parallel", r3.len(), t3),
+                ("smart", r4.len(), t4),
+            ];
+            for (method, results, time_ms) in runs {
+                report.push(serde_json::json!({
+                    "query": query,
+                    "method": method,
+                    "results": results,
+                    "time_ms": time_ms,
+                    "consistent": consistent
+                }));
+            }
+        } else if !silent {
             println!("Centralized: {} results in {} ms", r1.len(), t1);
             println!("Sequential multi-node: {} results in {} ms", r2.len(), t2);
             println!("Parallel multi-node: {} results in {} ms", r3.len(), t3);
             println!("Smart search (auto): {} results in {} ms ⭐", r4.len(), t4);
+            if consistent {
+                println!("✅ All methods returned the same {} records", r1.len());
+            } else {
+                println!(
+                    "❌ Result mismatch: centralized {}, sequential {}, parallel {}, smart {}",
+                    r1.len(), r2.len(), r3.len(), r4.len()
+                );
+            }
         }
     }
-}
\ No newline at end of file
+
+    if json_output {
+        println!("{}", serde_json::Value::Array(report));
+    }
+
+    if !mismatches.is_empty() {
+        eprintln!("❌ Search methods disagree for queries: {}", mismatches.join(", "));
+        std::process::exit(1);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn args(flags: &[&str]) -> Vec<String> {
+        std::iter::once("sarychdb").chain(flags.iter().copied()).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn flags_override_the_config_file_which_overrides_the_defaults() {
+        let dir = env::temp_dir().join(format!("sarychdb-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sarychdb.toml");
+        fs::write(&path, "nodes = 4\nthreads = 2\npage_max = 500\n").unwrap();
+
+        let mut config = CliConfig::from_args(args(&["--config", path.to_str().unwrap(), "--nodes", "8"]));
+        config.merge_file(FileConfig::load(path.to_str().unwrap()).unwrap());
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(config.nodes, Some(8));
+        assert_eq!(config.threads, Some(2));
+        let limits = page_limits(config.page_default, config.page_max, true);
+        assert_eq!((limits.default, limits.max), (PageLimits::default().default, 500));
+        assert_eq!(config.op_timeout, None);
+    }
+}
@@ -1,11 +1,55 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use once_cell::sync::Lazy;
 use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::Utc;
+
+// What a user may do with a database, each level includes the ones before it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLevel {
+    Read,
+    Write,
+    #[default]
+    Admin,
+}
+
+impl AccessLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessLevel::Read => "read",
+            AccessLevel::Write => "write",
+            AccessLevel::Admin => "admin",
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Database {
     pub namedb: String,
+    // Entries written before access levels existed are the user's own databases
+    #[serde(default)]
+    pub access: AccessLevel,
+    // Set when another user owns the database and granted access to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    // RFC3339, missing for entries created before timestamps were recorded and for grants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    // Anyone may read a public database without credentials, only set on the owner's entry
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub public: bool,
+}
+
+// Where a database a user can reach is stored, and what the user may do with it
+#[derive(Debug, Clone)]
+pub struct DatabaseAccess {
+    pub owner: String,
+    pub level: AccessLevel,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -13,6 +57,17 @@ pub struct User {
     pub user: String,
     pub password: String, // Password hash
     pub db: Vec<Database>,
+    // RFC3339, missing for users created before timestamps were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+}
+
+// Account details safe to return to the user, without the password hash
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub user: String,
+    pub created_at: Option<String>,
+    pub db: Vec<Database>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,37 +81,136 @@ pub struct CreateDbRequest {
     pub username: String,
     pub password: String,
     pub db_name: String,
+    #[serde(default)]
+    pub public: bool,
 }
 
-const USERS_FILE: &str = "users.json";
+// Make one of the user's own databases readable without credentials, or private again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetPublicRequest {
+    pub username: String,
+    pub password: String,
+    pub db_name: String,
+    pub public: bool,
+}
 
-pub struct AuthService;
+// Give another user access to a database, or change the level already granted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GrantDbRequest {
+    pub username: String,
+    pub password: String,
+    pub db_name: String,
+    pub grantee: String,
+    pub access: AccessLevel,
+}
+
+// Both live directly under the data directory
+pub const USERS_FILE: &str = "users.json";
+pub const USERS_DIR: &str = "users";
+
+// Valid bcrypt cost range, each step doubles the hashing time
+pub const BCRYPT_MIN_COST: u32 = 4;
+pub const BCRYPT_MAX_COST: u32 = 31;
+
+/// Settings an `AuthService` is built with
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// Root holding users.json and the users/ folder
+    pub data_dir: PathBuf,
+    /// Cost for new password hashes; existing hashes keep the cost they were made with
+    pub bcrypt_cost: u32,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            data_dir: PathBuf::from("."),
+            bcrypt_cost: DEFAULT_COST,
+        }
+    }
+}
+
+// Parsed users.json together with the file's modification time when it was read
+struct UsersCache {
+    users: Vec<User>,
+    modified: Option<SystemTime>,
+}
+
+// Users are read from disk only when users.json changed (saved by us or edited externally),
+// one entry per users.json path so services with different data directories don't mix
+static USERS_CACHE: Lazy<RwLock<HashMap<PathBuf, UsersCache>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub struct AuthService {
+    config: AuthConfig,
+}
+
+impl Default for AuthService {
+    fn default() -> Self {
+        Self::new(AuthConfig::default())
+    }
+}
 
 impl AuthService {
-    pub fn new() -> Self {
+    pub fn new(config: AuthConfig) -> Self {
+        let service = Self { config };
+        // Initialize the folder holding every user's databases (and the data directory itself)
+        let users_dir = service.users_dir();
+        if !users_dir.exists() {
+            let _ = fs::create_dir_all(&users_dir);
+        }
         // Initialize users.json file if it doesn't exist
-        if !Path::new(USERS_FILE).exists() {
+        let users_file = service.users_file();
+        if !users_file.exists() {
             let empty_users: Vec<User> = vec![];
             let json = serde_json::to_string_pretty(&empty_users).unwrap();
-            fs::write(USERS_FILE, json).unwrap();
+            fs::write(&users_file, json).unwrap();
         }
-        Self
+        service
     }
 
-    pub fn load_users() -> Result<Vec<User>, Box<dyn std::error::Error>> {
-        let data = fs::read_to_string(USERS_FILE)?;
+    pub fn config(&self) -> &AuthConfig {
+        &self.config
+    }
+
+    pub fn users_file(&self) -> PathBuf {
+        self.config.data_dir.join(USERS_FILE)
+    }
+
+    pub fn users_dir(&self) -> PathBuf {
+        self.config.data_dir.join(USERS_DIR)
+    }
+
+    pub fn load_users(&self) -> Result<Vec<User>, Box<dyn std::error::Error>> {
+        let users_file = self.users_file();
+        let modified = fs::metadata(&users_file)?.modified().ok();
+
+        // Serve from memory while the file is unchanged
+        if let Some(cache) = USERS_CACHE.read().unwrap().get(&users_file)
+            && modified.is_some()
+            && cache.modified == modified {
+            return Ok(cache.users.clone());
+        }
+
+        let data = fs::read_to_string(&users_file)?;
         let users: Vec<User> = serde_json::from_str(&data)?;
+        USERS_CACHE.write().unwrap().insert(users_file, UsersCache {
+            users: users.clone(),
+            modified,
+        });
         Ok(users)
     }
 
-    pub fn save_users(users: &Vec<User>) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save_users(&self, users: &Vec<User>) -> Result<(), Box<dyn std::error::Error>> {
+        let users_file = self.users_file();
         let json = serde_json::to_string_pretty(users)?;
-        fs::write(USERS_FILE, json)?;
+        fs::write(&users_file, json)?;
+        // Next load re-reads the file
+        USERS_CACHE.write().unwrap().remove(&users_file);
         Ok(())
     }
 
     pub fn create_user(&self, request: CreateUserRequest) -> Result<String, String> {
-        let mut users = Self::load_users().map_err(|e| e.to_string())?;
+        let mut users = self.load_users().map_err(|e| e.to_string())?;
         
         // Check if user already exists
         if users.iter().any(|u| u.user == request.username) {
@@ -70,11 +224,11 @@ impl AuthService {
         }
 
         // Hash the password
-        let password_hash = hash(request.password.as_bytes(), DEFAULT_COST)
+        let password_hash = hash(request.password.as_bytes(), self.config.bcrypt_cost)
             .map_err(|e| e.to_string())?;
 
         // Create user folder
-        let user_dir = format!("users/{}", request.username);
+        let user_dir = self.users_dir().join(&request.username);
         fs::create_dir_all(&user_dir).map_err(|e| format!("Error creating user folder: {}", e))?;
 
         // Create new user
@@ -82,56 +236,78 @@ impl AuthService {
             user: request.username.clone(),
             password: password_hash,
             db: vec![],
+            created_at: Some(Utc::now().to_rfc3339()),
         };
 
         users.push(new_user);
-        Self::save_users(&users).map_err(|e| e.to_string())?;
+        self.save_users(&users).map_err(|e| e.to_string())?;
 
-        Ok(format!("User '{}' created successfully with folder at: {}", request.username, user_dir))
+        Ok(format!("User '{}' created successfully with folder at: {}", request.username, user_dir.display()))
     }
 
     pub fn authenticate(&self, username: &str, password: &str) -> Result<bool, String> {
-        let users = Self::load_users().map_err(|e| e.to_string())?;
-        
-        if let Some(user) = users.iter().find(|u| u.user == username) {
-            verify(password, &user.password).map_err(|e| e.to_string())
-        } else {
-            Ok(false)
-        }
+        Ok(self.authenticated_user(username, password)?.is_some())
     }
 
-    pub fn create_database(&self, request: CreateDbRequest) -> Result<String, String> {
-        // Verify authentication
-        if !self.authenticate(&request.username, &request.password)? {
-            return Err("Invalid credentials".to_string());
+    // Load the users and verify the password once, returning the user when the credentials match
+    pub fn authenticated_user(&self, username: &str, password: &str) -> Result<Option<User>, String> {
+        let users = self.load_users().map_err(|e| e.to_string())?;
+
+        match users.into_iter().find(|u| u.user == username) {
+            Some(user) if verify(password, &user.password).map_err(|e| e.to_string())? => Ok(Some(user)),
+            _ => Ok(None),
         }
+    }
+
+    // Resolve the owner and access level of one of the user's database entries (exact, case-sensitive name)
+    pub fn database_access(user: &User, db_name: &str) -> Option<DatabaseAccess> {
+        user.db.iter().find(|db| db.namedb == db_name).map(|db| DatabaseAccess {
+            owner: db.owner.clone().unwrap_or_else(|| user.user.clone()),
+            level: db.access,
+        })
+    }
 
+    pub fn create_database(&self, request: CreateDbRequest) -> Result<String, String> {
         // Validate database name
         if request.db_name.is_empty() || request.db_name.contains(' ') || 
            request.db_name.contains('/') || request.db_name.contains('\\') {
             return Err("Invalid database name. Cannot contain spaces or special characters".to_string());
         }
 
-        let mut users = Self::load_users().map_err(|e| e.to_string())?;
+        // <db>.settings.json holds the settings of <db>, so the name would collide
+        if request.db_name.ends_with(".settings") {
+            return Err("Invalid database name. Cannot end with '.settings'".to_string());
+        }
+
+        // <db>.snapshot.<timestamp>.json are snapshots of <db>, see DatabaseManager::snapshot_database
+        if request.db_name.contains(".snapshot.") {
+            return Err("Invalid database name. Cannot contain '.snapshot.'".to_string());
+        }
+
+        let mut users = self.load_users().map_err(|e| e.to_string())?;
         
-        // Find the user
+        // Find the user and verify authentication against the list we are about to modify
         if let Some(user) = users.iter_mut().find(|u| u.user == request.username) {
+            if !verify(&request.password, &user.password).map_err(|e| e.to_string())? {
+                return Err("Invalid credentials".to_string());
+            }
+
             // Check if DB already exists
             if user.db.iter().any(|db| db.namedb == request.db_name) {
                 return Err("Database already exists for this user".to_string());
             }
 
             // Create empty JSON file for the DB in user folder
-            let user_dir = format!("users/{}", request.username);
-            let db_filepath = format!("{}/{}.json", user_dir, request.db_name);
+            let user_dir = self.users_dir().join(&request.username);
+            let db_filepath = user_dir.join(format!("{}.json", request.db_name));
             
             // Verify that user folder exists
-            if !Path::new(&user_dir).exists() {
+            if !user_dir.exists() {
                 fs::create_dir_all(&user_dir).map_err(|e| format!("Error creating user folder: {}", e))?;
             }
 
             // Check if file already exists with that name (prevent global duplicates)
-            if Path::new(&db_filepath).exists() {
+            if db_filepath.exists() {
                 return Err("File with that name already exists in user folder".to_string());
             }
 
@@ -142,40 +318,286 @@ impl AuthService {
             // Add DB to user
             user.db.push(Database {
                 namedb: request.db_name.clone(),
+                access: AccessLevel::Admin,
+                owner: None,
+                created_at: Some(Utc::now().to_rfc3339()),
+                public: request.public,
             });
 
-            Self::save_users(&users).map_err(|e| e.to_string())?;
-            Ok(format!("Database '{}' created successfully at: {}", request.db_name, db_filepath))
+            self.save_users(&users).map_err(|e| e.to_string())?;
+            Ok(format!("Database '{}' created successfully at: {}", request.db_name, db_filepath.display()))
         } else {
-            Err("User not found".to_string())
+            Err("Invalid credentials".to_string())
         }
     }
 
     pub fn get_user_databases(&self, username: &str, password: &str) -> Result<Vec<Database>, String> {
-        if !self.authenticate(username, password)? {
+        match self.authenticated_user(username, password)? {
+            Some(user) => Ok(user.db),
+            None => Err("Invalid credentials".to_string()),
+        }
+    }
+
+    pub fn get_user_info(&self, username: &str, password: &str) -> Result<UserInfo, String> {
+        match self.authenticated_user(username, password)? {
+            Some(user) => Ok(UserInfo {
+                user: user.user,
+                created_at: user.created_at,
+                db: user.db,
+            }),
+            None => Err("Invalid credentials".to_string()),
+        }
+    }
+
+    // Where one of the user's databases lives and the user's level on it, None when it isn't theirs
+    pub fn user_database_access(&self, username: &str, password: &str, db_name: &str) -> Result<Option<DatabaseAccess>, String> {
+        match self.authenticated_user(username, password)? {
+            Some(user) => Ok(Self::database_access(&user, db_name)),
+            None => Err("Invalid credentials".to_string()),
+        }
+    }
+
+    pub fn set_database_public(&self, request: SetPublicRequest) -> Result<String, String> {
+        let mut users = self.load_users().map_err(|e| e.to_string())?;
+
+        let user = users.iter_mut()
+            .find(|u| u.user == request.username)
+            .ok_or_else(|| "Invalid credentials".to_string())?;
+        if !verify(&request.password, &user.password).map_err(|e| e.to_string())? {
             return Err("Invalid credentials".to_string());
         }
 
-        let users = Self::load_users().map_err(|e| e.to_string())?;
-        
-        if let Some(user) = users.iter().find(|u| u.user == username) {
-            Ok(user.db.clone())
-        } else {
-            Err("User not found".to_string())
+        // Only the owner decides, grantees (even admins) can't publish someone else's data
+        let db = user.db.iter_mut()
+            .find(|db| db.namedb == request.db_name)
+            .ok_or_else(|| "Database not found for this user".to_string())?;
+        if db.owner.is_some() {
+            return Err(format!("Only the owner of '{}' can change whether it is public", request.db_name));
         }
+        db.public = request.public;
+
+        self.save_users(&users).map_err(|e| e.to_string())?;
+        let visibility = if request.public { "public" } else { "private" };
+        Ok(format!("Database '{}' is now {}", request.db_name, visibility))
     }
 
-    pub fn user_has_database(&self, username: &str, password: &str, db_name: &str) -> Result<bool, String> {
-        if !self.authenticate(username, password)? {
+    // Whether `owner` has a database named `db_name` flagged public
+    pub fn is_public_database(&self, owner: &str, db_name: &str) -> Result<bool, String> {
+        let users = self.load_users().map_err(|e| e.to_string())?;
+        Ok(users.iter()
+            .find(|u| u.user == owner)
+            .and_then(|u| u.db.iter().find(|db| db.namedb == db_name && db.owner.is_none()))
+            .is_some_and(|db| db.public))
+    }
+
+    pub fn grant_database(&self, request: GrantDbRequest) -> Result<String, String> {
+        let mut users = self.load_users().map_err(|e| e.to_string())?;
+
+        let granter = users.iter()
+            .find(|u| u.user == request.username)
+            .ok_or_else(|| "Invalid credentials".to_string())?;
+        if !verify(&request.password, &granter.password).map_err(|e| e.to_string())? {
             return Err("Invalid credentials".to_string());
         }
 
-        let users = Self::load_users().map_err(|e| e.to_string())?;
-        
-        if let Some(user) = users.iter().find(|u| u.user == username) {
-            Ok(user.db.iter().any(|db| db.namedb == db_name))
-        } else {
-            Err("User not found".to_string())
+        // Only admins of the database may hand out access to it
+        let access = Self::database_access(granter, &request.db_name)
+            .ok_or_else(|| "Database not found for this user".to_string())?;
+        if access.level < AccessLevel::Admin {
+            return Err(format!("Admin access to '{}' is required to grant access", request.db_name));
+        }
+        if request.grantee == access.owner {
+            return Err("The owner of a database always keeps admin access".to_string());
+        }
+
+        let grantee = users.iter_mut()
+            .find(|u| u.user == request.grantee)
+            .ok_or_else(|| format!("User '{}' not found", request.grantee))?;
+        match grantee.db.iter_mut().find(|db| db.namedb == request.db_name) {
+            // Already granted from this owner: change the level
+            Some(db) if db.owner.as_deref() == Some(access.owner.as_str()) => db.access = request.access,
+            Some(_) => return Err(format!("User '{}' already has a database named '{}'", request.grantee, request.db_name)),
+            None => grantee.db.push(Database {
+                namedb: request.db_name.clone(),
+                access: request.access,
+                owner: Some(access.owner.clone()),
+                created_at: None,
+                public: false,
+            }),
+        }
+
+        self.save_users(&users).map_err(|e| e.to_string())?;
+        Ok(format!("Granted {} access to '{}' for user '{}'", request.access.as_str(), request.db_name, request.grantee))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    // An AuthService over its own data directory, removed when the test ends
+    struct TestAuth {
+        service: AuthService,
+    }
+
+    impl TestAuth {
+        fn new() -> Self {
+            Self::with_cost(BCRYPT_MIN_COST)
+        }
+
+        fn with_cost(bcrypt_cost: u32) -> Self {
+            let data_dir = std::env::temp_dir().join(format!("sarychdb-test-{}", uuid::Uuid::new_v4()));
+            TestAuth { service: AuthService::new(AuthConfig { data_dir, bcrypt_cost }) }
+        }
+
+        fn user(&self, username: &str) {
+            self.service.create_user(CreateUserRequest { username: username.to_string(), password: "secret".to_string() }).unwrap();
+        }
+    }
+
+    impl std::ops::Deref for TestAuth {
+        type Target = AuthService;
+
+        fn deref(&self) -> &AuthService {
+            &self.service
+        }
+    }
+
+    impl Drop for TestAuth {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.service.config.data_dir);
         }
     }
-}
\ No newline at end of file
+
+    fn set_modified(path: &std::path::Path, modified: SystemTime) {
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn saved_users_are_seen_by_the_next_authentication() {
+        let auth = TestAuth::new();
+        assert!(!auth.authenticate("alice", "secret").unwrap());
+
+        auth.user("alice");
+
+        assert!(auth.authenticate("alice", "secret").unwrap());
+    }
+
+    #[test]
+    fn users_file_is_only_read_again_once_its_modification_time_changes() {
+        let auth = TestAuth::new();
+        auth.user("alice");
+        assert!(auth.authenticate("alice", "secret").unwrap());
+        let users_file = auth.users_file();
+        let modified = fs::metadata(&users_file).unwrap().modified().unwrap();
+
+        // Emptied behind the cache with the same modification time: still served from memory
+        fs::write(&users_file, "[]").unwrap();
+        set_modified(&users_file, modified);
+        assert!(auth.authenticate("alice", "secret").unwrap());
+
+        // An external edit moves the modification time
+        set_modified(&users_file, modified + Duration::from_secs(1));
+        assert!(!auth.authenticate("alice", "secret").unwrap());
+    }
+
+    #[test]
+    fn new_hashes_use_the_configured_cost() {
+        let auth = TestAuth::with_cost(5);
+
+        auth.user("alice");
+
+        let users = auth.load_users().unwrap();
+        assert!(users[0].password.starts_with("$2b$05$"), "{}", users[0].password);
+        assert!(auth.authenticate("alice", "secret").unwrap());
+    }
+
+    fn grant(auth: &AuthService, grantee: &str, access: AccessLevel) -> Result<String, String> {
+        auth.grant_database(GrantDbRequest {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+            db_name: "people".to_string(),
+            grantee: grantee.to_string(),
+            access,
+        })
+    }
+
+    #[test]
+    fn grants_resolve_to_the_owners_database_at_the_granted_level() {
+        let auth = TestAuth::new();
+        auth.user("alice");
+        auth.user("bob");
+        auth.user("carol");
+        auth.create_database(CreateDbRequest {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+            db_name: "people".to_string(),
+            public: false,
+        }).unwrap();
+
+        grant(&auth, "bob", AccessLevel::Read).unwrap();
+        let access = auth.user_database_access("bob", "secret", "people").unwrap().unwrap();
+        assert_eq!((access.owner.as_str(), access.level), ("alice", AccessLevel::Read));
+
+        // Granting again changes the level; only admins hand out access
+        grant(&auth, "bob", AccessLevel::Write).unwrap();
+        assert_eq!(auth.user_database_access("bob", "secret", "people").unwrap().unwrap().level, AccessLevel::Write);
+        let by_bob = auth.grant_database(GrantDbRequest {
+            username: "bob".to_string(),
+            password: "secret".to_string(),
+            db_name: "people".to_string(),
+            grantee: "carol".to_string(),
+            access: AccessLevel::Read,
+        });
+        assert!(by_bob.is_err());
+        assert!(auth.user_database_access("carol", "secret", "people").unwrap().is_none());
+    }
+
+    #[test]
+    fn new_users_and_databases_record_when_they_were_created() {
+        let auth = TestAuth::new();
+        let before = Utc::now();
+        auth.user("alice");
+        auth.create_database(CreateDbRequest { username: "alice".to_string(), password: "secret".to_string(), db_name: "people".to_string(), public: false }).unwrap();
+
+        let info = auth.get_user_info("alice", "secret").unwrap();
+
+        let created = |stamp: &Option<String>| chrono::DateTime::parse_from_rfc3339(stamp.as_deref().unwrap()).unwrap();
+        assert!(created(&info.created_at) >= before);
+        assert!(created(&info.db[0].created_at) >= created(&info.created_at));
+    }
+
+    #[test]
+    fn users_files_without_timestamps_still_load() {
+        let auth = TestAuth::new();
+        auth.user("alice");
+        let users_file = auth.users_file();
+        let modified = fs::metadata(&users_file).unwrap().modified().unwrap();
+        let password = hash("secret", BCRYPT_MIN_COST).unwrap();
+        let legacy = serde_json::json!([{"user": "alice", "password": password, "db": [{"namedb": "people"}]}]);
+        fs::write(&users_file, legacy.to_string()).unwrap();
+        set_modified(&users_file, modified + Duration::from_secs(1));
+
+        let info = auth.get_user_info("alice", "secret").unwrap();
+
+        assert_eq!(info.created_at, None);
+        assert_eq!(info.db[0].namedb, "people");
+        assert_eq!(info.db[0].created_at, None);
+        assert_eq!(info.db[0].access, AccessLevel::Admin);
+    }
+
+    #[test]
+    fn services_over_different_data_dirs_do_not_share_users() {
+        let first = TestAuth::new();
+        let second = TestAuth::new();
+
+        first.user("alice");
+
+        assert!(first.users_file().starts_with(&first.config.data_dir));
+        assert!(first.authenticate("alice", "secret").unwrap());
+        assert!(!second.authenticate("alice", "secret").unwrap());
+        second.user("alice");
+        assert_eq!(first.load_users().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings loaded with `--config <path>` (TOML, or JSON when the file ends in `.json`)
+///
+/// Every field is optional. Precedence, highest first:
+/// CLI flags > environment variables (`PORT`, `SARYCH_DATA_DIR`) > config file > built-in defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub data_dir: Option<PathBuf>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub nodes: Option<usize>,
+    pub threads: Option<usize>,
+    pub parallel_threshold: Option<usize>,
+    pub page_default: Option<usize>,
+    pub page_max: Option<usize>,
+    pub op_timeout: Option<usize>,
+    pub purge_interval: Option<u64>,
+    pub cache_cleanup_interval: Option<u64>,
+    pub user_quota: Option<u64>,
+    pub bulk_delete_fraction: Option<f64>,
+    pub compact_storage: Option<bool>,
+    pub id_version: Option<String>,
+    pub max_query_cost: Option<u64>,
+    pub snapshot_retention: Option<usize>,
+    pub max_body_size: Option<usize>,
+    pub cors_max_age: Option<u64>,
+    pub bcrypt_cost: Option<u32>,
+    pub bench_file: Option<String>,
+    pub bench_queries: Option<Vec<String>>,
+    pub silent: Option<bool>,
+}
+
+impl FileConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file '{}': {}", path, e))?;
+
+        let is_json = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+        if is_json {
+            serde_json::from_str(&contents)
+                .map_err(|e| format!("Invalid config file '{}': {}", path, e))
+        } else {
+            toml::from_str(&contents)
+                .map_err(|e| format!("Invalid config file '{}': {}", path, e))
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A config file in its own temp directory, removed when the test ends
+    struct TestConfigFile {
+        dir: PathBuf,
+        path: PathBuf,
+    }
+
+    impl TestConfigFile {
+        fn new(file_name: &str, contents: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("sarychdb-test-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(file_name);
+            fs::write(&path, contents).unwrap();
+            TestConfigFile { dir, path }
+        }
+
+        fn load(&self) -> Result<FileConfig, String> {
+            FileConfig::load(self.path.to_str().unwrap())
+        }
+    }
+
+    impl Drop for TestConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn toml_and_json_files_load_the_same_settings() {
+        let toml = TestConfigFile::new("sarychdb.toml", "port = 4000\nnodes = 4\nbench_queries = [\"a\", \"b\"]\n");
+        let json = TestConfigFile::new("sarychdb.json", r#"{"port": 4000, "nodes": 4, "bench_queries": ["a", "b"]}"#);
+
+        for config in [toml.load().unwrap(), json.load().unwrap()] {
+            assert_eq!(config.port, Some(4000));
+            assert_eq!(config.nodes, Some(4));
+            assert_eq!(config.bench_queries, Some(vec!["a".to_string(), "b".to_string()]));
+            assert_eq!(config.threads, None);
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let toml = TestConfigFile::new("sarychdb.toml", "port = 4000\nprot = 4001\n");
+        let json = TestConfigFile::new("sarychdb.json", r#"{"port": 4000, "prot": 4001}"#);
+
+        for error in [toml.load().unwrap_err(), json.load().unwrap_err()] {
+            assert!(error.starts_with("Invalid config file"), "{}", error);
+            assert!(error.contains("prot"), "{}", error);
+        }
+    }
+}
@@ -1,123 +1,1108 @@
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::fmt;
+use std::hash::Hasher;
+use std::str::FromStr;
 use std::fs;
-use std::path::Path;
-use std::time::Instant;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::btree_map::Entry;
+use std::sync::{Arc, Mutex};
 use once_cell::sync::Lazy;
+use regex::Regex;
 use crate::modules::search::{
-    load_json, split_nodes, 
+    load_json, load_json_mmap, load_json_gz, gzip_uncompressed_size, split_nodes, 
     get_optimal_node_count,
-    invalidate_cache_for_path, cached_parallel_search
+    invalidate_cache_for_path, cached_smart_search, smart_search_method,
+    get_cached_search, smart_search_limited, ordered_search_limited, CONTAINS_MODE,
+    compile_regex, cached_regex_search, cached_fuzzy_search,
+    smart_search, regex_search, fuzzy_search, uncached_results,
+    split_terms, multi_term_search, cached_multi_term_search,
+    load_json_page, for_each_json_record, JsonPage
 };
+use crate::modules::auth::USERS_DIR;
 use uuid::Uuid;
-use chrono::Utc;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use chrono::{DateTime, Utc};
 
-// Simple cache structure with once_cell
-static DB_CACHE: Lazy<Mutex<HashMap<String, (Vec<Value>, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Simple cache structure with once_cell, keyed by database file path
+type DbCache = HashMap<String, (Vec<Value>, Instant)>;
+static DB_CACHE: Lazy<Mutex<DbCache>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
-const CACHE_TTL_SECS: u64 = 300; // 5 minutes cache
+// Cumulative cache reads per database file, kept across invalidations so stats can report a hit rate
+#[derive(Debug, Clone, Copy, Default)]
+struct CacheCounters {
+    hits: u64,
+    misses: u64,
+    last_hit: bool,
+}
+static DB_CACHE_COUNTERS: Lazy<Mutex<HashMap<String, CacheCounters>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
+// One lock per database file, held for the whole read-modify-write of a write
+// so concurrent writers can't overwrite each other's changes. Reads don't take it.
+type WriteLocks = HashMap<String, Arc<Mutex<()>>>;
+static WRITE_LOCKS: Lazy<Mutex<WriteLocks>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Last `_seq` handed out per database file. The records themselves carry `_seq`,
+// so after a restart numbering resumes from the highest one stored
+static LAST_SEQ: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Bytes stored per user folder, measured from disk at most every USAGE_CACHE_TTL and
+// adjusted by every write in between so quota checks don't walk the folder each time
+static USER_USAGE: Lazy<Mutex<HashMap<PathBuf, (u64, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+const USAGE_CACHE_TTL: Duration = Duration::from_secs(5);
+
+// Appends to the audit logs, so concurrent writers never interleave partial lines
+static AUDIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Errors returned by database operations, each maps to an HTTP status
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbError {
+    /// The database (or record) does not exist
+    NotFound(String),
+    /// The request itself is invalid
+    BadRequest(String),
+    /// The write would break a uniqueness rule, such as a duplicate `_id`
+    Conflict(String),
+    /// The write would take the owner past their storage quota
+    TooLarge(String),
+    /// Reading or writing the files failed
+    Internal(String),
+}
+
+impl DbError {
+    pub fn message(&self) -> &str {
+        match self {
+            DbError::NotFound(message)
+            | DbError::BadRequest(message)
+            | DbError::Conflict(message)
+            | DbError::TooLarge(message)
+            | DbError::Internal(message) => message,
+        }
+    }
+}
+
+impl DbError {
+    /// Same kind of error with `context` in front of the message
+    pub fn with_context(self, context: &str) -> Self {
+        match self {
+            DbError::NotFound(message) => DbError::NotFound(format!("{}: {}", context, message)),
+            DbError::BadRequest(message) => DbError::BadRequest(format!("{}: {}", context, message)),
+            DbError::Conflict(message) => DbError::Conflict(format!("{}: {}", context, message)),
+            DbError::TooLarge(message) => DbError::TooLarge(format!("{}: {}", context, message)),
+            DbError::Internal(message) => DbError::Internal(format!("{}: {}", context, message)),
+        }
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// 64-bit FNV-1a over everything written to it, also usable as an `io::Write` sink for serde_json
+/// Unlike `DefaultHasher` its output is fixed, so hashes clients keep (ETags, record versions) stay
+/// valid when the server is rebuilt with another Rust release
+#[derive(Debug, Clone, Copy)]
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        StableHasher(Self::OFFSET_BASIS)
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Write for StableHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Hasher::write(self, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Default and maximum page sizes for browse/list/get pagination
+#[derive(Debug, Clone, Copy)]
+pub struct PageLimits {
+    pub default: usize,
+    pub max: usize,
+}
+
+impl Default for PageLimits {
+    fn default() -> Self {
+        PageLimits { default: 10, max: 1000 }
+    }
+}
+
+/// Settings a `DatabaseManager` is built with
 #[derive(Debug, Clone)]
-pub struct DatabaseManager;
+pub struct DatabaseConfig {
+    /// Root holding the users/ folder with every database file
+    pub data_dir: PathBuf,
+    /// How long loaded databases and search results stay cached
+    pub cache_ttl: Duration,
+    pub page_limits: PageLimits,
+    /// How often expired records are removed from the files, zero disables the purge
+    pub purge_interval: Duration,
+    /// How often expired database and search cache entries are evicted, zero leaves them to lazy cleanup
+    pub cache_cleanup_interval: Duration,
+    /// Most bytes a user's folder may hold, writes that would grow it past this fail; None is unlimited
+    pub user_quota: Option<u64>,
+    /// Share of a database (0-1] a delete may remove without confirmBulk, 1.0 never asks
+    pub bulk_delete_fraction: f64,
+    /// Write database files as compact JSON instead of indented, unless a database's settings say otherwise
+    pub compact_storage: bool,
+    /// UUID version of the `_id` generated for records that don't bring one
+    pub id_version: IdVersion,
+    /// Highest estimated cost a search may have without `force`; None is unlimited
+    pub max_query_cost: Option<u64>,
+    /// Snapshots kept per database, taking one deletes the oldest beyond this; None keeps them all
+    pub snapshot_retention: Option<usize>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            data_dir: PathBuf::from("."),
+            cache_ttl: Duration::from_secs(300), // 5 minutes cache
+            page_limits: PageLimits::default(),
+            purge_interval: Duration::from_secs(60),
+            cache_cleanup_interval: Duration::from_secs(60),
+            user_quota: None,
+            bulk_delete_fraction: 0.5,
+            compact_storage: false,
+            id_version: IdVersion::default(),
+            max_query_cost: None,
+            snapshot_retention: None,
+        }
+    }
+}
+
+/// Kind of UUID used for generated `_id`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdVersion {
+    /// Random, the historical default
+    #[default]
+    V4,
+    /// Time-ordered: ids generated later sort after earlier ones, as strings too
+    V7,
+}
+
+impl IdVersion {
+    pub fn generate(self) -> String {
+        match self {
+            IdVersion::V4 => Uuid::new_v4().to_string(),
+            IdVersion::V7 => Uuid::now_v7().to_string(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IdVersion::V4 => "v4",
+            IdVersion::V7 => "v7",
+        }
+    }
+}
+
+impl FromStr for IdVersion {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "v4" | "4" => Ok(IdVersion::V4),
+            "v7" | "7" => Ok(IdVersion::V7),
+            _ => Err(()),
+        }
+    }
+}
+
+// Id field of databases that don't configure their own
+pub const DEFAULT_ID_FIELD: &str = "_id";
+
+// Metadata fields that a full replace must never drop
+const IMMUTABLE_FIELDS: [&str; 4] = ["_id", "_created_at", "_created_by", "_seq"];
+
+// Fields the server stamps on every record, besides the id
+const SERVER_FIELDS: [&str; 5] = ["_created_at", "_created_by", "_seq", "_updated_at", "_updated_by"];
+
+// Append-only JSONL log of the writes to a user's databases, kept in their folder
+pub const AUDIT_LOG_FILE: &str = "_audit.log";
+
+// RFC3339 timestamp after which a record is hidden from reads and later purged
+pub const EXPIRES_AT_FIELD: &str = "_expires_at";
+
+// Snapshots are stored as `<db>.snapshot.<timestamp>.json` (`.json.gz` for gzipped databases), the
+// timestamp sorts in the order they were taken
+const SNAPSHOT_MARKER: &str = ".snapshot.";
+const SNAPSHOT_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+// Files from this size are paged straight from disk by browse and plain lists when not cached, instead of
+// being loaded whole; smaller ones are quick to load and worth keeping in the cache
+const STREAMED_PAGE_MIN_BYTES: u64 = 16 * 1024 * 1024;
+
+// `$regex` patterns of a list filter, compiled once per request (see compile_filter_regexes)
+type FilterRegexes<'f> = HashMap<&'f str, Regex>;
+
+// Records inspected by the stats schema summary, larger databases are summarized from the first ones
+const SCHEMA_SAMPLE_SIZE: usize = 10_000;
+
+// Upper bound for the nodes header, more chunks than this only add scheduling overhead
+pub const MAX_SEARCH_NODES: usize = 256;
+
+// Edit distance used by queryType=fuzzy when no maxDistance header is sent
+pub const DEFAULT_FUZZY_DISTANCE: usize = 2;
+
+// Cost of matching one record against a regex, relative to a substring search
+const REGEX_COST_WEIGHT: u64 = 10;
+
+/// How an update body is applied to each matched record
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateMode<'a> {
+    /// Merge body keys into the record, keeping keys not present in the body (PATCH)
+    Merge,
+    /// Swap the record for the body, keeping only `_id`, `_created_at` and `_seq` (PUT)
+    Replace,
+    /// Add `amount` to a numeric field, missing fields start at 0 (body is ignored)
+    Increment { field: &'a str, amount: Number },
+    /// Append the body value to an array field, creating it if absent
+    ArrayPush { field: &'a str },
+    /// Remove every element equal to the body value from an array field
+    ArrayPull { field: &'a str },
+    /// Apply a JSON Patch (RFC 6902) to the record, a failing step aborts the whole write
+    Patch(&'a [PatchOp]),
+}
+
+impl UpdateMode<'_> {
+    pub fn parse(value: Option<&str>) -> Result<Self, DbError> {
+        match value {
+            None | Some("merge") => Ok(UpdateMode::Merge),
+            Some("replace") => Ok(UpdateMode::Replace),
+            Some(other) => Err(DbError::BadRequest(format!("Invalid replaceMode '{}'. Use: merge, replace", other))),
+        }
+    }
+}
+
+/// One step of a JSON Patch body, only add, remove and replace are supported
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchOp {
+    pub op: String,
+    /// JSON Pointer (RFC 6901) to the field, e.g. `/address/city` or `/tags/-`
+    pub path: String,
+    // Some(Value::Null) for an explicit null, None when the key is missing
+    #[serde(default, deserialize_with = "present_value")]
+    pub value: Option<Value>,
+}
+
+fn present_value<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+impl PatchOp {
+    /// Parse and validate a patch body before any record is touched
+    pub fn parse_all(body: Value) -> Result<Vec<PatchOp>, DbError> {
+        let ops: Vec<PatchOp> = serde_json::from_value(body)
+            .map_err(|e| DbError::BadRequest(format!("Patch body must be an array of {{op, path, value}} objects: {}", e)))?;
+        if ops.is_empty() {
+            return Err(DbError::BadRequest("Patch body cannot be empty".to_string()));
+        }
+
+        for op in &ops {
+            match op.op.as_str() {
+                "add" | "replace" if op.value.is_none() => {
+                    return Err(DbError::BadRequest(format!("Patch op '{}' on '{}' requires a value", op.op, op.path)));
+                }
+                "add" | "replace" | "remove" => {}
+                other => return Err(DbError::BadRequest(format!("Unsupported patch op '{}'. Use: add, remove, replace", other))),
+            }
+            let tokens = op.tokens()?;
+            if IMMUTABLE_FIELDS.contains(&tokens[0].as_str()) {
+                return Err(DbError::BadRequest(format!("Patch cannot modify immutable field '{}'", tokens[0])));
+            }
+        }
+        Ok(ops)
+    }
+
+    // Split the pointer into unescaped reference tokens (~1 is '/', ~0 is '~')
+    fn tokens(&self) -> Result<Vec<String>, DbError> {
+        let Some(rest) = self.path.strip_prefix('/') else {
+            return Err(DbError::BadRequest(format!("Invalid patch path '{}'. Must start with '/' and name a field", self.path)));
+        };
+        Ok(rest.split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+    }
+
+    fn apply(&self, record: &mut Value) -> Result<(), DbError> {
+        let missing = || DbError::BadRequest(format!("Patch path '{}' does not exist", self.path));
+        let tokens = self.tokens()?;
+        let (last, parents) = tokens.split_last().ok_or_else(missing)?;
+
+        let mut parent = record;
+        for token in parents {
+            parent = match parent {
+                Value::Object(map) => map.get_mut(token),
+                Value::Array(arr) => Self::index(token).and_then(|i| arr.get_mut(i)),
+                _ => None,
+            }.ok_or_else(missing)?;
+        }
+
+        match parent {
+            Value::Object(map) => match self.op.as_str() {
+                "add" => { map.insert(last.clone(), self.value.clone().unwrap_or(Value::Null)); }
+                "replace" => *map.get_mut(last).ok_or_else(missing)? = self.value.clone().unwrap_or(Value::Null),
+                _ => { map.remove(last).ok_or_else(missing)?; }
+            },
+            Value::Array(arr) => {
+                // '-' addresses the slot after the last element, only valid for add
+                let index = if last == "-" && self.op == "add" { Some(arr.len()) } else { Self::index(last) };
+                match (self.op.as_str(), index) {
+                    ("add", Some(i)) if i <= arr.len() => arr.insert(i, self.value.clone().unwrap_or(Value::Null)),
+                    ("replace", Some(i)) if i < arr.len() => arr[i] = self.value.clone().unwrap_or(Value::Null),
+                    ("remove", Some(i)) if i < arr.len() => { arr.remove(i); }
+                    _ => return Err(missing()),
+                }
+            }
+            _ => return Err(DbError::BadRequest(format!("Patch path '{}' does not point inside an object or array", self.path))),
+        }
+        Ok(())
+    }
+
+    // Array indexes are plain decimal digits, no sign and no leading zeros
+    fn index(token: &str) -> Option<usize> {
+        if token.is_empty() || !token.bytes().all(|b| b.is_ascii_digit()) || (token.len() > 1 && token.starts_with('0')) {
+            return None;
+        }
+        token.parse().ok()
+    }
+}
+
+/// Which records an update applies to
+#[derive(Debug, Clone, Copy)]
+pub enum UpdateTarget<'a> {
+    /// The single record whose `_id` matches
+    Id(&'a str),
+    /// Every record matching the search query
+    Query(&'a str),
+}
+
+/// One step of a `POST /api/transaction` batch, fields mirror the /sarych headers
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TransactionOp {
+    pub database: String,
+    /// post, put, upsert, patch, increment, arrayPush, arrayPull or delete
+    pub op: String,
+    #[serde(default)]
+    pub body: Option<Value>,
+    pub query: Option<String>,
+    pub id_update: Option<String>,
+    pub replace_mode: Option<String>,
+    pub field: Option<String>,
+    pub amount: Option<Number>,
+    /// Same as the confirmBulk header, lets a delete remove more than the bulk delete fraction
+    #[serde(default)]
+    pub confirm_bulk: bool,
+}
+
+/// Body of `POST /api/cache/warm`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WarmCacheRequest {
+    pub databases: Vec<String>,
+    /// Full-text queries run against every listed database so their results are cached too
+    #[serde(default)]
+    pub queries: Vec<String>,
+}
+
+/// Result of a write that touched existing records
+#[derive(Debug)]
+pub struct WriteOutcome {
+    pub message: String,
+    pub count: usize,
+    /// Post-update versions for updates, removed records for deletes (empty unless requested)
+    pub affected: Vec<Value>,
+}
+
+/// One line of the audit log: who changed which database, how, and how many records
+#[derive(Debug, Serialize)]
+pub struct AuditEntry<'a> {
+    pub timestamp: String,
+    pub user: &'a str,
+    pub database: &'a str,
+    pub operation: &'a str,
+    pub affected_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<&'a str>,
+}
+
+impl<'a> AuditEntry<'a> {
+    pub fn new(user: &'a str, database: &'a str, operation: &'a str, affected_count: usize, query: Option<&'a str>) -> Self {
+        Self { timestamp: Utc::now().to_rfc3339(), user, database, operation, affected_count, query }
+    }
+}
+
+/// Query parameters for `search_records`
+#[derive(Debug, Default)]
+pub struct SearchOptions<'a> {
+    pub query: Option<&'a str>,
+    /// key, value, id, regex, fuzzy; anything else is a full-text substring search
+    pub query_type: Option<&'a str>,
+    /// Edit distance for fuzzy searches, DEFAULT_FUZZY_DISTANCE when unset
+    pub max_distance: Option<usize>,
+    /// Stop once this many matches are collected
+    pub max_results: Option<usize>,
+    /// Read the file from disk and search without the search cache, leaving it untouched
+    pub no_cache: bool,
+    /// Chunks the data is split into for the search, get_optimal_node_count() when unset
+    pub nodes: Option<usize>,
+    /// queryLogic for full-text searches: split the query into terms and require all of them (true)
+    /// or any (false); None keeps the query as a single substring
+    pub all_terms: Option<bool>,
+    /// Run the search even when its estimated cost is over `max_query_cost`
+    pub force: bool,
+    /// With max_results, return the first matches in storage order instead of whichever threads find first
+    pub stable_order: bool,
+}
+
+/// How a GET search was resolved, reported by `explain`
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchPlan {
+    /// all, key_scan, value_scan, id_scan, regex, fuzzy, multi_term, sequential, parallel, limited,
+    /// limited_ordered or search_cache
+    pub method: &'static str,
+    pub nodes: usize,
+    pub total_items: usize,
+    pub database_cache_hit: bool,
+    /// Only set for full-text, regex and fuzzy searches, the only ones cached
+    pub search_cache_hit: Option<bool>,
+    /// There are no indexes yet, so this is always false
+    pub index_used: bool,
+    /// See `estimate_search_cost`, compared against `max_query_cost`
+    pub estimated_cost: u64,
+}
+
+/// Pagination, sorting and filtering parameters for `list_records`
+#[derive(Debug, Default)]
+pub struct ListOptions<'a> {
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+    /// Comma-separated fields, later ones break ties on earlier ones
+    pub sort_by: Option<&'a str>,
+    /// Comma-separated orders matching `sort_by`; missing entries default to asc
+    pub sort_order: Option<&'a str>,
+    pub filters: Option<&'a Value>,
+    /// Case-insensitive free text, matched against `search_fields` (or the whole record when empty)
+    pub search: Option<&'a str>,
+    pub search_fields: Vec<&'a str>,
+    /// `_created_at` window, inclusive start and exclusive end; records without the field are left out
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    /// Read the file from disk instead of the database cache
+    pub no_cache: bool,
+}
+
+/// Per-database storage options, kept next to the data in `<db>.settings.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    /// Parse the file through a memory map instead of a buffered reader
+    pub mmap: bool,
+    /// Stamp `_created_by` / `_updated_by` with the authenticated user on every write
+    pub track_authors: bool,
+    /// Store the file as compact JSON (true) or indented (false), None follows the server default
+    pub compact: Option<bool>,
+    /// Keep the file gzip-compressed as `<db>.json.gz`
+    pub gzip: bool,
+    /// Field holding each record's id: generated on insert, matched by getById, idUpdate and exists
+    pub id_field: String,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings {
+            mmap: false,
+            track_authors: false,
+            compact: None,
+            gzip: false,
+            id_field: DEFAULT_ID_FIELD.to_string(),
+        }
+    }
+}
+
+// What a write needs from the database settings, read once per write
+struct WriteContext<'a> {
+    // User stamped in `_created_by` / `_updated_by`, when the database tracks authors
+    author: Option<&'a str>,
+    id_field: String,
+}
+
+// Reservoir sampling: after any number of offers, each one is in the sample with the same probability
+struct Reservoir<T> {
+    size: usize,
+    rng: StdRng,
+    sample: Vec<T>,
+    seen: usize,
+}
+
+impl<T> Reservoir<T> {
+    // A seed makes the picks reproducible for the same sequence of offers
+    fn new(size: usize, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Reservoir { size, rng, sample: Vec::new(), seen: 0 }
+    }
+
+    fn offer(&mut self, item: T) {
+        if self.sample.len() < self.size {
+            self.sample.push(item);
+        } else {
+            let slot = self.rng.gen_range(0..=self.seen);
+            if slot < self.size {
+                self.sample[slot] = item;
+            }
+        }
+        self.seen += 1;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseManager {
+    config: DatabaseConfig,
+}
 
 impl DatabaseManager {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: DatabaseConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &DatabaseConfig {
+        &self.config
+    }
+
+    // Check that a path component (username or database name) can't leave the users/ root
+    pub fn sanitize_component(component: &str) -> Result<&str, DbError> {
+        if component.is_empty() {
+            return Err(DbError::BadRequest("Path component cannot be empty".to_string()));
+        }
+        if component.contains('/') || component.contains('\\') || component.contains("..") || component.contains('\0') {
+            return Err(DbError::BadRequest(format!("Invalid path component '{}'", component.escape_debug())));
+        }
+        Ok(component)
+    }
+
+    // `<db>.json`, also the key of the database in the caches and locks whatever format it is stored in
+    pub fn get_db_path(&self, username: &str, db_name: &str) -> Result<String, DbError> {
+        self.user_file(username, &format!("{}.json", Self::sanitize_component(db_name)?))
+    }
+
+    // File currently holding the data: `<db>.json`, or `<db>.json.gz` for a gzipped database
+    pub fn get_stored_path(&self, username: &str, db_name: &str) -> Result<String, DbError> {
+        let filepath = self.get_db_path(username, db_name)?;
+        let gzipped = format!("{}.gz", filepath);
+        if !Path::new(&filepath).exists() && Path::new(&gzipped).exists() {
+            return Ok(gzipped);
+        }
+        Ok(filepath)
     }
 
-    pub fn get_db_path(username: &str, db_name: &str) -> String {
-        format!("users/{}/{}.json", username, db_name)
+    // <data dir>/users/<username>/<file_name>
+    fn user_file(&self, username: &str, file_name: &str) -> Result<String, DbError> {
+        let path = self.user_dir(username)?.join(file_name);
+        Ok(path.to_string_lossy().into_owned())
     }
 
-    pub fn database_exists(username: &str, db_name: &str) -> bool {
-        let filepath = Self::get_db_path(username, db_name);
-        Path::new(&filepath).exists()
+    // <data dir>/users/<username>
+    fn user_dir(&self, username: &str) -> Result<PathBuf, DbError> {
+        Ok(self.config.data_dir.join(USERS_DIR).join(Self::sanitize_component(username)?))
     }
 
-    pub fn read_database(username: &str, db_name: &str) -> Result<Vec<Value>, String> {
-        let filepath = Self::get_db_path(username, db_name);
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
+    // Fail with BadRequest for unsafe names and NotFound when the database file is missing
+    pub fn require_database(&self, username: &str, db_name: &str) -> Result<(), DbError> {
+        let filepath = self.get_stored_path(username, db_name)?;
+        if !Path::new(&filepath).exists() {
+            return Err(DbError::NotFound("Database does not exist".to_string()));
         }
+        Ok(())
+    }
+
+    pub fn get_settings_path(&self, username: &str, db_name: &str) -> Result<String, DbError> {
+        self.user_file(username, &format!("{}.settings.json", Self::sanitize_component(db_name)?))
+    }
+
+    // Settings for a database, defaults when none were saved
+    pub fn read_settings(&self, username: &str, db_name: &str) -> Result<DatabaseSettings, DbError> {
+        let path = self.get_settings_path(username, db_name)?;
+        if !Path::new(&path).exists() {
+            return Ok(DatabaseSettings::default());
+        }
+        let contents = fs::read_to_string(&path).map_err(|e| DbError::Internal(e.to_string()))?;
+        serde_json::from_str(&contents).map_err(|e| DbError::Internal(format!("Invalid database settings: {}", e)))
+    }
+
+    // Apply the fields in `changes` on top of the current settings and save them
+    pub fn update_settings(&self, username: &str, db_name: &str, changes: &Value) -> Result<DatabaseSettings, DbError> {
+        self.require_database(username, db_name)?;
+        let Some(changes) = changes.as_object() else {
+            return Err(DbError::BadRequest("Settings must be a JSON object".to_string()));
+        };
+
+        let previous = self.read_settings(username, db_name)?;
+        let mut merged = serde_json::to_value(&previous)
+            .map_err(|e| DbError::Internal(e.to_string()))?;
+        if let Some(obj) = merged.as_object_mut() {
+            for (key, value) in changes {
+                obj.insert(key.clone(), value.clone());
+            }
+        }
+        let settings: DatabaseSettings = serde_json::from_value(merged)
+            .map_err(|e| DbError::BadRequest(format!("Invalid settings: {}", e)))?;
+        // The id field can't be blank or another metadata field, which is maintained by the server
+        let id_field = settings.id_field.as_str();
+        if id_field.trim().is_empty() || SERVER_FIELDS.contains(&id_field) || id_field == EXPIRES_AT_FIELD {
+            return Err(DbError::BadRequest(format!("Invalid settings: '{}' cannot be the id field", settings.id_field)));
+        }
+
+        let json = serde_json::to_string_pretty(&settings).map_err(|e| DbError::Internal(e.to_string()))?;
+        fs::write(self.get_settings_path(username, db_name)?, json).map_err(|e| DbError::Internal(e.to_string()))?;
+        self.invalidate_cache(username, db_name);
+
+        // Turning gzip on or off rewrites the file now, so it is always stored the way the settings say
+        if settings.gzip != previous.gzip {
+            let lock = self.write_lock(username, db_name)?;
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let data = self.read_database(username, db_name)?;
+            self.write_database(username, db_name, &data)?;
+        }
+        Ok(settings)
+    }
+
+    pub fn read_database(&self, username: &str, db_name: &str) -> Result<Vec<Value>, DbError> {
+        self.require_database(username, db_name)?;
+        let filepath = self.get_stored_path(username, db_name)?;
         
-        Ok(load_json(&filepath))
+        let loaded = if filepath.ends_with(".gz") {
+            load_json_gz(&filepath)
+        } else if self.read_settings(username, db_name)?.mmap {
+            load_json_mmap(&filepath)
+        } else {
+            load_json(&filepath)
+        };
+        loaded.map_err(DbError::Internal)
     }
 
     // Read database with cache support
-    pub fn read_database_cached(username: &str, db_name: &str) -> Result<Vec<Value>, String> {
-        let cache_key = format!("{}:{}", username, db_name);
+    pub fn read_database_cached(&self, username: &str, db_name: &str) -> Result<Vec<Value>, DbError> {
+        self.read_database_cached_with_hit(username, db_name, false).map(|(data, _)| data)
+    }
+
+    // noCache reads always go to disk and leave the cached copy as it was
+    fn read_database_fresh_or_cached(&self, username: &str, db_name: &str, no_cache: bool) -> Result<Vec<Value>, DbError> {
+        self.read_database_cached_with_hit(username, db_name, no_cache).map(|(data, _)| data)
+    }
+
+    // Same as read_database_cached, also reporting whether the copy came from the cache
+    fn read_database_cached_with_hit(&self, username: &str, db_name: &str, skip_cache: bool) -> Result<(Vec<Value>, bool), DbError> {
+        let cache_key = self.get_db_path(username, db_name)?;
         
         // Try to get from cache
-        {
+        if !skip_cache {
             let cache = DB_CACHE.lock().unwrap();
             if let Some((data, timestamp)) = cache.get(&cache_key) {
                 // Check if cache is still valid (within TTL)
-                if timestamp.elapsed().as_secs() < CACHE_TTL_SECS {
-                    return Ok(data.clone());
+                if timestamp.elapsed() < self.config.cache_ttl {
+                    Self::count_cache_read(&cache_key, true);
+                    return Ok((data.clone(), true));
                 }
             }
         }
         
         // Cache miss or expired, read from disk
-        let data = Self::read_database(username, db_name)?;
+        Self::count_cache_read(&cache_key, false);
+        let data = self.read_database(username, db_name)?;
         
-        // Update cache
-        {
+        // Update cache, unless the read asked to bypass it
+        if !skip_cache {
             let mut cache = DB_CACHE.lock().unwrap();
             cache.insert(cache_key, (data.clone(), Instant::now()));
         }
         
-        Ok(data)
+        Ok((data, false))
+    }
+
+    // Whether the database is in the cache and still within the TTL
+    fn is_cached(&self, cache_key: &str) -> bool {
+        let cache = DB_CACHE.lock().unwrap();
+        cache.get(cache_key).is_some_and(|(_, timestamp)| timestamp.elapsed() < self.config.cache_ttl)
+    }
+
+    // A page of live records read straight from a large file that isn't cached, building only the page's records;
+    // None when the database is cached or small, then paging the loaded copy is cheaper (and caches it)
+    fn stream_live_page(&self, username: &str, db_name: &str, offset: usize, limit: usize, no_cache: bool) -> Result<Option<JsonPage>, DbError> {
+        let cache_key = self.get_db_path(username, db_name)?;
+        if !no_cache && self.is_cached(&cache_key) {
+            return Ok(None);
+        }
+        let filepath = self.get_stored_path(username, db_name)?;
+        if !fs::metadata(&filepath).is_ok_and(|meta| meta.len() >= STREAMED_PAGE_MIN_BYTES) {
+            return Ok(None);
+        }
+
+        Self::count_cache_read(&cache_key, false);
+        let now = Utc::now();
+        load_json_page(&filepath, offset, limit, EXPIRES_AT_FIELD, |expires_at| !Self::expired_by(expires_at, now))
+            .map(Some)
+            .map_err(DbError::Internal)
+    }
+
+    // Live records from `offset` and the number of live records, streamed when possible
+    fn read_live_page(&self, username: &str, db_name: &str, offset: usize, limit: usize, no_cache: bool) -> Result<(Vec<Value>, usize), DbError> {
+        if let Some(page) = self.stream_live_page(username, db_name, offset, limit, no_cache)? {
+            return Ok((page.records, page.kept));
+        }
+        let mut data = self.read_database_fresh_or_cached(username, db_name, no_cache)?;
+        Self::retain_live(&mut data);
+        let total_records = data.len();
+        Ok((data.into_iter().skip(offset).take(limit).collect(), total_records))
+    }
+
+    // WARM - Load a database into the cache ahead of traffic and pre-run common searches
+    pub fn warm_cache(&self, username: &str, db_name: &str, queries: &[String]) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
+
+        let (data, already_cached) = self.read_database_cached_with_hit(username, db_name, false)?;
+        let mut warmed_queries = Vec::with_capacity(queries.len());
+        for query in queries.iter().filter(|q| !q.is_empty()) {
+            // Warming is asked for explicitly, so it isn't held to max_query_cost
+            let results = self.search_records(username, db_name, &SearchOptions {
+                query: Some(query),
+                force: true,
+                ..Default::default()
+            })?;
+            warmed_queries.push(serde_json::json!({ "query": query, "matches": results.len() }));
+        }
+
+        Ok(serde_json::json!({
+            "database": db_name,
+            "records": data.len(),
+            "already_cached": already_cached,
+            "queries": warmed_queries
+        }))
+    }
+
+    // Drop loaded databases older than the TTL; like the search cache, they are freed outside the lock
+    pub fn evict_expired_cache(&self) -> usize {
+        let expired: Vec<(Vec<Value>, Instant)> = {
+            let mut cache = DB_CACHE.lock().unwrap();
+            let keys: Vec<String> = cache.iter()
+                .filter(|(_, (_, timestamp))| timestamp.elapsed() >= self.config.cache_ttl)
+                .map(|(key, _)| key.clone())
+                .collect();
+            keys.iter().filter_map(|key| cache.remove(key)).collect()
+        };
+        expired.len()
+    }
+
+    fn count_cache_read(cache_key: &str, hit: bool) {
+        let mut counters = DB_CACHE_COUNTERS.lock().unwrap();
+        let entry = counters.entry(cache_key.to_string()).or_default();
+        if hit {
+            entry.hits += 1;
+        } else {
+            entry.misses += 1;
+        }
+        entry.last_hit = hit;
+    }
+
+    // Cache reads of one database so far and the age of its cached copy (null when not cached)
+    fn cache_metrics(&self, filepath: &str) -> Value {
+        let counters = DB_CACHE_COUNTERS.lock().unwrap().get(filepath).copied().unwrap_or_default();
+        let entry_age_ms = DB_CACHE.lock().unwrap()
+            .get(filepath)
+            .map(|(_, timestamp)| timestamp.elapsed())
+            .filter(|age| *age < self.config.cache_ttl)
+            .map(|age| age.as_millis() as u64);
+        let reads = counters.hits + counters.misses;
+
+        serde_json::json!({
+            "last_read_hit": counters.last_hit,
+            "entry_age_ms": entry_age_ms,
+            "hits": counters.hits,
+            "misses": counters.misses,
+            "hit_rate": if reads == 0 { 0.0 } else { counters.hits as f64 / reads as f64 }
+        })
+    }
+
+    // Number of databases currently held in the cache
+    pub fn cache_len() -> usize {
+        DB_CACHE.lock().unwrap().len()
     }
 
     // Invalidate cache when data is written
-    pub fn invalidate_cache(username: &str, db_name: &str) {
-        let cache_key = format!("{}:{}", username, db_name);
-        let mut cache = DB_CACHE.lock().unwrap();
-        cache.remove(&cache_key);
+    pub fn invalidate_cache(&self, username: &str, db_name: &str) {
+        if let Ok(cache_key) = self.get_db_path(username, db_name) {
+            let mut cache = DB_CACHE.lock().unwrap();
+            cache.remove(&cache_key);
+        }
+    }
+
+    // Lock guarding writes to one database, created on first use
+    fn write_lock(&self, username: &str, db_name: &str) -> Result<Arc<Mutex<()>>, DbError> {
+        let filepath = self.get_db_path(username, db_name)?;
+        let mut locks = WRITE_LOCKS.lock().unwrap();
+        Ok(Arc::clone(locks.entry(filepath).or_default()))
     }
 
-    pub fn write_database(username: &str, db_name: &str, data: &Vec<Value>) -> Result<(), String> {
-        let filepath = Self::get_db_path(username, db_name);
-        let json = serde_json::to_string_pretty(data).map_err(|e| e.to_string())?;
-        fs::write(&filepath, json).map_err(|e| e.to_string())?;
+    pub fn write_database(&self, username: &str, db_name: &str, data: &[Value]) -> Result<(), DbError> {
+        let cache_key = self.get_db_path(username, db_name)?;
+        let (filepath, stale, contents) = self.encode_database(username, db_name, data, false)?;
+        // The file in the other format is removed, so it counts as shrinking to nothing
+        let growth = self.check_quota(username, &[(filepath.as_str(), contents.len() as u64), (stale.as_str(), 0)])?;
+        fs::write(&filepath, contents).map_err(|e| DbError::Internal(e.to_string()))?;
+        Self::remove_stale(&stale)?;
+        self.record_growth(username, growth);
         
         // Invalidate both database cache and search cache after write
-        Self::invalidate_cache(username, db_name);
-        invalidate_cache_for_path(&filepath);
+        self.invalidate_cache(username, db_name);
+        invalidate_cache_for_path(&cache_key);
         Ok(())
     }
 
-    // GET - Search records with queryType support and optimized parallel search
-    pub fn search_records(&self, username: &str, db_name: &str, query: Option<&str>, query_type: Option<&str>) -> Result<Vec<Value>, String> {
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
+    // File contents for a database: indented by default, compact when its settings or the server ask for it,
+    // gzipped into `<db>.json.gz` when its settings enable gzip. Both JSON forms load the same way, so
+    // switching compact only affects the next write; `force_compact` writes compact JSON whatever the settings
+    // say. Returns the file to write, the file in the other format that the write replaces, and the contents
+    fn encode_database(&self, username: &str, db_name: &str, data: &[Value], force_compact: bool) -> Result<(String, String, Vec<u8>), DbError> {
+        let settings = self.read_settings(username, db_name)?;
+        let compact = force_compact || settings.compact.unwrap_or(self.config.compact_storage);
+        let serialized = if compact { serde_json::to_vec(data) } else { serde_json::to_vec_pretty(data) };
+        let json = serialized.map_err(|e| DbError::Internal(e.to_string()))?;
+
+        let filepath = self.get_db_path(username, db_name)?;
+        let gzipped = format!("{}.gz", filepath);
+        if !settings.gzip {
+            return Ok((filepath, gzipped, json));
+        }
+        let mut encoder = GzEncoder::new(Vec::with_capacity(json.len() / 4), Compression::default());
+        encoder.write_all(&json).map_err(|e| DbError::Internal(e.to_string()))?;
+        let compressed = encoder.finish().map_err(|e| DbError::Internal(e.to_string()))?;
+        Ok((gzipped, filepath, compressed))
+    }
+
+    // Remove the copy left in the previous format after a database switched gzip on or off
+    fn remove_stale(path: &str) -> Result<(), DbError> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(DbError::Internal(format!("Could not remove '{}': {}", path, e))),
+            _ => Ok(()),
         }
+    }
+
+    // GET - Search records with queryType support and optimized parallel search
+    pub fn search_records(&self, username: &str, db_name: &str, options: &SearchOptions) -> Result<Arc<Vec<Value>>, DbError> {
+        self.search_records_explained(username, db_name, options)
+            .map(|(results, _)| results)
+    }
+
+    // Rough cost of a search: records visited times the work per record for its query type
+    // No index can narrow a search yet (see SearchPlan::index_used), so every query is costed as a full scan
+    pub fn estimate_search_cost(total_items: usize, options: &SearchOptions) -> u64 {
+        let per_record = match (options.query.filter(|q| !q.trim().is_empty()), options.query_type) {
+            (None, _) => 1,
+            (Some(_), Some("key" | "value" | "id")) => 1,
+            (Some(_), Some("regex")) => REGEX_COST_WEIGHT,
+            // An edit distance per string value grows with the query and the allowed distance
+            (Some(q), Some("fuzzy")) => {
+                let max_distance = options.max_distance.unwrap_or(DEFAULT_FUZZY_DISTANCE) as u64;
+                (q.chars().count() as u64).saturating_mul(max_distance + 1)
+            }
+            (Some(q), _) if options.all_terms.is_some() => split_terms(q).len().max(1) as u64,
+            (Some(_), _) => 1,
+        };
+        (total_items as u64).saturating_mul(per_record)
+    }
 
-        let data = Self::read_database_cached(username, db_name)?;
+    // GET with the plan that produced the results
+    pub fn search_records_explained(&self, username: &str, db_name: &str, options: &SearchOptions) -> Result<(Arc<Vec<Value>>, SearchPlan), DbError> {
+        let (query, query_type) = (options.query, options.query_type);
+        self.require_database(username, db_name)?;
+
+        let (mut data, database_cache_hit) = self.read_database_cached_with_hit(username, db_name, options.no_cache)?;
+        let mut plan = SearchPlan {
+            method: "all",
+            nodes: 1,
+            total_items: data.len(),
+            database_cache_hit,
+            search_cache_hit: None,
+            index_used: false,
+            estimated_cost: Self::estimate_search_cost(data.len(), options),
+        };
+        if let Some(budget) = self.config.max_query_cost
+            && plan.estimated_cost > budget && !options.force {
+            return Err(DbError::BadRequest(format!(
+                "Estimated query cost {} exceeds the limit of {} ({} records). Narrow the query or send force: true",
+                plan.estimated_cost, budget, data.len()
+            )));
+        }
         
+        // A limited search stops at maxResults matches, expired ones must not take up any of them
+        if options.max_results.is_some() {
+            Self::retain_live(&mut data);
+        }
+        let node_count = options.nodes.unwrap_or_else(get_optimal_node_count);
+
         match query {
-            Some(q) if !q.is_empty() => {
+            // A whitespace-only query is no query at all: it returns every record like an empty one
+            Some(q) if !q.trim().is_empty() => {
                 let results = match query_type {
-                    Some("key") => self.search_by_key(&data, q),
-                    Some("value") => self.search_by_value(&data, q),
+                    Some("key") => {
+                        plan.method = "key_scan";
+                        Arc::new(self.search_by_key(&data, q))
+                    }
+                    Some("value") => {
+                        plan.method = "value_scan";
+                        Arc::new(self.search_by_value(&data, q))
+                    }
+                    Some("id") => {
+                        plan.method = "id_scan";
+                        let id_field = self.read_settings(username, db_name)?.id_field;
+                        Arc::new(self.search_by_id(&data, &id_field, q))
+                    }
+                    Some("regex") => {
+                        // Compiled once per request; only string values are matched
+                        let regex = compile_regex(q).map_err(DbError::BadRequest)?;
+                        let nodes = split_nodes(&data, node_count);
+                        let filepath = self.get_db_path(username, db_name)?;
+                        plan.method = "regex";
+                        plan.nodes = nodes.len();
+                        if options.no_cache {
+                            uncached_results(regex_search(&nodes, &regex))
+                        } else {
+                            let (results, cache_hit) = cached_regex_search(&filepath, &nodes, q, &regex, self.config.cache_ttl.as_secs());
+                            if cache_hit {
+                                plan.method = "search_cache";
+                            }
+                            plan.search_cache_hit = Some(cache_hit);
+                            results
+                        }
+                    }
+                    Some("fuzzy") => {
+                        // Opt-in: an edit distance per string value is much slower than contains
+                        let max_distance = options.max_distance.unwrap_or(DEFAULT_FUZZY_DISTANCE);
+                        let nodes = split_nodes(&data, node_count);
+                        let filepath = self.get_db_path(username, db_name)?;
+                        plan.method = "fuzzy";
+                        plan.nodes = nodes.len();
+                        if options.no_cache {
+                            uncached_results(fuzzy_search(&nodes, q, max_distance))
+                        } else {
+                            let (results, cache_hit) = cached_fuzzy_search(&filepath, &nodes, q, max_distance, self.config.cache_ttl.as_secs());
+                            if cache_hit {
+                                plan.method = "search_cache";
+                            }
+                            plan.search_cache_hit = Some(cache_hit);
+                            results
+                        }
+                    }
+                    // A single term keeps the plain substring path below
+                    _ if options.all_terms.is_some() && split_terms(q).len() > 1 => {
+                        let all = options.all_terms == Some(true);
+                        let nodes = split_nodes(&data, node_count);
+                        let filepath = self.get_db_path(username, db_name)?;
+                        plan.method = "multi_term";
+                        plan.nodes = nodes.len();
+                        if options.no_cache {
+                            uncached_results(multi_term_search(&nodes, &split_terms(q), all))
+                        } else {
+                            let (results, cache_hit) = cached_multi_term_search(&filepath, &nodes, q, all, self.config.cache_ttl.as_secs());
+                            if cache_hit {
+                                plan.method = "search_cache";
+                            }
+                            plan.search_cache_hit = Some(cache_hit);
+                            results
+                        }
+                    }
                     _ => {
                         // Use intelligent search with cache
-                        // Get optimal node count based on CPU cores
-                        let node_count = get_optimal_node_count();
-                        let nodes = split_nodes(data, node_count);
+                        // One node per CPU core unless the request asked for a count
+                        let nodes = split_nodes(&data, node_count);
                         
-                        // Use cached parallel search with 5-minute TTL
-                        let filepath = Self::get_db_path(username, db_name);
-                        cached_parallel_search(&filepath, &nodes, q, 300)
+                        // Search results expire with the same TTL as loaded databases
+                        let filepath = self.get_db_path(username, db_name)?;
+                        plan.nodes = nodes.len();
+                        match options.max_results {
+                            // A partial result set is never cached, but a cached full one can answer it
+                            Some(max) => match get_cached_search(&filepath, CONTAINS_MODE, q).filter(|_| !options.no_cache) {
+                                Some(cached) => {
+                                    plan.method = "search_cache";
+                                    plan.search_cache_hit = Some(true);
+                                    cached
+                                }
+                                None if options.stable_order => {
+                                    plan.method = "limited_ordered";
+                                    plan.search_cache_hit = (!options.no_cache).then_some(false);
+                                    Arc::new(ordered_search_limited(&nodes, q, max).into_iter().cloned().collect())
+                                }
+                                None => {
+                                    plan.method = "limited";
+                                    plan.search_cache_hit = (!options.no_cache).then_some(false);
+                                    Arc::new(smart_search_limited(&nodes, q, max).into_iter().cloned().collect())
+                                }
+                            },
+                            None if options.no_cache => {
+                                plan.method = smart_search_method(data.len());
+                                uncached_results(smart_search(&nodes, q))
+                            }
+                            None => {
+                                let (results, cache_hit) = cached_smart_search(&filepath, &nodes, q, self.config.cache_ttl.as_secs());
+                                plan.method = if cache_hit { "search_cache" } else { smart_search_method(data.len()) };
+                                plan.search_cache_hit = Some(cache_hit);
+                                results
+                            }
+                        }
                     }
                 };
-                Ok(results)
+                // Cached results can outlive a record's expiry, so they are filtered on every read
+                let results = Self::without_expired(results);
+                // Every mode honours maxResults; only the default one stops scanning early
+                let results = match options.max_results {
+                    Some(max) if results.len() > max => Arc::new(results[..max].to_vec()),
+                    _ => results,
+                };
+                Ok((results, plan))
             },
             _ => {
                 // No query, return all records
-                Ok(data)
+                Self::retain_live(&mut data);
+                Ok((Arc::new(data), plan))
             }
         }
     }
@@ -136,6 +1121,15 @@ impl DatabaseManager {
             .collect()
     }
 
+    // Exact id lookup on the database's id field, zero or one record (there is no index, so this is a linear scan)
+    fn search_by_id(&self, data: &[Value], id_field: &str, id: &str) -> Vec<Value> {
+        data.iter()
+            .find(|item| Self::id_matches(item, id_field, id))
+            .cloned()
+            .into_iter()
+            .collect()
+    }
+
     // Search by value in any part of the structure
     fn search_by_value(&self, data: &[Value], search_value: &str) -> Vec<Value> {
         data.iter()
@@ -145,91 +1139,928 @@ impl DatabaseManager {
     }
 
     // POST - Insert new record
-    pub fn insert_record(&self, username: &str, db_name: &str, mut record: Value) -> Result<String, String> {
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
-        }
+    pub fn insert_record(&self, username: &str, db_name: &str, actor: &str, mut record: Value) -> Result<String, DbError> {
+        self.require_database(username, db_name)?;
+        let ctx = self.write_context(username, db_name, actor)?;
 
-        let mut data = Self::read_database_cached(username, db_name)?;
-        
-        // Add metadata to record
-        if let Value::Object(ref mut obj) = record {
-            obj.insert("_id".to_string(), Value::String(Uuid::new_v4().to_string()));
-            obj.insert("_created_at".to_string(), Value::String(Utc::now().to_rfc3339()));
-        }
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let mut data = self.read_for_write(username, db_name)?;
+        self.add_insert_metadata(username, db_name, &data, &mut record, &ctx)?;
 
         data.push(record);
-        Self::write_database(username, db_name, &data)?;
+        self.write_database(username, db_name, &data)?;
         
         Ok("Record inserted successfully".to_string())
     }
 
-    // PUT - Update records with ID support
-    pub fn update_records(&self, username: &str, db_name: &str, query: &str, update_data: Value, id_update: Option<&str>) -> Result<String, String> {
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
-        }
-
-        let mut data = Self::read_database_cached(username, db_name)?;
-        let mut updated_count = 0;
-
-        // Update by specific ID if provided
-        if let Some(target_id) = id_update {
-            for item in &mut data {
-                if let &mut Value::Object(ref obj) = item {
-                    if let Some(Value::String(id)) = obj.get("_id") {
-                        if id == target_id {
-                            if let (Value::Object(target), Value::Object(source)) = (item, &update_data) {
-                                // Update fields from update_data
-                                for (key, value) in source {
-                                    target.insert(key.clone(), value.clone());
-                                }
-                                // Add update timestamp
-                                target.insert("_updated_at".to_string(), Value::String(Utc::now().to_rfc3339()));
-                                updated_count += 1;
-                                break; // Only update one record when using ID
-                            }
-                        }
-                    }
+    // Add metadata to a new record; a client-supplied id is kept but must not already exist
+    // A custom id field may also hold numbers, since it usually carries the client's own keys
+    fn add_insert_metadata(&self, username: &str, db_name: &str, data: &[Value], record: &mut Value, ctx: &WriteContext) -> Result<(), DbError> {
+        if let Value::Object(obj) = record {
+            let id_field = ctx.id_field.as_str();
+            let id = match obj.get(id_field) {
+                None => None,
+                Some(Value::String(id)) if !id.is_empty() => Some(id.clone()),
+                Some(Value::Number(id)) if id_field != DEFAULT_ID_FIELD => Some(id.to_string()),
+                Some(_) if id_field == DEFAULT_ID_FIELD => return Err(DbError::BadRequest("_id must be a non-empty string".to_string())),
+                Some(_) => return Err(DbError::BadRequest(format!("{} must be a non-empty string or a number", id_field))),
+            };
+            match id {
+                None => {
+                    obj.insert(id_field.to_string(), Value::String(self.config.id_version.generate()));
                 }
-            }
-        } else {
-            // Update by query (existing behavior)
-            for item in &mut data {
-                if self.item_matches_query(item, query) {
-                    if let (Value::Object(target), Value::Object(source)) = (item, &update_data) {
-                        // Update fields
-                        for (key, value) in source {
-                            target.insert(key.clone(), value.clone());
-                        }
-                        // Add update timestamp
-                        target.insert("_updated_at".to_string(), Value::String(Utc::now().to_rfc3339()));
-                        updated_count += 1;
+                Some(id) => {
+                    if data.iter().any(|item| Self::id_matches(item, id_field, &id)) {
+                        return Err(DbError::Conflict(format!("A record with {} '{}' already exists", id_field, id)));
                     }
                 }
             }
+            obj.insert("_created_at".to_string(), Value::String(Utc::now().to_rfc3339()));
+            obj.insert("_seq".to_string(), Value::from(self.next_seq(username, db_name, data, 1)?));
+            if let Some(author) = ctx.author {
+                obj.insert("_created_by".to_string(), Value::String(author.to_string()));
+            }
         }
+        Ok(())
+    }
 
-        Self::write_database(username, db_name, &data)?;
-        Ok(format!("Updated {} records", updated_count))
+    // Author (only when the database has track_authors enabled) and id field for a write by `actor`
+    fn write_context<'a>(&self, username: &str, db_name: &str, actor: &'a str) -> Result<WriteContext<'a>, DbError> {
+        let settings = self.read_settings(username, db_name)?;
+        Ok(WriteContext {
+            author: settings.track_authors.then_some(actor),
+            id_field: settings.id_field,
+        })
     }
 
-    // DELETE - Delete records matching query
-    pub fn delete_records(&self, username: &str, db_name: &str, query: &str) -> Result<String, String> {
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
+    // Whether the record's id field holds `id`; numbers match their decimal text
+    fn id_matches(item: &Value, id_field: &str, id: &str) -> bool {
+        match item.get(id_field) {
+            Some(Value::String(value)) => value == id,
+            Some(Value::Number(value)) => value.to_string() == id,
+            _ => false,
         }
+    }
 
-        let mut data = Self::read_database_cached(username, db_name)?;
-        let initial_count = data.len();
-        
-        // Filter records that DON'T match the query (delete those that DO match)
-        data.retain(|item| !self.item_matches_query(item, query));
+    // An id as id_matches compares it, so `1` and `"1"` are the same id
+    fn id_key(id: &Value) -> String {
+        match id {
+            Value::String(value) => value.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    // Reserve `count` consecutive `_seq` values after everything in `data`, returning the first one
+    // Callers hold the write lock, so two writers never get the same range
+    fn next_seq(&self, username: &str, db_name: &str, data: &[Value], count: u64) -> Result<u64, DbError> {
+        self.next_seq_after(username, db_name, Self::max_seq(data.iter()), count)
+    }
+
+    fn next_seq_after(&self, username: &str, db_name: &str, max_stored: u64, count: u64) -> Result<u64, DbError> {
+        let filepath = self.get_db_path(username, db_name)?;
+        let mut last_seq = LAST_SEQ.lock().unwrap();
+        let last = last_seq.entry(filepath).or_default();
+        let first = (*last).max(max_stored) + 1;
+        *last = first + count - 1;
+        Ok(first)
+    }
+
+    // Highest `_seq` among the records, 0 when none has one
+    fn max_seq<'a>(records: impl Iterator<Item = &'a Value>) -> u64 {
+        records
+            .filter_map(|item| item.get("_seq").and_then(Value::as_u64))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // IMPORT - Replace or append the database contents with a batch of records
+    // Records without an `_id` get a fresh one, existing ids are kept as-is; every record gets `_created_at` if missing
+    pub fn import_records(&self, username: &str, db_name: &str, records: Vec<Value>, append: bool) -> Result<String, DbError> {
+        self.require_database(username, db_name)?;
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(position) = records.iter().position(|record| !record.is_object()) {
+            return Err(DbError::BadRequest(format!("Record at index {} is not a JSON object", position)));
+        }
+
+        let imported_count = records.len();
+        let mut data = if append {
+            self.read_for_write(username, db_name)?
+        } else {
+            Vec::with_capacity(imported_count)
+        };
+
+        // Ids must stay unique, both against existing records and within the batch
+        let id_field = self.read_settings(username, db_name)?.id_field;
+        let mut seen_ids: HashSet<String> = data.iter()
+            .filter_map(|item| item.get(&id_field).map(Self::id_key))
+            .collect();
+        for (position, record) in records.iter().enumerate() {
+            if let Some(id) = record.get(&id_field)
+                && !seen_ids.insert(Self::id_key(id)) {
+                return Err(DbError::Conflict(format!("Record at index {} has duplicate {} {}", position, id_field, id)));
+            }
+        }
+
+        // Imported `_seq` values are kept (a restore keeps its order), the rest continue the sequence
+        let missing_seq = records.iter().filter(|record| record.get("_seq").is_none()).count() as u64;
+        let mut seq = if missing_seq > 0 {
+            self.next_seq_after(username, db_name, Self::max_seq(data.iter().chain(&records)), missing_seq)?
+        } else {
+            0
+        };
+        for mut record in records {
+            if let Value::Object(ref mut obj) = record {
+                if !obj.contains_key(&id_field) {
+                    obj.insert(id_field.clone(), Value::String(self.config.id_version.generate()));
+                }
+                obj.entry("_created_at").or_insert_with(|| Value::String(Utc::now().to_rfc3339()));
+                if !obj.contains_key("_seq") {
+                    obj.insert("_seq".to_string(), Value::from(seq));
+                    seq += 1;
+                }
+            }
+            data.push(record);
+        }
+
+        self.write_database(username, db_name, &data)?;
+        Ok(format!("Imported {} records ({} total)", imported_count, data.len()))
+    }
+
+    // PUT - Update records with ID support
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_records(&self, username: &str, db_name: &str, actor: &str, target: UpdateTarget, update_data: Value, mode: UpdateMode, return_docs: bool) -> Result<WriteOutcome, DbError> {
+        self.require_database(username, db_name)?;
+        let ctx = self.write_context(username, db_name, actor)?;
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let mut data = self.read_for_write(username, db_name)?;
+        let (updated_count, affected) = self.update_matches(&mut data, &target, &update_data, &mode, return_docs, &ctx)?;
+
+        self.write_database(username, db_name, &data)?;
+        Ok(WriteOutcome {
+            message: format!("Updated {} records", updated_count),
+            count: updated_count,
+            affected,
+        })
+    }
+
+    // UPSERT - Update the matching records, or insert the body as a new record when nothing matches
+    // Both branches run under one write lock; returns true in the second field when a record was inserted
+    #[allow(clippy::too_many_arguments)]
+    pub fn upsert_record(&self, username: &str, db_name: &str, actor: &str, target: UpdateTarget, update_data: Value, mode: UpdateMode, return_docs: bool) -> Result<(WriteOutcome, bool), DbError> {
+        self.require_database(username, db_name)?;
+        let ctx = self.write_context(username, db_name, actor)?;
+        if !update_data.is_object() {
+            return Err(DbError::BadRequest("Upsert body must be a JSON object".to_string()));
+        }
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let mut data = self.read_for_write(username, db_name)?;
+        let outcome = self.upsert_in(username, db_name, &mut data, target, update_data, mode, return_docs, &ctx)?;
+        self.write_database(username, db_name, &data)?;
+        Ok(outcome)
+    }
+
+    // Upsert against records already in memory, the caller persists `data`
+    #[allow(clippy::too_many_arguments)]
+    fn upsert_in(&self, username: &str, db_name: &str, data: &mut Vec<Value>, target: UpdateTarget, update_data: Value, mode: UpdateMode, return_docs: bool, ctx: &WriteContext) -> Result<(WriteOutcome, bool), DbError> {
+        let (updated_count, affected) = self.update_matches(data, &target, &update_data, &mode, return_docs, ctx)?;
+        if updated_count > 0 {
+            return Ok((WriteOutcome {
+                message: format!("Updated {} records", updated_count),
+                count: updated_count,
+                affected,
+            }, false));
+        }
+
+        // Nothing matched: an idUpdate target becomes the new record's id unless the body has one
+        let mut record = update_data;
+        if let (UpdateTarget::Id(id), Value::Object(obj)) = (&target, &mut record) {
+            obj.entry(ctx.id_field.as_str()).or_insert_with(|| Value::String(id.to_string()));
+        }
+        self.add_insert_metadata(username, db_name, data, &mut record, ctx)?;
+        let affected = if return_docs { vec![record.clone()] } else { Vec::new() };
+        data.push(record);
+
+        Ok((WriteOutcome {
+            message: "Record inserted successfully".to_string(),
+            count: 1,
+            affected,
+        }, true))
+    }
+
+    // Apply the update to every record matched by `target`, returning how many changed
+    // and (with `return_docs`) their post-update versions
+    fn update_matches(&self, data: &mut [Value], target: &UpdateTarget, update_data: &Value, mode: &UpdateMode, return_docs: bool, ctx: &WriteContext) -> Result<(usize, Vec<Value>), DbError> {
+        let mut updated_count = 0;
+        let mut affected = Vec::new();
+
+        for item in data.iter_mut() {
+            let matched = match target {
+                // Update by specific ID if provided
+                UpdateTarget::Id(target_id) => Self::id_matches(item, &ctx.id_field, target_id),
+                // Update by query (existing behavior)
+                UpdateTarget::Query(query) => self.item_matches_query(item, query),
+            };
+
+            if matched && self.apply_update(item, update_data, mode, ctx)? {
+                updated_count += 1;
+                if return_docs {
+                    affected.push(item.clone());
+                }
+                if let UpdateTarget::Id(_) = target {
+                    break; // Only update one record when using ID
+                }
+            }
+        }
+
+        Ok((updated_count, affected))
+    }
+
+    // Apply the update to a single record, returns false if the record (or body) is not an object
+    fn apply_update(&self, item: &mut Value, update_data: &Value, mode: &UpdateMode, ctx: &WriteContext) -> Result<bool, DbError> {
+        let Value::Object(target) = item else {
+            return Ok(false);
+        };
+
+        match mode {
+            UpdateMode::Merge | UpdateMode::Replace => {
+                let Value::Object(source) = update_data else {
+                    return Ok(false);
+                };
+                let replace = *mode == UpdateMode::Replace;
+
+                // The id field is kept like the immutable metadata, whatever its name
+                let kept = |key: &str| IMMUTABLE_FIELDS.contains(&key) || key == ctx.id_field;
+                if replace {
+                    // Drop every field except the immutable metadata
+                    target.retain(|key, _| kept(key));
+                }
+
+                for (key, value) in source {
+                    if replace && kept(key) {
+                        continue;
+                    }
+                    target.insert(key.clone(), value.clone());
+                }
+            }
+            UpdateMode::Increment { field, amount } => {
+                let current = match target.get(*field) {
+                    None => Number::from(0),
+                    Some(Value::Number(n)) => n.clone(),
+                    Some(_) => return Err(DbError::BadRequest(format!("Field '{}' is not numeric and cannot be incremented", field))),
+                };
+                let updated = Self::add_numbers(&current, amount)
+                    .ok_or_else(|| DbError::BadRequest(format!("Increment of field '{}' overflowed", field)))?;
+                target.insert(field.to_string(), Value::Number(updated));
+            }
+            UpdateMode::ArrayPush { field } | UpdateMode::ArrayPull { field } => {
+                let push = matches!(mode, UpdateMode::ArrayPush { .. });
+                match target.get_mut(*field) {
+                    Some(Value::Array(arr)) => {
+                        if push {
+                            arr.push(update_data.clone());
+                        } else {
+                            arr.retain(|element| element != update_data);
+                        }
+                    }
+                    Some(_) => return Err(DbError::BadRequest(format!("Field '{}' is not an array", field))),
+                    None if push => {
+                        target.insert(field.to_string(), Value::Array(vec![update_data.clone()]));
+                    }
+                    // Pulling from a missing field is a no-op
+                    None => {}
+                }
+            }
+            UpdateMode::Patch(ops) => {
+                let id = target.get(&ctx.id_field).cloned();
+                // Pointers resolve from a Value root, so move the map out and put it back once patched
+                let mut patched = Value::Object(std::mem::take(target));
+                let result = ops.iter().try_for_each(|op| op.apply(&mut patched));
+                if let Value::Object(map) = patched {
+                    *target = map;
+                }
+                let id_text = match &id {
+                    Some(Value::String(id)) => id.clone(),
+                    Some(id) => id.to_string(),
+                    None => String::new(),
+                };
+                result.map_err(|e| e.with_context(&format!("Patch failed on record '{}'", id_text)))?;
+                // `_id` is already rejected when the ops are parsed, a custom id field is checked here
+                if target.get(&ctx.id_field) != id.as_ref() {
+                    return Err(DbError::BadRequest(format!("Patch failed on record '{}': {} cannot be changed", id_text, ctx.id_field)));
+                }
+            }
+        }
+
+        // Add update timestamp
+        target.insert("_updated_at".to_string(), Value::String(Utc::now().to_rfc3339()));
+        if let Some(author) = ctx.author {
+            target.insert("_updated_by".to_string(), Value::String(author.to_string()));
+        }
+        Ok(true)
+    }
+
+    // Add two JSON numbers, staying integral when both sides are integers
+    fn add_numbers(a: &Number, b: &Number) -> Option<Number> {
+        match (a.as_i64(), b.as_i64()) {
+            (Some(x), Some(y)) => x.checked_add(y).map(Number::from),
+            _ => Number::from_f64(a.as_f64()? + b.as_f64()?),
+        }
+    }
+
+    // DELETE - Delete records matching query
+    pub fn delete_records(&self, username: &str, db_name: &str, query: &str, confirm_bulk: bool, return_docs: bool) -> Result<WriteOutcome, DbError> {
+        Self::require_delete_query(query)?;
+        self.require_database(username, db_name)?;
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let data = self.read_for_write(username, db_name)?;
         
-        let deleted_count = initial_count - data.len();
-        Self::write_database(username, db_name, &data)?;
+        // Split records into those that match the query (deleted) and the rest (kept)
+        let (removed, kept): (Vec<Value>, Vec<Value>) = data
+            .into_iter()
+            .partition(|item| self.item_matches_query(item, query));
+        
+        let deleted_count = removed.len();
+        self.check_bulk_delete(deleted_count, deleted_count + kept.len(), confirm_bulk)?;
+        self.write_database(username, db_name, &kept)?;
         
-        Ok(format!("Deleted {} records", deleted_count))
+        Ok(WriteOutcome {
+            message: format!("Deleted {} records", deleted_count),
+            count: deleted_count,
+            affected: if return_docs { removed } else { Vec::new() },
+        })
+    }
+
+    // DEDUPE - Keep the first record of each group sharing the same values for `fields`
+    // With no fields, records are compared whole, leaving out the id and the server-stamped metadata
+    pub fn dedupe_records(&self, username: &str, db_name: &str, fields: &[&str], confirm_bulk: bool, return_docs: bool) -> Result<WriteOutcome, DbError> {
+        self.require_database(username, db_name)?;
+        let id_field = self.read_settings(username, db_name)?.id_field;
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let data = self.read_for_write(username, db_name)?;
+        let total = data.len();
+        let now = Utc::now();
+
+        let mut seen = HashSet::new();
+        let (kept, removed): (Vec<Value>, Vec<Value>) = data
+            .into_iter()
+            .partition(|item| {
+                // Expired records are hidden, so they neither count as a first occurrence nor get removed here
+                if Self::is_expired(item, now) {
+                    return true;
+                }
+                match Self::dedupe_key(item, fields, &id_field) {
+                    Some(key) => seen.insert(key),
+                    None => true,
+                }
+            });
+
+        let removed_count = removed.len();
+        if removed_count > 0 {
+            self.check_bulk_delete(removed_count, total, confirm_bulk)?;
+            self.write_database(username, db_name, &kept)?;
+        }
+
+        Ok(WriteOutcome {
+            message: format!("Removed {} duplicate records", removed_count),
+            count: removed_count,
+            affected: if return_docs { removed } else { Vec::new() },
+        })
+    }
+
+    // JSON text of the values compared by dedupe; records missing one of the fields have no key and are kept
+    // Object keys are ordered, so equal documents always serialize the same way
+    fn dedupe_key(item: &Value, fields: &[&str], id_field: &str) -> Option<String> {
+        if fields.is_empty() {
+            return match item {
+                Value::Object(obj) => {
+                    let content: serde_json::Map<String, Value> = obj.iter()
+                        .filter(|(key, _)| key.as_str() != id_field && !SERVER_FIELDS.contains(&key.as_str()))
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect();
+                    Some(Value::Object(content).to_string())
+                }
+                other => Some(other.to_string()),
+            };
+        }
+        let values = fields.iter()
+            .map(|field| item.get(*field).cloned())
+            .collect::<Option<Vec<Value>>>()?;
+        Some(Value::Array(values).to_string())
+    }
+
+    // TRANSACTION - Apply a batch of writes to in-memory copies and persist them only if every step succeeds
+    // Every database in the batch stays write-locked until the files are replaced, so no writer interleaves
+    pub fn run_transaction(&self, username: &str, ops: &[TransactionOp]) -> Result<Vec<Value>, DbError> {
+        if ops.is_empty() {
+            return Err(DbError::BadRequest("Transaction needs at least one operation".to_string()));
+        }
+
+        // Lock in name order so two transactions over the same databases can't deadlock
+        let db_names: BTreeSet<&str> = ops.iter().map(|op| op.database.as_str()).collect();
+        for db_name in &db_names {
+            self.require_database(username, db_name)
+                .map_err(|e| e.with_context(&format!("Database '{}'", db_name)))?;
+        }
+        let locks = db_names.iter()
+            .map(|db_name| self.write_lock(username, db_name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let _guards: Vec<_> = locks.iter()
+            .map(|lock| lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+            .collect();
+
+        let mut staged: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+        let mut results = Vec::with_capacity(ops.len());
+        for (index, op) in ops.iter().enumerate() {
+            let data = match staged.entry(op.database.as_str()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.read_for_write(username, &op.database)?),
+            };
+            let result = self.apply_transaction_op(username, data, op)
+                .map_err(|e| e.with_context(&format!("Operation {} ({} on '{}') failed, nothing was written", index, op.op, op.database)))?;
+            results.push(result);
+        }
+
+        self.commit_staged(username, &staged, false)?;
+        Ok(results)
+    }
+
+    // Run one transaction step against the staged copy of its database
+    // Transactions only touch the caller's own databases, so the owner is also the author
+    fn apply_transaction_op(&self, username: &str, data: &mut Vec<Value>, op: &TransactionOp) -> Result<Value, DbError> {
+        let db_name = op.database.as_str();
+        let ctx = self.write_context(username, db_name, username)?;
+        let operation = op.op.to_lowercase();
+        let target = || match (op.id_update.as_deref(), op.query.as_deref()) {
+            (Some(id), _) => Ok(UpdateTarget::Id(id)),
+            (None, Some(query)) => Ok(UpdateTarget::Query(query)),
+            (None, None) => Err(DbError::BadRequest(format!("query or idUpdate required for {}", op.op))),
+        };
+        let body = || op.body.clone().ok_or_else(|| DbError::BadRequest(format!("body required for {}", op.op)));
+        let field = || op.field.as_deref().ok_or_else(|| DbError::BadRequest(format!("field required for {}", op.op)));
+
+        let (count, action) = match operation.as_str() {
+            "post" => {
+                let mut record = body()?;
+                self.add_insert_metadata(username, db_name, data, &mut record, &ctx)?;
+                data.push(record);
+                (1, "inserted")
+            }
+            "put" => {
+                let mode = UpdateMode::parse(op.replace_mode.as_deref())?;
+                (self.update_matches(data, &target()?, &body()?, &mode, false, &ctx)?.0, "updated")
+            }
+            "upsert" => {
+                let update_data = body()?;
+                if !update_data.is_object() {
+                    return Err(DbError::BadRequest("Upsert body must be a JSON object".to_string()));
+                }
+                let mode = UpdateMode::parse(op.replace_mode.as_deref())?;
+                let (outcome, inserted) = self.upsert_in(username, db_name, data, target()?, update_data, mode, false, &ctx)?;
+                (outcome.count, if inserted { "inserted" } else { "updated" })
+            }
+            "patch" => {
+                let ops = PatchOp::parse_all(body()?)?;
+                (self.update_matches(data, &target()?, &Value::Null, &UpdateMode::Patch(&ops), false, &ctx)?.0, "updated")
+            }
+            "increment" => {
+                let amount = op.amount.clone().unwrap_or_else(|| Number::from(1));
+                let mode = UpdateMode::Increment { field: field()?, amount };
+                (self.update_matches(data, &target()?, &Value::Null, &mode, false, &ctx)?.0, "updated")
+            }
+            "arraypush" | "arraypull" => {
+                let mode = if operation == "arraypush" {
+                    UpdateMode::ArrayPush { field: field()? }
+                } else {
+                    UpdateMode::ArrayPull { field: field()? }
+                };
+                (self.update_matches(data, &target()?, &body()?, &mode, false, &ctx)?.0, "updated")
+            }
+            "delete" => {
+                let query = op.query.as_deref()
+                    .ok_or_else(|| DbError::BadRequest("query required for delete".to_string()))?;
+                Self::require_delete_query(query)?;
+                let matched = data.iter().filter(|item| self.item_matches_query(item, query)).count();
+                self.check_bulk_delete(matched, data.len(), op.confirm_bulk)?;
+                data.retain(|item| !self.item_matches_query(item, query));
+                (matched, "deleted")
+            }
+            other => return Err(DbError::BadRequest(format!(
+                "Unsupported transaction operation '{}'. Use: post, put, upsert, patch, increment, arrayPush, arrayPull, delete", other
+            ))),
+        };
+
+        Ok(serde_json::json!({
+            "database": db_name,
+            "op": op.op,
+            "action": action,
+            "count": count
+        }))
+    }
+
+    // Persist every staged database: all files are written next to the originals first, then each
+    // original is moved aside to `.bak` and the new file renamed into its place. The originals are only
+    // deleted once every database is in place, so a failed write or rename puts back the ones already
+    // replaced and leaves every database as it was. A crash halfway through the renames can still leave
+    // some databases committed, with their previous version in `.bak`
+    fn commit_staged(&self, username: &str, staged: &BTreeMap<&str, Vec<Value>>, force_compact: bool) -> Result<(), DbError> {
+        let mut serialized = Vec::with_capacity(staged.len());
+        for (db_name, data) in staged {
+            let (filepath, stale, contents) = self.encode_database(username, db_name, data, force_compact)?;
+            serialized.push((self.get_db_path(username, db_name)?, filepath, stale, contents));
+        }
+        // The whole batch counts against the quota at once
+        let sizes: Vec<(&str, u64)> = serialized.iter()
+            .flat_map(|(_, path, stale, contents)| [(path.as_str(), contents.len() as u64), (stale.as_str(), 0)])
+            .collect();
+        let growth = self.check_quota(username, &sizes)?;
+
+        let mut pending: Vec<(String, String, String, String)> = Vec::with_capacity(staged.len());
+        for (cache_key, filepath, stale, contents) in serialized {
+            let temp_path = format!("{}.tx", filepath);
+            if let Err(e) = fs::write(&temp_path, contents) {
+                let _ = fs::remove_file(&temp_path);
+                for (_, _, _, temp) in &pending {
+                    let _ = fs::remove_file(temp);
+                }
+                return Err(DbError::Internal(format!("Transaction not committed: {}", e)));
+            }
+            pending.push((cache_key, filepath, stale, temp_path));
+        }
+
+        // Files already swapped in, with the backup of the original they replaced (None when there was none)
+        let mut replaced: Vec<(&str, Option<String>)> = Vec::with_capacity(pending.len());
+        for (_, filepath, _, temp_path) in &pending {
+            match Self::swap_in(filepath, temp_path) {
+                Ok(backup) => replaced.push((filepath, backup)),
+                Err(e) => {
+                    for (filepath, backup) in replaced.iter().rev() {
+                        let restored = match backup {
+                            Some(backup) => fs::rename(backup, filepath),
+                            None => fs::remove_file(filepath),
+                        };
+                        if let Err(e) = restored {
+                            eprintln!("⚠️  Could not roll back {}: {}", filepath, e);
+                        }
+                    }
+                    for (_, _, _, temp) in &pending {
+                        let _ = fs::remove_file(temp);
+                    }
+                    return Err(DbError::Internal(format!("Transaction not committed: {}", e)));
+                }
+            }
+        }
+
+        // Committed: what is left is cleanup, a failure there no longer undoes the transaction
+        for (_, backup) in &replaced {
+            if let Some(backup) = backup
+                && let Err(e) = fs::remove_file(backup) {
+                eprintln!("⚠️  Could not remove {}: {}", backup, e);
+            }
+        }
+        for (cache_key, _, stale, _) in &pending {
+            if let Err(e) = Self::remove_stale(stale) {
+                eprintln!("⚠️  {}", e);
+            }
+            invalidate_cache_for_path(cache_key);
+        }
+        self.record_growth(username, growth);
+        for db_name in staged.keys() {
+            self.invalidate_cache(username, db_name);
+        }
+        Ok(())
+    }
+
+    // Move the file at `filepath` to `<filepath>.bak` and rename `temp_path` into its place, returning the
+    // backup; if the second rename fails the original is moved back
+    fn swap_in(filepath: &str, temp_path: &str) -> std::io::Result<Option<String>> {
+        if !Path::new(filepath).exists() {
+            return fs::rename(temp_path, filepath).map(|_| None);
+        }
+        let backup = format!("{}.bak", filepath);
+        fs::rename(filepath, &backup)?;
+        if let Err(e) = fs::rename(temp_path, filepath) {
+            let _ = fs::rename(&backup, filepath);
+            return Err(e);
+        }
+        Ok(Some(backup))
+    }
+
+    // Records a write starts from: expired ones are dropped so they can't be matched, revived or
+    // collide with a new `_id`, and the write that follows persists their removal
+    fn read_for_write(&self, username: &str, db_name: &str) -> Result<Vec<Value>, DbError> {
+        let mut data = self.read_database_cached(username, db_name)?;
+        Self::retain_live(&mut data);
+        Ok(data)
+    }
+
+    // Reject writes that would grow the user's folder past the quota; shrinking writes always pass
+    // so a user over quota can still delete. Returns the growth to record once the write succeeds
+    fn check_quota(&self, username: &str, new_sizes: &[(&str, u64)]) -> Result<i64, DbError> {
+        let Some(quota) = self.config.user_quota else {
+            return Ok(0);
+        };
+        let growth: i64 = new_sizes.iter()
+            .map(|(path, new_size)| *new_size as i64 - fs::metadata(path).map(|m| m.len() as i64).unwrap_or(0))
+            .sum();
+        if growth <= 0 {
+            return Ok(growth);
+        }
+        let used = self.user_storage_bytes(username)?;
+        let projected = used.saturating_add_signed(growth);
+        if projected > quota {
+            return Err(DbError::TooLarge(format!(
+                "Storage quota exceeded: this write needs {} bytes, {} of {} bytes are used",
+                growth, used, quota
+            )));
+        }
+        Ok(growth)
+    }
+
+    // Size of every file in the user's folder, cached briefly
+    pub fn user_storage_bytes(&self, username: &str) -> Result<u64, DbError> {
+        let user_dir = self.user_dir(username)?;
+        if let Some((bytes, measured)) = USER_USAGE.lock().unwrap().get(&user_dir)
+            && measured.elapsed() < USAGE_CACHE_TTL {
+            return Ok(*bytes);
+        }
+
+        // The audit log is excluded so logging writes can never push a user over quota
+        let bytes = fs::read_dir(&user_dir)
+            .map(|entries| entries.flatten()
+                .filter(|entry| entry.file_name() != AUDIT_LOG_FILE)
+                .filter_map(|entry| entry.metadata().ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len())
+                .sum())
+            .unwrap_or(0);
+        USER_USAGE.lock().unwrap().insert(user_dir, (bytes, Instant::now()));
+        Ok(bytes)
+    }
+
+    fn record_growth(&self, username: &str, growth: i64) {
+        if growth == 0 {
+            return;
+        }
+        if let Ok(user_dir) = self.user_dir(username)
+            && let Some((bytes, _)) = USER_USAGE.lock().unwrap().get_mut(&user_dir) {
+            *bytes = bytes.saturating_add_signed(growth);
+        }
+    }
+
+    // A record is expired once its `_expires_at` is at or before now; unparseable values never expire
+    fn is_expired(item: &Value, now: DateTime<Utc>) -> bool {
+        Self::expired_by(item.get(EXPIRES_AT_FIELD), now)
+    }
+
+    // Whether an `_expires_at` value is a timestamp at or before `now`
+    fn expired_by(expires_at: Option<&Value>, now: DateTime<Utc>) -> bool {
+        expires_at
+            .and_then(Value::as_str)
+            .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn retain_live(data: &mut Vec<Value>) {
+        let now = Utc::now();
+        data.retain(|item| !Self::is_expired(item, now));
+    }
+
+    // Shared results are only copied when something in them has expired
+    fn without_expired(results: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        let now = Utc::now();
+        if results.iter().any(|item| Self::is_expired(item, now)) {
+            Arc::new(results.iter().filter(|item| !Self::is_expired(item, now)).cloned().collect())
+        } else {
+            results
+        }
+    }
+
+    // PURGE - Remove expired records from every database file, returns how many were dropped
+    pub fn purge_expired(&self) -> usize {
+        let users_dir = self.config.data_dir.join(USERS_DIR);
+        let Ok(user_dirs) = fs::read_dir(&users_dir) else {
+            return 0;
+        };
+
+        let mut purged = 0;
+        for user_dir in user_dirs.flatten() {
+            let username = user_dir.file_name().to_string_lossy().into_owned();
+            let Ok(files) = fs::read_dir(user_dir.path()) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let file_name = file.file_name().to_string_lossy().into_owned();
+                let db_name = file_name.strip_suffix(".json").or_else(|| file_name.strip_suffix(".json.gz"));
+                let Some(db_name) = db_name.filter(|name| !name.ends_with(".settings") && !name.contains(SNAPSHOT_MARKER)) else {
+                    continue;
+                };
+                match self.purge_database(&username, db_name) {
+                    Ok(count) => purged += count,
+                    Err(e) => eprintln!("⚠️  Could not purge expired records from {}/{}: {}", username, db_name, e),
+                }
+            }
+        }
+        purged
+    }
+
+    // Reads the file itself, not through the cache: the purge visits every database every interval and
+    // would otherwise keep all of them loaded (and count as reads in their cache metrics)
+    fn purge_database(&self, username: &str, db_name: &str) -> Result<usize, DbError> {
+        if !self.may_hold_expiry(username, db_name)? {
+            return Ok(0);
+        }
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let mut data = self.read_database(username, db_name)?;
+        let before = data.len();
+        Self::retain_live(&mut data);
+        let purged = before - data.len();
+        if purged > 0 {
+            self.write_database(username, db_name, &data)?;
+        }
+        Ok(purged)
+    }
+
+    // Whether the database file mentions `_expires_at`, checked on the raw text so databases without
+    // expiring records are never parsed by the purge; gzipped files can't be checked without inflating them
+    fn may_hold_expiry(&self, username: &str, db_name: &str) -> Result<bool, DbError> {
+        let filepath = self.get_stored_path(username, db_name)?;
+        if filepath.ends_with(".gz") {
+            return Ok(true);
+        }
+        let contents = fs::read(&filepath).map_err(|e| DbError::Internal(e.to_string()))?;
+        let needle = format!("\"{}\"", EXPIRES_AT_FIELD);
+        Ok(contents.windows(needle.len()).any(|window| window == needle.as_bytes()))
+    }
+
+    // TRUNCATE - Remove every record, leaving an empty database with its settings
+    // The count covers live records only, expired ones were already gone for readers
+    pub fn truncate_database(&self, username: &str, db_name: &str) -> Result<WriteOutcome, DbError> {
+        self.require_database(username, db_name)?;
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let removed = self.read_for_write(username, db_name)?.len();
+        self.write_database(username, db_name, &[])?;
+        Ok(WriteOutcome {
+            message: format!("Database truncated, {} records removed", removed),
+            count: removed,
+            affected: Vec::new(),
+        })
+    }
+
+    // COMPACT - Rewrite the file as compact JSON without expired records, gzipped if the database is
+    // Later writes go back to the format its settings ask for
+    // The new file replaces the old one by a rename, so readers see either the old or the new contents
+    // There are no indexes to rebuild yet: searches always scan the records (see SearchPlan::index_used)
+    pub fn compact_database(&self, username: &str, db_name: &str) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
+
+        let lock = self.write_lock(username, db_name)?;
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let stored_path = self.get_stored_path(username, db_name)?;
+        let size_before = fs::metadata(&stored_path).map(|meta| meta.len()).unwrap_or(0);
+
+        let mut data = self.read_database_cached(username, db_name)?;
+        let total_before = data.len();
+        Self::retain_live(&mut data);
+        let records = data.len();
+
+        let staged = BTreeMap::from([(db_name, data)]);
+        self.commit_staged(username, &staged, true)?;
+
+        let stored_path = self.get_stored_path(username, db_name)?;
+        let size_after = fs::metadata(&stored_path).map(|meta| meta.len()).unwrap_or(0);
+        Ok(serde_json::json!({
+            "database": db_name,
+            "records": records,
+            "expired_removed": total_before - records,
+            "size_before": size_before,
+            "size_after": size_after,
+            "bytes_saved": size_before.saturating_sub(size_after)
+        }))
+    }
+
+    // SNAPSHOT - Copy the database file as it is right now, holding the write lock so no write lands halfway
+    // The copy goes to a temporary file first, a snapshot that exists is always complete
+    pub fn snapshot_database(&self, username: &str, db_name: &str) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
+
+        let lock = self.write_lock(username, db_name)?;
+        let guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let stored_path = self.get_stored_path(username, db_name)?;
+        let size = fs::metadata(&stored_path)
+            .map_err(|e| DbError::Internal(format!("Could not read '{}': {}", stored_path, e)))?
+            .len();
+        let taken_at = Utc::now();
+        let extension = if stored_path.ends_with(".gz") { "json.gz" } else { "json" };
+        let file_name = format!(
+            "{}{}{}.{}",
+            Self::sanitize_component(db_name)?, SNAPSHOT_MARKER, taken_at.format(SNAPSHOT_TIMESTAMP_FORMAT), extension
+        );
+        let snapshot_path = self.user_file(username, &file_name)?;
+        if Path::new(&snapshot_path).exists() {
+            return Err(DbError::Conflict(format!("A snapshot of '{}' was already taken at this time, try again", db_name)));
+        }
+
+        let growth = self.check_quota(username, &[(snapshot_path.as_str(), size)])?;
+        let temp_path = format!("{}.tx", snapshot_path);
+        if let Err(e) = fs::copy(&stored_path, &temp_path).and_then(|_| fs::rename(&temp_path, &snapshot_path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(DbError::Internal(format!("Snapshot not taken: {}", e)));
+        }
+        self.record_growth(username, growth);
+        drop(guard);
+
+        let pruned = self.prune_snapshots(username, db_name)?;
+        Ok(serde_json::json!({
+            "database": db_name,
+            "snapshot": format!("{}/{}/{}", USERS_DIR, username, file_name),
+            "taken_at": taken_at.to_rfc3339(),
+            "size_bytes": size,
+            "pruned": pruned
+        }))
+    }
+
+    // Delete the oldest snapshots of a database beyond snapshot_retention, returns their file names
+    fn prune_snapshots(&self, username: &str, db_name: &str) -> Result<Vec<String>, DbError> {
+        let Some(keep) = self.config.snapshot_retention else {
+            return Ok(Vec::new());
+        };
+        let prefix = format!("{}{}", Self::sanitize_component(db_name)?, SNAPSHOT_MARKER);
+        let user_dir = self.user_dir(username)?;
+        let mut snapshots: Vec<String> = fs::read_dir(&user_dir)
+            .map_err(|e| DbError::Internal(e.to_string()))?
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| Self::is_snapshot_of(name, &prefix))
+            .collect();
+        snapshots.sort();
+
+        let excess = snapshots.len().saturating_sub(keep);
+        let mut pruned = Vec::with_capacity(excess);
+        for name in snapshots.drain(..excess) {
+            let path = user_dir.join(&name);
+            let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    self.record_growth(username, -(size as i64));
+                    pruned.push(name);
+                },
+                Err(e) => eprintln!("⚠️  Could not prune snapshot {}: {}", path.display(), e),
+            }
+        }
+        Ok(pruned)
+    }
+
+    // `<prefix><timestamp>.json` or `.json.gz`, anything else sharing the prefix is left alone
+    fn is_snapshot_of(file_name: &str, prefix: &str) -> bool {
+        file_name.strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(".json.gz").or_else(|| rest.strip_suffix(".json")))
+            .is_some_and(|stamp| !stamp.is_empty() && stamp.chars().all(|c| c.is_ascii_digit() || c == 'T' || c == 'Z'))
+    }
+
+    // A delete removing more than bulk_delete_fraction of the records needs confirmBulk
+    // Removing a single record is always allowed, even when it is the whole database
+    fn check_bulk_delete(&self, removed: usize, total: usize, confirm_bulk: bool) -> Result<(), DbError> {
+        let fraction = self.config.bulk_delete_fraction;
+        if confirm_bulk || removed <= 1 || removed as f64 <= total as f64 * fraction {
+            return Ok(());
+        }
+        Err(DbError::Conflict(format!(
+            "Delete would remove {} of {} records (more than {}%). Send confirmBulk: true to proceed",
+            removed, total, (fraction * 100.0).round()
+        )))
+    }
+
+    // An empty substring is contained in every string value, so a blank delete query would wipe the database
+    fn require_delete_query(query: &str) -> Result<(), DbError> {
+        if query.trim().is_empty() {
+            return Err(DbError::BadRequest("Delete query cannot be empty or whitespace".to_string()));
+        }
+        Ok(())
     }
 
     // Helper function to check if an item matches the query
@@ -258,29 +2089,25 @@ impl DatabaseManager {
         username: &str,
         db_name: &str,
         page: Option<usize>,
-        limit: Option<usize>
-    ) -> Result<Value, String> {
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
-        }
-        
-        let data = Self::read_database_cached(username, db_name)?;
-        let total_records = data.len();
+        limit: Option<usize>,
+        no_cache: bool
+    ) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
 
+        // Only the requested records are kept; large files that aren't cached are not even fully parsed
         match (page, limit) {
             // Case 1: Solo limit, sin page -> Devolver los primeros N registros
-            (None, Some(lim)) => {
-                let paginated_data: Vec<Value> = data
-                    .into_iter()
-                    .take(lim)
-                    .collect();
-                
+            (None, Some(requested)) => {
+                let (lim, limit_clamped) = self.clamp_limit(Some(requested));
+                let (paginated_data, total_records) = self.read_live_page(username, db_name, 0, lim, no_cache)?;
+                                
                 let returned_count = paginated_data.len();
                 
                 Ok(serde_json::json!({
                     "data": paginated_data,
                     "pagination": {
                         "limit": lim,
+                        "limit_clamped": limit_clamped,
                         "returned": returned_count,
                         "total_records": total_records,
                         "mode": "limit_only"
@@ -288,62 +2115,68 @@ impl DatabaseManager {
                 }))
             },
             // Case 2: limit y page -> Paginación normal
-            (Some(p), Some(lim)) => {
+            (Some(p), Some(requested)) => {
+                let (lim, limit_clamped) = self.clamp_limit(Some(requested));
                 let page_num = p.max(1); // Asegurar que page sea al menos 1
-                let offset = (page_num - 1) * lim;
-                
-                let paginated_data: Vec<Value> = data
-                    .into_iter()
-                    .skip(offset)
-                    .take(lim)
-                    .collect();
-                
+                let offset = (page_num - 1).saturating_mul(lim);
+
+                let (paginated_data, total_records) = self.read_live_page(username, db_name, offset, lim, no_cache)?;
+                                
                 let total_pages = if lim > 0 { 
                     (total_records as f64 / lim as f64).ceil() as usize 
                 } else { 
                     0 
                 };
                 let returned_count = paginated_data.len();
+                let (first_page, last_page, next_page, prev_page) = Self::page_links(page_num, total_pages);
                 
                 Ok(serde_json::json!({
                     "data": paginated_data,
                     "pagination": {
                         "page": page_num,
                         "limit": lim,
+                        "limit_clamped": limit_clamped,
                         "returned": returned_count,
                         "total_records": total_records,
                         "total_pages": total_pages,
                         "has_next": page_num < total_pages,
                         "has_prev": page_num > 1,
+                        "first_page": first_page,
+                        "last_page": last_page,
+                        "next_page": next_page,
+                        "prev_page": prev_page,
                         "mode": "paginated"
                     }
                 }))
             },
             // Case 3: page sin limit -> Error (necesita limit para paginar)
             (Some(_), None) => {
-                Err("Cannot use 'page' without 'limit'. Please provide both parameters.".to_string())
+                Err(DbError::BadRequest("Cannot use 'page' without 'limit'. Please provide both parameters.".to_string()))
             },
             // Case 4: Ni page ni limit -> Devolver primeros 10 registros (default)
             (None, None) => {
-                let default_limit = 10;
-                let paginated_data: Vec<Value> = data
-                    .into_iter()
-                    .take(default_limit)
-                    .collect();
-                
+                let default_limit = self.config.page_limits.default;
+                let (paginated_data, total_records) = self.read_live_page(username, db_name, 0, default_limit, no_cache)?;
+                                
                 let returned_count = paginated_data.len();
                 let total_pages = (total_records as f64 / default_limit as f64).ceil() as usize;
+                let (first_page, last_page, next_page, prev_page) = Self::page_links(1, total_pages);
                 
                 Ok(serde_json::json!({
                     "data": paginated_data,
                     "pagination": {
                         "page": 1,
                         "limit": default_limit,
+                        "limit_clamped": false,
                         "returned": returned_count,
                         "total_records": total_records,
                         "total_pages": total_pages,
                         "has_next": total_pages > 1,
                         "has_prev": false,
+                        "first_page": first_page,
+                        "last_page": last_page,
+                        "next_page": next_page,
+                        "prev_page": prev_page,
                         "mode": "default"
                     }
                 }))
@@ -356,97 +2189,550 @@ impl DatabaseManager {
         &self,
         username: &str,
         db_name: &str,
-        page: Option<usize>,
-        limit: Option<usize>,
-        sort_by: Option<&str>,
-        sort_order: Option<&str>,
-        filters: Option<&Value>
-    ) -> Result<Value, String> {
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
+        options: &ListOptions
+    ) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
+
+        // Without sorting or filtering the page is known without loading the records, see stream_live_page
+        let plain = options.sort_by.is_none() && options.filters.is_none() && options.search.is_none_or(str::is_empty)
+            && options.created_after.is_none() && options.created_before.is_none();
+        if plain {
+            let (page_num, page_size, _) = self.page_bounds(options);
+            let offset = (page_num - 1).saturating_mul(page_size);
+            if let Some(page) = self.stream_live_page(username, db_name, offset, page_size, options.no_cache)? {
+                return Ok(serde_json::json!({
+                    "data": page.records,
+                    "pagination": self.pagination_block(page.total, page.kept, options),
+                    "sorting": self.sort_records(&mut [], options)?
+                }));
+            }
         }
 
-        let mut data = Self::read_database_cached(username, db_name)?;
+        let mut data = self.read_database_fresh_or_cached(username, db_name, options.no_cache)?;
         let total_records = data.len();
 
-        // Apply filters if provided
-        if let Some(filter_obj) = filters {
-            if let Value::Object(filters_map) = filter_obj {
-                data.retain(|item| self.matches_filters(item, filters_map));
-            }
+        self.apply_list_filters(&mut data, options)?;
+
+        let sorting = self.sort_records(&mut data, options)?;
+        let (paginated_data, pagination) = self.page_of(&data, total_records, options);
+
+        Ok(serde_json::json!({
+            "data": paginated_data,
+            "pagination": pagination,
+            "sorting": sorting
+        }))
+    }
+
+    // Sort by the sortBy/sortOrder of `options` and describe the sort; shared by list and get
+    // A sort field that no record has is flagged instead of failing
+    pub fn sort_records(&self, data: &mut [Value], options: &ListOptions) -> Result<Value, DbError> {
+        let sort_keys = Self::sort_keys(options)?;
+        let sort_field_missing = sort_keys.iter()
+            .any(|(field, _)| !data.iter().any(|item| self.get_field_value(item, field).is_some()));
+        if !sort_keys.is_empty() {
+            data.sort_by(|a, b| self.compare_values(a, b, &sort_keys));
         }
+        let order = if sort_keys.is_empty() {
+            "asc".to_string()
+        } else {
+            sort_keys.iter().map(|(_, order)| *order).collect::<Vec<_>>().join(",")
+        };
 
-        let filtered_count = data.len();
+        Ok(serde_json::json!({
+            "field": options.sort_by,
+            "order": order,
+            "sort_field_missing": sort_field_missing
+        }))
+    }
+
+    // The requested page of filtered (and sorted) records with its pagination block; shared by list and get
+    // `total_records` is the count before filtering, `data.len()` after
+    pub fn page_of<'d>(&self, data: &'d [Value], total_records: usize, options: &ListOptions) -> (&'d [Value], Value) {
+        let (page_num, page_size, _) = self.page_bounds(options);
+        (Self::paginate(data, page_num, page_size), self.pagination_block(total_records, data.len(), options))
+    }
 
-        // Apply sorting if provided
-        if let Some(field) = sort_by {
-            let order = sort_order.unwrap_or("asc");
-            data.sort_by(|a, b| self.compare_values(a, b, field, order));
+    // Requested page (from 1), page size and whether the size was clamped
+    fn page_bounds(&self, options: &ListOptions) -> (usize, usize, bool) {
+        let (page_size, limit_clamped) = self.clamp_limit(options.limit);
+        (options.page.unwrap_or(1).max(1), page_size, limit_clamped)
+    }
+
+    // `filtered_count` records, out of `total_records` before filtering, split into pages
+    fn pagination_block(&self, total_records: usize, filtered_count: usize, options: &ListOptions) -> Value {
+        let (page_num, page_size, limit_clamped) = self.page_bounds(options);
+        let total_pages = Self::total_pages(filtered_count, page_size);
+        let (first_page, last_page, next_page, prev_page) = Self::page_links(page_num, total_pages);
+
+        serde_json::json!({
+            "page": page_num,
+            "limit": page_size,
+            "limit_clamped": limit_clamped,
+            "total_records": total_records,
+            "filtered_records": filtered_count,
+            "total_pages": total_pages,
+            "has_next": page_num < total_pages,
+            "has_prev": page_num > 1,
+            "first_page": first_page,
+            "last_page": last_page,
+            "next_page": next_page,
+            "prev_page": prev_page
+        })
+    }
+
+    // Pair each sortBy field with its order, validating orders and defaulting missing ones to ascending
+    fn sort_keys<'a>(options: &ListOptions<'a>) -> Result<Vec<(&'a str, &'a str)>, DbError> {
+        let fields: Vec<&str> = options.sort_by
+            .map(|fields| fields.split(',').map(str::trim).filter(|f| !f.is_empty()).collect())
+            .unwrap_or_default();
+        let orders: Vec<&str> = options.sort_order
+            .map(|orders| orders.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+
+        if orders.len() > fields.len().max(1) {
+            return Err(DbError::BadRequest(format!(
+                "sortOrder has {} values but sortBy has {} fields", orders.len(), fields.len()
+            )));
+        }
+        if let Some(bad) = orders.iter().find(|order| !matches!(**order, "asc" | "desc")) {
+            return Err(DbError::BadRequest(format!(
+                "Invalid sortOrder '{}'. Use 'asc' or 'desc'", bad
+            )));
         }
 
-        // Apply pagination
-        let page_num = page.unwrap_or(1);
-        let page_size = limit.unwrap_or(10);
-        let offset = (page_num.saturating_sub(1)) * page_size;
-        
-        let paginated_data: Vec<Value> = data
-            .into_iter()
-            .skip(offset)
-            .take(page_size)
-            .collect();
+        Ok(fields.into_iter()
+            .enumerate()
+            .map(|(i, field)| (field, orders.get(i).copied().unwrap_or("asc")))
+            .collect())
+    }
+
+    // Resolve the page size from the configured default and max, reporting whether it was clamped
+    pub fn clamp_limit(&self, limit: Option<usize>) -> (usize, bool) {
+        let limits = self.config.page_limits;
+        match limit {
+            Some(lim) if lim > limits.max => (limits.max, true),
+            Some(lim) => (lim, false),
+            None => (limits.default, false),
+        }
+    }
+
+    // Slice one page out of a result set (pages start at 1)
+    pub fn paginate(data: &[Value], page: usize, page_size: usize) -> &[Value] {
+        let offset = page.saturating_sub(1).saturating_mul(page_size).min(data.len());
+        let end = offset.saturating_add(page_size).min(data.len());
+        &data[offset..end]
+    }
 
-        let total_pages = (filtered_count as f64 / page_size as f64).ceil() as usize;
+    // Number of pages needed to show `count` records
+    pub fn total_pages(count: usize, page_size: usize) -> usize {
+        (count as f64 / page_size as f64).ceil() as usize
+    }
+
+    // Page numbers for pagination controls: (first, last, next, prev)
+    // next is None on the last page and prev is None on the first; an empty result still has page 1
+    pub fn page_links(page: usize, total_pages: usize) -> (usize, usize, Option<usize>, Option<usize>) {
+        let last_page = total_pages.max(1);
+        let next_page = (page < total_pages).then(|| page + 1);
+        let prev_page = (page > 1).then(|| (page - 1).min(last_page));
+        (1, last_page, next_page, prev_page)
+    }
+
+    // LIST (cursor) - Keyset pagination returning records strictly after `cursor` in the sort order
+    // Records are ordered by the sort field and then by id, and `next_cursor` carries both, so records
+    // sharing a sort value are neither skipped nor repeated across pages
+    // An empty cursor starts from the beginning; `next_cursor` is null once the end is reached
+    pub fn list_records_cursor(
+        &self,
+        username: &str,
+        db_name: &str,
+        options: &ListOptions,
+        cursor: &str
+    ) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
+
+        let mut data = self.read_database_fresh_or_cached(username, db_name, options.no_cache)?;
+        let total_records = data.len();
+
+        self.apply_list_filters(&mut data, options)?;
+        let filtered_count = data.len();
+
+        // Keyset pagination walks a single field, the id breaks ties
+        let sort_keys = Self::sort_keys(options)?;
+        let id_field = self.read_settings(username, db_name)?.id_field;
+        let (field, order) = match sort_keys.as_slice() {
+            [] => (id_field.as_str(), "asc"),
+            [key] => *key,
+            _ => return Err(DbError::BadRequest("Cursor pagination supports a single sortBy field".to_string())),
+        };
+        let descending = order == "desc";
+        let keys = [(field, order), (id_field.as_str(), order)];
+        let position = Self::decode_cursor(cursor).map(|(value, id)| {
+            let mut position = serde_json::Map::new();
+            position.insert(id_field.clone(), id);
+            position.insert(field.to_string(), value);
+            Value::Object(position)
+        });
+
+        // Records without a usable cursor value or id can't be positioned and are skipped
+        data.retain(|item| {
+            let positioned = [field, id_field.as_str()].iter()
+                .all(|key| self.get_field_value(item, key).and_then(Self::cursor_value).is_some());
+            positioned && (cursor.is_empty() || match &position {
+                Some(position) => self.compare_values(item, position, &keys) == std::cmp::Ordering::Greater,
+                // A bare value, as sent before cursors carried the id: everything strictly after it
+                None => self.is_past_cursor(item, field, cursor, descending),
+            })
+        });
+        data.sort_by(|a, b| self.compare_values(a, b, &keys));
+
+        let (page_size, limit_clamped) = self.clamp_limit(options.limit);
+        let has_next = data.len() > page_size;
+        data.truncate(page_size);
+
+        let next_cursor = if has_next {
+            data.last().and_then(|item| Self::encode_cursor(
+                self.get_field_value(item, field)?,
+                self.get_field_value(item, &id_field)?,
+            ))
+        } else {
+            None
+        };
 
         Ok(serde_json::json!({
-            "data": paginated_data,
+            "data": data,
             "pagination": {
-                "page": page_num,
+                "mode": "cursor",
+                "cursor": cursor,
+                "next_cursor": next_cursor,
                 "limit": page_size,
+                "limit_clamped": limit_clamped,
+                "returned": data.len(),
                 "total_records": total_records,
                 "filtered_records": filtered_count,
-                "total_pages": total_pages,
-                "has_next": page_num < total_pages,
-                "has_prev": page_num > 1
+                "has_next": has_next
             },
             "sorting": {
-                "field": sort_by,
-                "order": sort_order.unwrap_or("asc")
+                "field": field,
+                "order": order
             }
         }))
     }
 
-    // Check if item matches all filters
-    fn matches_filters(&self, item: &Value, filters: &serde_json::Map<String, Value>) -> bool {
+    // String form of a field value usable as a cursor (strings and numbers only)
+    fn cursor_value(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    // `next_cursor`: the sort value and id of the last record as a percent-encoded JSON pair, safe in a header
+    fn encode_cursor(value: &Value, id: &Value) -> Option<String> {
+        Self::cursor_value(value)?;
+        Self::cursor_value(id)?;
+        Some(urlencoding::encode(&serde_json::json!([value, id]).to_string()).into_owned())
+    }
+
+    // The (sort value, id) pair of a cursor made by encode_cursor, None for a bare sort value
+    fn decode_cursor(cursor: &str) -> Option<(Value, Value)> {
+        let decoded = urlencoding::decode(cursor).ok()?;
+        match serde_json::from_str(&decoded).ok()? {
+            Value::Array(pair) => match <[Value; 2]>::try_from(pair).ok()? {
+                [value, id] if Self::cursor_value(&value).is_some() && Self::cursor_value(&id).is_some() => Some((value, id)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Check if the item's field value comes strictly after a bare cursor value in the sort order
+    fn is_past_cursor(&self, item: &Value, field: &str, cursor: &str, descending: bool) -> bool {
+        let ordering = match self.get_field_value(item, field) {
+            Some(Value::String(s)) => s.as_str().cmp(cursor),
+            Some(Value::Number(n)) => match (n.as_f64(), cursor.parse::<f64>().ok()) {
+                (Some(value), Some(cursor_num)) => value.partial_cmp(&cursor_num).unwrap_or(std::cmp::Ordering::Equal),
+                _ => return false,
+            },
+            _ => return false,
+        };
+        if descending {
+            ordering == std::cmp::Ordering::Less
+        } else {
+            ordering == std::cmp::Ordering::Greater
+        }
+    }
+
+    // Apply structured filters and free-text search shared by both list modes
+    fn apply_list_filters(&self, data: &mut Vec<Value>, options: &ListOptions) -> Result<(), DbError> {
+        Self::retain_live(data);
+
+        // Apply filters if provided, their `$regex` patterns are compiled once up front
+        if let Some(Value::Object(filters_map)) = options.filters {
+            let mut regexes = FilterRegexes::new();
+            Self::compile_filter_regexes(filters_map, &mut regexes)?;
+            data.retain(|item| self.matches_filters(item, filters_map, &regexes));
+        }
+
+        // Apply free-text search on top of the filters
+        if let Some(text) = options.search.filter(|t| !t.is_empty()) {
+            let needle = text.to_lowercase();
+            data.retain(|item| self.matches_text_search(item, &needle, &options.search_fields));
+        }
+
+        if options.created_after.is_some() || options.created_before.is_some() {
+            data.retain(|item| {
+                let created_at = item.get("_created_at")
+                    .and_then(Value::as_str)
+                    .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok());
+                created_at.is_some_and(|created_at| {
+                    options.created_after.is_none_or(|after| created_at >= after)
+                        && options.created_before.is_none_or(|before| created_at < before)
+                })
+            });
+        }
+        Ok(())
+    }
+
+    // Compile every `$regex` pattern in the filters, `$or`/`$and` branches included
+    // An invalid pattern fails the whole request instead of silently matching nothing
+    fn compile_filter_regexes<'f>(filters: &'f serde_json::Map<String, Value>, regexes: &mut FilterRegexes<'f>) -> Result<(), DbError> {
         for (key, filter_value) in filters {
-            if let Value::Object(obj) = item {
-                match obj.get(key) {
-                    Some(item_value) => {
-                        if !self.value_matches_filter(item_value, filter_value) {
-                            return false;
+            match (key.as_str(), filter_value) {
+                ("$or" | "$and", Value::Array(branches)) => {
+                    for branch in branches {
+                        if let Value::Object(branch) = branch {
+                            Self::compile_filter_regexes(branch, regexes)?;
                         }
                     }
-                    None => return false,
                 }
-            } else {
+                (_, Value::Object(operator)) if operator.len() == 1 => {
+                    if let Some(Value::String(pattern)) = operator.get("$regex")
+                        && !regexes.contains_key(pattern.as_str()) {
+                        let regex = compile_regex(pattern)
+                            .map_err(|e| DbError::BadRequest(format!("Filter on '{}': {}", key, e)))?;
+                        regexes.insert(pattern, regex);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Nested objects become dotted keys (address.city) and array items their index (tags.0)
+    // Empty objects and arrays are kept as they are, so the key doesn't disappear
+    pub fn flatten_value(value: &Value) -> Value {
+        match value {
+            Value::Object(obj) => {
+                let mut flat = serde_json::Map::new();
+                for (key, nested) in obj {
+                    Self::flatten_into(key.clone(), nested, &mut flat);
+                }
+                Value::Object(flat)
+            }
+            other => other.clone(),
+        }
+    }
+
+    fn flatten_into(prefix: String, value: &Value, flat: &mut serde_json::Map<String, Value>) {
+        match value {
+            Value::Object(obj) if !obj.is_empty() => {
+                for (key, nested) in obj {
+                    Self::flatten_into(format!("{}.{}", prefix, key), nested, flat);
+                }
+            }
+            Value::Array(items) if !items.is_empty() => {
+                for (index, nested) in items.iter().enumerate() {
+                    Self::flatten_into(format!("{}.{}", prefix, index), nested, flat);
+                }
+            }
+            other => {
+                flat.insert(prefix, other.clone());
+            }
+        }
+    }
+
+    // Flatten the top-level scalar fields of each record into CSV (header = union of keys)
+    pub fn records_to_csv(records: &[Value]) -> Result<String, DbError> {
+        let mut columns: Vec<&str> = Vec::new();
+        for record in records {
+            if let Value::Object(obj) = record {
+                for (key, value) in obj {
+                    if !value.is_array() && !value.is_object() && !columns.contains(&key.as_str()) {
+                        columns.push(key);
+                    }
+                }
+            }
+        }
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(&columns).map_err(|e| DbError::Internal(e.to_string()))?;
+        for record in records {
+            let row = columns.iter().map(|column| match record.get(column) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Number(n)) => n.to_string(),
+                Some(Value::Bool(b)) => b.to_string(),
+                // Missing fields, nulls and nested values become empty cells
+                _ => String::new(),
+            });
+            writer.write_record(row).map_err(|e| DbError::Internal(e.to_string()))?;
+        }
+
+        let bytes = writer.into_inner().map_err(|e| DbError::Internal(e.to_string()))?;
+        String::from_utf8(bytes).map_err(|e| DbError::Internal(e.to_string()))
+    }
+
+    // Convert CSV rows into JSON objects, empty cells are omitted
+    // Cells that round-trip exactly as a number or boolean keep that type, everything else stays a string
+    pub fn csv_to_records(input: &[u8]) -> Result<Vec<Value>, DbError> {
+        let mut reader = csv::Reader::from_reader(input);
+        let headers = reader.headers().map_err(|e| DbError::BadRequest(format!("Invalid CSV header: {}", e)))?.clone();
+
+        let mut records = Vec::new();
+        for (index, row) in reader.records().enumerate() {
+            let row = row.map_err(|e| DbError::BadRequest(format!("Invalid CSV row {}: {}", index + 1, e)))?;
+            let mut obj = serde_json::Map::new();
+            for (column, cell) in headers.iter().zip(row.iter()) {
+                if !cell.is_empty() {
+                    obj.insert(column.to_string(), Self::csv_cell_value(cell));
+                }
+            }
+            records.push(Value::Object(obj));
+        }
+        Ok(records)
+    }
+
+    // Convert an application/x-www-form-urlencoded body into one flat JSON object
+    // Values are typed like CSV cells, a key sent several times collects its values into an array
+    pub fn form_to_record(input: &[u8]) -> Value {
+        let mut obj = serde_json::Map::new();
+        for (key, value) in url::form_urlencoded::parse(input) {
+            let value = Self::csv_cell_value(&value);
+            match obj.get_mut(key.as_ref()) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    obj.insert(key.into_owned(), value);
+                }
+            }
+        }
+        Value::Object(obj)
+    }
+
+    fn csv_cell_value(cell: &str) -> Value {
+        match cell {
+            "true" => return Value::Bool(true),
+            "false" => return Value::Bool(false),
+            _ => {}
+        }
+        match serde_json::from_str::<Number>(cell) {
+            Ok(n) if n.to_string() == cell => Value::Number(n),
+            _ => Value::String(cell.to_string()),
+        }
+    }
+
+    // Check if item matches all filters (implicit AND, `$or`/`$and` take arrays of filter objects)
+    fn matches_filters(&self, item: &Value, filters: &serde_json::Map<String, Value>, regexes: &FilterRegexes) -> bool {
+        for (key, filter_value) in filters {
+            let matched = match key.as_str() {
+                "$or" => self.matches_filter_branches(item, filter_value, false, regexes),
+                "$and" => self.matches_filter_branches(item, filter_value, true, regexes),
+                _ => self.value_matches_filter(self.get_field_value(item, key), filter_value, regexes),
+            };
+            if !matched {
                 return false;
             }
         }
         true
     }
 
-    // Compare filter value with item value (supports exact match and arrays)
-    fn value_matches_filter(&self, item_value: &Value, filter_value: &Value) -> bool {
-        match filter_value {
-            Value::Array(arr) => {
-                // If filter is array, item value must be one of the array values (OR logic)
-                arr.iter().any(|fv| item_value == fv)
+    // Check if any of the given fields (or any value when no fields are given) contains the lowercase needle
+    fn matches_text_search(&self, item: &Value, needle: &str, fields: &[&str]) -> bool {
+        if fields.is_empty() {
+            return self.value_contains_text(item, needle);
+        }
+        fields.iter().any(|field| {
+            self.get_field_value(item, field)
+                .is_some_and(|value| self.value_contains_text(value, needle))
+        })
+    }
+
+    // Case-insensitive version of search_in_json_value (needle must already be lowercase)
+    fn value_contains_text(&self, value: &Value, needle: &str) -> bool {
+        match value {
+            Value::String(s) => s.to_lowercase().contains(needle),
+            Value::Number(n) => n.to_string().contains(needle),
+            Value::Bool(b) => b.to_string().contains(needle),
+            Value::Array(arr) => arr.iter().any(|v| self.value_contains_text(v, needle)),
+            Value::Object(obj) => obj.values().any(|v| self.value_contains_text(v, needle)),
+            Value::Null => false,
+        }
+    }
+
+    // Evaluate the branches of a `$or` (any) or `$and` (all) compound filter
+    fn matches_filter_branches(&self, item: &Value, branches: &Value, require_all: bool, regexes: &FilterRegexes) -> bool {
+        let Value::Array(branches) = branches else {
+            return false;
+        };
+        let mut results = branches.iter().map(|branch| match branch {
+            Value::Object(filters) => self.matches_filters(item, filters, regexes),
+            _ => false,
+        });
+        if require_all {
+            results.all(|matched| matched)
+        } else {
+            results.any(|matched| matched)
+        }
+    }
+
+    // Compare filter value with item value: exact match, or an operator object
+    // (`$exists`, `$in`, `$nin`, `$contains`, `$regex`)
+    // A missing field and a field set to null are different: `null` only matches the latter,
+    // `{"$exists": false}` only the former
+    fn value_matches_filter(&self, item_value: Option<&Value>, filter_value: &Value, regexes: &FilterRegexes) -> bool {
+        if let Value::Object(operator) = filter_value
+            && operator.len() == 1
+            && let Some((name, operand)) = operator.iter().next() {
+            match name.as_str() {
+                // Presence only, whatever the value (null included); a non-boolean never matches
+                "$exists" => return operand.as_bool().is_some_and(|wanted| item_value.is_some() == wanted),
+                // Operands must be arrays, anything else never matches
+                "$in" => return operand.as_array().is_some_and(|set| item_value.is_some_and(|value| set.contains(value))),
+                // A missing field is not in the set either
+                "$nin" => return operand.as_array().is_some_and(|set| item_value.is_none_or(|value| !set.contains(value))),
+                // Text operators only match string values, case-sensitively; a non-string operand never matches
+                "$contains" => return match (item_value, operand) {
+                    (Some(Value::String(text)), Value::String(needle)) => text.contains(needle.as_str()),
+                    _ => false,
+                },
+                "$regex" => return match (item_value, operand) {
+                    (Some(Value::String(text)), Value::String(pattern)) => regexes.get(pattern.as_str()).is_some_and(|regex| regex.is_match(text)),
+                    _ => false,
+                },
+                _ => {}
             }
+        }
+        let Some(item_value) = item_value else {
+            return false;
+        };
+        match filter_value {
+            // A bare array is shorthand for `$in`
+            Value::Array(set) => set.contains(item_value),
             _ => item_value == filter_value
         }
     }
 
+    // Compare two items by each (field, order) key in turn, moving to the next key on a tie
+    fn compare_values(&self, a: &Value, b: &Value, keys: &[(&str, &str)]) -> std::cmp::Ordering {
+        keys.iter()
+            .map(|(field, order)| self.compare_field(a, b, field, order))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }
+
     // Compare two items by a specific field for sorting
-    fn compare_values(&self, a: &Value, b: &Value, field: &str, order: &str) -> std::cmp::Ordering {
+    fn compare_field(&self, a: &Value, b: &Value, field: &str, order: &str) -> std::cmp::Ordering {
         let a_val = self.get_field_value(a, field);
         let b_val = self.get_field_value(b, field);
 
@@ -479,31 +2765,1807 @@ impl DatabaseManager {
         }
     }
 
-    // Get database statistics with read time measurement
-    // Get database statistics with read time measurement
-    pub fn get_stats(&self, username: &str, db_name: &str) -> Result<Value, String> {
-        if !Self::database_exists(username, db_name) {
-            return Err("Database does not exist".to_string());
+    // EXISTS - Count live records matching the target without copying them, stopping after `limit` matches
+    pub fn count_matches(&self, username: &str, db_name: &str, target: &UpdateTarget, limit: usize, no_cache: bool) -> Result<usize, DbError> {
+        self.require_database(username, db_name)?;
+        let data = self.read_database_fresh_or_cached(username, db_name, no_cache)?;
+        let id_field = self.read_settings(username, db_name)?.id_field;
+        let now = Utc::now();
+
+        Ok(data.iter()
+            .filter(|item| !Self::is_expired(item, now))
+            .filter(|item| match target {
+                UpdateTarget::Id(id) => Self::id_matches(item, &id_field, id),
+                UpdateTarget::Query(query) => self.item_matches_query(item, query),
+            })
+            .take(limit)
+            .count())
+    }
+
+    // METADATA - Id, timestamps and a content fingerprint of one live record, without copying its body
+    pub fn record_metadata(&self, username: &str, db_name: &str, id: &str, no_cache: bool) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
+        let data = self.read_database_fresh_or_cached(username, db_name, no_cache)?;
+        let id_field = self.read_settings(username, db_name)?.id_field;
+        let now = Utc::now();
+
+        let record = data.iter()
+            .filter(|item| !Self::is_expired(item, now))
+            .find(|item| Self::id_matches(item, &id_field, id))
+            .ok_or_else(|| DbError::NotFound(format!("No record with {} '{}'", id_field, id)))?;
+        let mut metadata = serde_json::Map::new();
+        for field in [id_field.as_str(), "_created_at", "_updated_at"] {
+            metadata.insert(field.to_string(), record.get(field).cloned().unwrap_or(Value::Null));
+        }
+        metadata.insert("_version".to_string(), Value::String(Self::record_version(record)?));
+        Ok(Value::Object(metadata))
+    }
+
+    // SAMPLE - `size` random live records by reservoir sampling in a single pass. A cached database is sampled
+    // in place and any other one streamed from disk, so besides the cache only the sample is held, never the data
+    // A seed makes the pick reproducible for as long as the database doesn't change; returns the sample and the live count
+    pub fn sample_records(&self, username: &str, db_name: &str, size: usize, seed: Option<u64>, no_cache: bool) -> Result<(Vec<Value>, usize), DbError> {
+        self.require_database(username, db_name)?;
+        let cache_key = self.get_db_path(username, db_name)?;
+        let now = Utc::now();
+
+        if !no_cache {
+            let cache = DB_CACHE.lock().unwrap();
+            if let Some((data, timestamp)) = cache.get(&cache_key)
+                && timestamp.elapsed() < self.config.cache_ttl {
+                Self::count_cache_read(&cache_key, true);
+                let mut reservoir = Reservoir::new(size, seed);
+                for item in data.iter().filter(|item| !Self::is_expired(item, now)) {
+                    reservoir.offer(item);
+                }
+                return Ok((reservoir.sample.into_iter().cloned().collect(), reservoir.seen));
+            }
+        }
+
+        // Like a streamed page, this read doesn't fill the cache
+        Self::count_cache_read(&cache_key, false);
+        let filepath = self.get_stored_path(username, db_name)?;
+        let mut reservoir = Reservoir::new(size, seed);
+        for_each_json_record(&filepath, |item| {
+            if !Self::is_expired(&item, now) {
+                reservoir.offer(item);
+            }
+        }).map_err(DbError::Internal)?;
+        Ok((reservoir.sample, reservoir.seen))
+    }
+
+    // Opaque fingerprint of a record's content: it changes whenever any field does, and stays the same
+    // across server upgrades. The JSON is hashed as it is serialized, so no copy of the record is built
+    fn record_version(record: &Value) -> Result<String, DbError> {
+        let mut hasher = StableHasher::new();
+        serde_json::to_writer(&mut hasher, record).map_err(|e| DbError::Internal(e.to_string()))?;
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    // DISTINCT - Unique values of a top-level field across the live records
+    pub fn distinct_records(&self, username: &str, db_name: &str, field: &str, limit: Option<usize>) -> Result<(Vec<Value>, usize), DbError> {
+        self.require_database(username, db_name)?;
+        let mut data = self.read_database_cached(username, db_name)?;
+        Self::retain_live(&mut data);
+        Ok(Self::distinct_values(&data, field, limit))
+    }
+
+    // Values in first-seen order, deduplicated on their JSON text so 1 and "1" stay apart;
+    // records without the field are skipped, an explicit null counts as a value
+    // At most `limit` values are returned, along with the number of distinct values in total
+    pub fn distinct_values(data: &[Value], field: &str, limit: Option<usize>) -> (Vec<Value>, usize) {
+        let mut seen = HashSet::new();
+        let mut values = Vec::new();
+        for value in data.iter().filter_map(|item| item.get(field)) {
+            if seen.insert(value.to_string()) && limit.is_none_or(|limit| values.len() < limit) {
+                values.push(value.clone());
+            }
         }
+        (values, seen.len())
+    }
+
+    // Get database statistics with read time measurement
+    pub fn get_stats(&self, username: &str, db_name: &str) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
 
         // Measure file read time (with cache)
         let read_start = Instant::now();
-        let data = Self::read_database_cached(username, db_name)?;
+        let mut data = self.read_database_cached(username, db_name)?;
         let read_time_ms = read_start.elapsed().as_millis();
+        // Expired records wait in the file for the purge, but readers never see them
+        Self::retain_live(&mut data);
 
-        let filepath = Self::get_db_path(username, db_name);
+        let filepath = self.get_db_path(username, db_name)?;
+        let stored_path = self.get_stored_path(username, db_name)?;
+        let size_bytes = fs::metadata(&stored_path)
+            .map(|m| m.len())
+            .unwrap_or(0);
+        // Gzipped databases also report the size of the JSON they hold
+        let compressed = stored_path.ends_with(".gz");
+        let uncompressed_bytes = if compressed {
+            gzip_uncompressed_size(&stored_path).map_err(DbError::Internal)?
+        } else {
+            size_bytes
+        };
         let stats = serde_json::json!({
             "database": db_name,
             "username": username,
             "total_records": data.len(),
-            "size_bytes": fs::metadata(&filepath)
-                .map(|m| m.len())
-                .unwrap_or(0),
+            "size_bytes": size_bytes,
+            "compressed": compressed,
+            "uncompressed_bytes": uncompressed_bytes,
             "read_time_ms": read_time_ms,
-            "cached": true,
+            "cache": self.cache_metrics(&filepath),
+            "schema_summary": Self::schema_summary(&data),
             "timestamp": Utc::now().to_rfc3339()
         });
 
         Ok(stats)
     }
-}
\ No newline at end of file
+
+    // Record count and file size from get_stats, without the schema scan so listing many databases stays cheap
+    pub fn database_summary(&self, username: &str, db_name: &str) -> Result<Value, DbError> {
+        self.require_database(username, db_name)?;
+        let mut data = self.read_database_cached(username, db_name)?;
+        Self::retain_live(&mut data);
+        let filepath = self.get_stored_path(username, db_name)?;
+        Ok(serde_json::json!({
+            "total_records": data.len(),
+            "size_bytes": fs::metadata(&filepath)
+                .map(|m| m.len())
+                .unwrap_or(0)
+        }))
+    }
+
+    pub fn get_audit_path(&self, username: &str) -> Result<String, DbError> {
+        self.user_file(username, AUDIT_LOG_FILE)
+    }
+
+    // Record a completed write in the owner's audit log. Best effort: the write already
+    // happened, so a logging failure is only reported on stderr
+    pub fn audit(&self, owner: &str, entry: AuditEntry) {
+        if let Err(e) = self.append_audit(owner, &entry) {
+            eprintln!("⚠️  Could not write audit entry for {}/{} ({}): {}", owner, entry.database, entry.operation, e);
+        }
+    }
+
+    fn append_audit(&self, owner: &str, entry: &AuditEntry) -> Result<(), DbError> {
+        let path = self.get_audit_path(owner)?;
+        let mut line = serde_json::to_string(entry).map_err(|e| DbError::Internal(e.to_string()))?;
+        line.push('\n');
+
+        let _guard = AUDIT_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| DbError::Internal(e.to_string()))?;
+        file.write_all(line.as_bytes()).map_err(|e| DbError::Internal(e.to_string()))
+    }
+
+    // AUDIT - One page of the user's audit log, oldest entries first
+    pub fn read_audit(&self, username: &str, page: usize, limit: Option<usize>) -> Result<Value, DbError> {
+        let path = self.get_audit_path(username)?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(DbError::Internal(e.to_string())),
+        };
+        // A line cut short by a crash is skipped rather than failing the whole read
+        let entries: Vec<Value> = contents.lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        let (limit, limit_clamped) = self.clamp_limit(limit);
+        let total_pages = entries.len().div_ceil(limit.max(1));
+        Ok(serde_json::json!({
+            "entries": Self::paginate(&entries, page, limit),
+            "pagination": {
+                "page": page,
+                "limit": limit,
+                "limit_clamped": limit_clamped,
+                "total_pages": total_pages,
+                "total_entries": entries.len(),
+                "has_next": page < total_pages,
+            }
+        }))
+    }
+
+    // USAGE - Disk size and record count of each listed database plus totals
+    // A database listed for the user whose file is gone counts as 0 bytes and is flagged instead of failing the report
+    pub fn usage(&self, username: &str, db_names: &[&str]) -> Result<Value, DbError> {
+        let mut databases = Vec::with_capacity(db_names.len());
+        let (mut total_bytes, mut total_records, mut missing) = (0u64, 0usize, 0usize);
+
+        for db_name in db_names {
+            let filepath = self.get_stored_path(username, db_name)?;
+            let Ok(metadata) = fs::metadata(&filepath) else {
+                missing += 1;
+                databases.push(serde_json::json!({
+                    "database": db_name,
+                    "size_bytes": 0,
+                    "records": 0,
+                    "missing": true,
+                    "note": "Database is registered for this user but its file does not exist"
+                }));
+                continue;
+            };
+            // Settings live next to the data and count towards the footprint
+            let settings_bytes = fs::metadata(self.get_settings_path(username, db_name)?)
+                .map(|m| m.len())
+                .unwrap_or(0);
+            let size_bytes = metadata.len() + settings_bytes;
+            total_bytes += size_bytes;
+
+            let mut entry = serde_json::json!({
+                "database": db_name,
+                "size_bytes": size_bytes,
+                "missing": false
+            });
+            match self.read_database_cached(username, db_name) {
+                Ok(mut data) => {
+                    Self::retain_live(&mut data);
+                    total_records += data.len();
+                    entry["records"] = Value::from(data.len());
+                }
+                Err(e) => {
+                    entry["records"] = Value::Null;
+                    entry["note"] = Value::String(format!("Records could not be counted: {}", e));
+                }
+            }
+            databases.push(entry);
+        }
+
+        Ok(serde_json::json!({
+            "username": username,
+            "databases": databases,
+            "totals": {
+                "databases": db_names.len(),
+                "size_bytes": total_bytes,
+                "records": total_records,
+                "missing_files": missing
+            }
+        }))
+    }
+
+    // Top-level fields of the records with how many records have each one and the types of their values
+    fn schema_summary(data: &[Value]) -> Value {
+        let sample = &data[..data.len().min(SCHEMA_SAMPLE_SIZE)];
+        let mut fields: BTreeMap<&str, (usize, BTreeMap<&str, usize>)> = BTreeMap::new();
+        let mut non_object_records = 0;
+
+        for record in sample {
+            let Some(obj) = record.as_object() else {
+                non_object_records += 1;
+                continue;
+            };
+            for (name, value) in obj {
+                let kind = match value {
+                    Value::String(_) => "string",
+                    Value::Number(_) => "number",
+                    Value::Bool(_) => "bool",
+                    Value::Array(_) => "array",
+                    Value::Object(_) => "object",
+                    Value::Null => "null",
+                };
+                let (count, types) = fields.entry(name.as_str()).or_default();
+                *count += 1;
+                *types.entry(kind).or_default() += 1;
+            }
+        }
+
+        let fields: serde_json::Map<String, Value> = fields.into_iter()
+            .map(|(name, (count, types))| (name.to_string(), serde_json::json!({
+                "count": count,
+                "types": types
+            })))
+            .collect();
+
+        serde_json::json!({
+            "records_scanned": sample.len(),
+            "sampled": sample.len() < data.len(),
+            "non_object_records": non_object_records,
+            "fields": fields
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // A DatabaseManager over its own data directory, removed when the test ends
+    struct TestDb {
+        manager: DatabaseManager,
+    }
+
+    impl TestDb {
+        fn new() -> Self {
+            Self::with_config(DatabaseConfig::default())
+        }
+
+        fn with_config(config: DatabaseConfig) -> Self {
+            let data_dir = std::env::temp_dir().join(format!("sarychdb-test-{}", Uuid::new_v4()));
+            fs::create_dir_all(data_dir.join(USERS_DIR).join("alice")).unwrap();
+            TestDb { manager: DatabaseManager::new(DatabaseConfig { data_dir, ..config }) }
+        }
+
+        // Create an empty database owned by alice
+        fn create(&self, db_name: &str) {
+            fs::write(self.manager.get_db_path("alice", db_name).unwrap(), "[]").unwrap();
+        }
+
+        fn insert(&self, db_name: &str, record: Value) {
+            self.manager.insert_record("alice", db_name, "alice", record).unwrap();
+        }
+
+        // Live records as readers see them
+        fn records(&self, db_name: &str) -> Vec<Value> {
+            self.manager.search_records("alice", db_name, &SearchOptions::default()).unwrap().to_vec()
+        }
+    }
+
+    impl std::ops::Deref for TestDb {
+        type Target = DatabaseManager;
+
+        fn deref(&self) -> &DatabaseManager {
+            &self.manager
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.manager.config.data_dir);
+        }
+    }
+
+    #[test]
+    fn replace_drops_fields_missing_from_the_body() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "name": "Ada", "email": "ada@example.com"}));
+
+        db.update_records("alice", "people", "alice", UpdateTarget::Id("a"), json!({"name": "Ada L."}), UpdateMode::Replace, false).unwrap();
+
+        let record = &db.records("people")[0];
+        assert_eq!(record["name"], "Ada L.");
+        assert!(record.get("email").is_none());
+        assert_eq!(record["_id"], "a");
+        assert!(record.get("_created_at").is_some());
+    }
+
+    #[test]
+    fn merge_keeps_fields_missing_from_the_body() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "name": "Ada", "email": "ada@example.com"}));
+
+        db.update_records("alice", "people", "alice", UpdateTarget::Id("a"), json!({"name": "Ada L."}), UpdateMode::Merge, false).unwrap();
+
+        let record = &db.records("people")[0];
+        assert_eq!(record["name"], "Ada L.");
+        assert_eq!(record["email"], "ada@example.com");
+    }
+
+    #[test]
+    fn replace_cannot_overwrite_immutable_metadata() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "name": "Ada"}));
+        let created_at = db.records("people")[0]["_created_at"].clone();
+
+        db.update_records("alice", "people", "alice", UpdateTarget::Id("a"), json!({"_id": "b", "_created_at": "then"}), UpdateMode::Replace, false).unwrap();
+
+        let record = &db.records("people")[0];
+        assert_eq!(record["_id"], "a");
+        assert_eq!(record["_created_at"], created_at);
+    }
+
+    #[test]
+    fn return_docs_reports_updated_records_after_the_change() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "team": "red", "score": 1}));
+        db.insert("people", json!({"_id": "b", "team": "red", "score": 2}));
+        db.insert("people", json!({"_id": "c", "team": "blue", "score": 3}));
+
+        let outcome = db.update_records("alice", "people", "alice", UpdateTarget::Query("red"), json!({"team": "green"}), UpdateMode::Merge, true).unwrap();
+
+        assert_eq!(outcome.count, 2);
+        assert_eq!(outcome.affected.len(), 2);
+        assert!(outcome.affected.iter().all(|record| record["team"] == "green" && record.get("_updated_at").is_some()));
+    }
+
+    #[test]
+    fn return_docs_reports_deleted_records() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "team": "red"}));
+        db.insert("people", json!({"_id": "b", "team": "blue"}));
+
+        let outcome = db.delete_records("alice", "people", "red", false, true).unwrap();
+
+        assert_eq!(outcome.count, 1);
+        assert_eq!(outcome.affected.len(), 1);
+        assert_eq!(outcome.affected[0]["_id"], "a");
+        assert_eq!(db.records("people").len(), 1);
+    }
+
+    #[test]
+    fn without_return_docs_only_the_count_comes_back() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "team": "red"}));
+        db.insert("people", json!({"_id": "b", "team": "blue"}));
+
+        let updated = db.update_records("alice", "people", "alice", UpdateTarget::Query("red"), json!({"team": "green"}), UpdateMode::Merge, false).unwrap();
+        let deleted = db.delete_records("alice", "people", "blue", false, false).unwrap();
+
+        assert_eq!((updated.count, deleted.count), (1, 1));
+        assert!(updated.affected.is_empty());
+        assert!(deleted.affected.is_empty());
+    }
+
+    #[test]
+    fn increment_creates_a_missing_field_at_the_amount() {
+        let db = TestDb::new();
+        db.create("counters");
+        db.insert("counters", json!({"_id": "a"}));
+
+        db.update_records("alice", "counters", "alice", UpdateTarget::Id("a"), Value::Null, UpdateMode::Increment { field: "hits", amount: Number::from(5) }, false).unwrap();
+
+        assert_eq!(db.records("counters")[0]["hits"], 5);
+    }
+
+    #[test]
+    fn increment_adds_to_an_existing_field_and_decrements_with_negative_amounts() {
+        let db = TestDb::new();
+        db.create("counters");
+        db.insert("counters", json!({"_id": "a", "hits": 10}));
+
+        db.update_records("alice", "counters", "alice", UpdateTarget::Id("a"), Value::Null, UpdateMode::Increment { field: "hits", amount: Number::from(3) }, false).unwrap();
+        assert_eq!(db.records("counters")[0]["hits"], 13);
+
+        db.update_records("alice", "counters", "alice", UpdateTarget::Id("a"), Value::Null, UpdateMode::Increment { field: "hits", amount: Number::from(-20) }, false).unwrap();
+        assert_eq!(db.records("counters")[0]["hits"], -7);
+    }
+
+    #[test]
+    fn increment_rejects_non_numeric_fields() {
+        let db = TestDb::new();
+        db.create("counters");
+        db.insert("counters", json!({"_id": "a", "hits": "many"}));
+
+        let result = db.update_records("alice", "counters", "alice", UpdateTarget::Id("a"), Value::Null, UpdateMode::Increment { field: "hits", amount: Number::from(1) }, false);
+
+        assert!(matches!(result, Err(DbError::BadRequest(_))));
+        assert_eq!(db.records("counters")[0]["hits"], "many");
+    }
+
+    // Every page of a cursor listing sorted by `sort_by`, inserting `late` after the first page
+    fn iterate_cursor(db: &TestDb, sort_by: &str, late: Value) -> Vec<Value> {
+        let options = ListOptions { sort_by: Some(sort_by), limit: Some(2), ..Default::default() };
+        let mut seen = Vec::new();
+        let mut cursor = String::new();
+        let mut late = Some(late);
+        loop {
+            let page = db.list_records_cursor("alice", "events", &options, &cursor).unwrap();
+            seen.extend(page["data"].as_array().unwrap().iter().map(|record| record["_id"].clone()));
+            if let Some(record) = late.take() {
+                db.insert("events", record);
+            }
+            match page["pagination"]["next_cursor"].as_str() {
+                Some(next) => cursor = next.to_string(),
+                None => return seen,
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_iteration_visits_tied_records_once() {
+        let db = TestDb::new();
+        db.create("events");
+        for (id, day) in [("a", 1), ("b", 1), ("c", 1), ("d", 2), ("e", 2), ("f", 3), ("g", 3)] {
+            db.insert("events", json!({"_id": id, "day": day}));
+        }
+
+        let seen = iterate_cursor(&db, "day", json!({"_id": "h", "day": 9}));
+
+        assert_eq!(seen, ["a", "b", "c", "d", "e", "f", "g", "h"].map(Value::from));
+    }
+
+    #[test]
+    fn cursor_iteration_skips_records_inserted_behind_it() {
+        let db = TestDb::new();
+        db.create("events");
+        for (id, day) in [("b", 2), ("c", 2), ("d", 3), ("e", 3), ("f", 4)] {
+            db.insert("events", json!({"_id": id, "day": day}));
+        }
+
+        // Sorts before the first page, so it is never reached and nothing shifts
+        let seen = iterate_cursor(&db, "day", json!({"_id": "a", "day": 1}));
+
+        assert_eq!(seen, ["b", "c", "d", "e", "f"].map(Value::from));
+    }
+
+    #[test]
+    fn bare_value_cursor_returns_records_after_it() {
+        let db = TestDb::new();
+        db.create("events");
+        for (id, day) in [("a", 1), ("b", 2), ("c", 3)] {
+            db.insert("events", json!({"_id": id, "day": day}));
+        }
+        let options = ListOptions { sort_by: Some("day"), ..Default::default() };
+
+        let page = db.list_records_cursor("alice", "events", &options, "1").unwrap();
+
+        let ids: Vec<&Value> = page["data"].as_array().unwrap().iter().map(|record| &record["_id"]).collect();
+        assert_eq!(ids, [&json!("b"), &json!("c")]);
+    }
+
+    #[test]
+    fn concurrent_inserts_are_never_lost() {
+        const WRITERS: usize = 16;
+        const INSERTS_PER_WRITER: usize = 10;
+        let db = Arc::new(TestDb::new());
+        db.create("hits");
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let db = Arc::clone(&db);
+                std::thread::spawn(move || {
+                    for n in 0..INSERTS_PER_WRITER {
+                        db.insert("hits", json!({"writer": writer, "n": n}));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let records = db.read_database("alice", "hits").unwrap();
+        assert_eq!(records.len(), WRITERS * INSERTS_PER_WRITER);
+        let seqs: HashSet<u64> = records.iter().filter_map(|record| record["_seq"].as_u64()).collect();
+        assert_eq!(seqs.len(), WRITERS * INSERTS_PER_WRITER);
+    }
+
+    #[test]
+    fn snapshots_taken_during_writes_hold_whole_writes_only() {
+        const INSERTS: usize = 60;
+        let db = Arc::new(TestDb::new());
+        db.create("hits");
+
+        let writer = {
+            let db = Arc::clone(&db);
+            std::thread::spawn(move || {
+                for n in 0..INSERTS {
+                    db.insert("hits", json!({"n": n}));
+                }
+            })
+        };
+        let mut snapshots = Vec::new();
+        while !writer.is_finished() || snapshots.is_empty() {
+            match db.snapshot_database("alice", "hits") {
+                Ok(taken) => snapshots.push(taken["snapshot"].as_str().unwrap().to_string()),
+                // Two snapshots in the same millisecond, the next one gets a new name
+                Err(DbError::Conflict(_)) => {}
+                Err(e) => panic!("snapshot failed: {}", e),
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        writer.join().unwrap();
+
+        for snapshot in snapshots {
+            let contents = fs::read(db.config.data_dir.join(&snapshot)).unwrap();
+            let records: Vec<Value> = serde_json::from_slice(&contents).unwrap();
+            // Inserts land in `_seq` order, so a consistent snapshot holds exactly the first ones
+            let seqs: Vec<u64> = records.iter().map(|record| record["_seq"].as_u64().unwrap()).collect();
+            assert_eq!(seqs, (1..=records.len() as u64).collect::<Vec<_>>(), "{}", snapshot);
+        }
+    }
+
+    #[test]
+    fn path_components_that_could_leave_the_users_folder_are_rejected() {
+        for component in ["", "../bob", "..", "a/b", "a\\b", "nul\0byte"] {
+            assert!(
+                matches!(DatabaseManager::sanitize_component(component), Err(DbError::BadRequest(_))),
+                "{:?} was accepted", component
+            );
+        }
+        assert_eq!(DatabaseManager::sanitize_component("alice"), Ok("alice"));
+    }
+
+    #[test]
+    fn unsafe_usernames_never_reach_the_filesystem() {
+        let db = TestDb::new();
+        db.create("people");
+
+        for username in ["../alice", "alice/..", "..\\alice"] {
+            assert!(matches!(db.get_db_path(username, "people"), Err(DbError::BadRequest(_))));
+            assert!(matches!(db.read_database(username, "people"), Err(DbError::BadRequest(_))));
+        }
+    }
+
+    #[test]
+    fn expired_records_do_not_use_up_max_results() {
+        let db = TestDb::new();
+        db.create("sessions");
+        let past = (Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+        let mut records: Vec<Value> = (0..5).map(|n| json!({"_id": format!("old{}", n), "kind": "session", EXPIRES_AT_FIELD: past})).collect();
+        records.extend((0..3).map(|n| json!({"_id": format!("live{}", n), "kind": "session"})));
+        db.write_database("alice", "sessions", &records).unwrap();
+
+        for stable_order in [false, true] {
+            let results = db.search_records("alice", "sessions", &SearchOptions {
+                query: Some("session"),
+                max_results: Some(2),
+                stable_order,
+                ..Default::default()
+            }).unwrap();
+
+            assert_eq!(results.len(), 2);
+            assert!(results.iter().all(|record| record.get(EXPIRES_AT_FIELD).is_none()));
+        }
+    }
+
+    #[test]
+    fn max_results_caps_every_query_type() {
+        let db = TestDb::new();
+        db.create("items");
+        let records: Vec<Value> = (0..20).map(|n| json!({"_id": format!("i{}", n), "name": format!("item {}", n)})).collect();
+        db.write_database("alice", "items", &records).unwrap();
+
+        for query_type in [None, Some("value"), Some("regex"), Some("fuzzy")] {
+            let results = db.search_records("alice", "items", &SearchOptions {
+                query: Some("item"),
+                query_type,
+                max_results: Some(5),
+                ..Default::default()
+            }).unwrap();
+            assert_eq!(results.len(), 5, "queryType {:?}", query_type);
+        }
+    }
+
+    fn transaction_op(database: &str, op: &str, body: Option<Value>, id_update: Option<&str>) -> TransactionOp {
+        serde_json::from_value(json!({
+            "database": database,
+            "op": op,
+            "body": body,
+            "idUpdate": id_update,
+        })).unwrap()
+    }
+
+    // Raw contents of every file in alice's folder
+    fn user_files(db: &TestDb) -> BTreeMap<String, Vec<u8>> {
+        fs::read_dir(db.user_dir("alice").unwrap()).unwrap()
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .map(|entry| (entry.file_name().to_string_lossy().into_owned(), fs::read(entry.path()).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn transaction_commits_every_operation() {
+        let db = TestDb::new();
+        db.create("orders");
+        db.create("stock");
+        db.insert("stock", json!({"_id": "lamp", "available": 5}));
+
+        let results = db.run_transaction("alice", &[
+            transaction_op("orders", "post", Some(json!({"_id": "o-1", "item": "lamp"})), None),
+            transaction_op("stock", "put", Some(json!({"available": 4})), Some("lamp")),
+            transaction_op("orders", "post", Some(json!({"_id": "o-2", "item": "lamp"})), None),
+        ]).unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(db.records("orders").len(), 2);
+        assert_eq!(db.records("stock")[0]["available"], 4);
+        assert!(user_files(&db).keys().all(|name| !name.ends_with(".tx") && !name.ends_with(".bak")));
+    }
+
+    #[test]
+    fn failing_transaction_leaves_every_file_unchanged() {
+        let db = TestDb::new();
+        db.create("orders");
+        db.create("stock");
+        db.insert("orders", json!({"_id": "o-1", "item": "lamp"}));
+        db.insert("stock", json!({"_id": "lamp", "available": 5}));
+        let before = user_files(&db);
+
+        // The third operation inserts a duplicate id
+        let result = db.run_transaction("alice", &[
+            transaction_op("stock", "put", Some(json!({"available": 4})), Some("lamp")),
+            transaction_op("orders", "post", Some(json!({"_id": "o-2", "item": "lamp"})), None),
+            transaction_op("orders", "post", Some(json!({"_id": "o-1", "item": "lamp"})), None),
+        ]);
+
+        assert!(matches!(result, Err(DbError::Conflict(_))));
+        assert_eq!(user_files(&db), before);
+    }
+
+    #[test]
+    fn failed_rename_puts_back_the_databases_already_replaced() {
+        let db = TestDb::new();
+        db.create("a");
+        db.create("b");
+        db.insert("a", json!({"_id": "a1"}));
+        // The original of `b` can't be moved aside onto a directory, so `b` fails after `a` was swapped in
+        let blocker = format!("{}.bak", db.get_db_path("alice", "b").unwrap());
+        fs::create_dir_all(Path::new(&blocker).join("keep")).unwrap();
+        let before = user_files(&db);
+
+        let result = db.run_transaction("alice", &[
+            transaction_op("a", "post", Some(json!({"_id": "a2"})), None),
+            transaction_op("b", "post", Some(json!({"_id": "b1"})), None),
+        ]);
+
+        assert!(matches!(result, Err(DbError::Internal(_))));
+        assert_eq!(user_files(&db), before);
+        assert_eq!(db.records("a").len(), 1);
+        assert!(db.records("b").is_empty());
+    }
+
+    #[test]
+    fn stable_hasher_is_fnv1a() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = StableHasher::new();
+            Hasher::write(&mut hasher, bytes);
+            hasher.finish()
+        };
+
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    // `sessions` with two expired records and one live one, written straight to the file
+    fn with_expired_sessions(db: &TestDb) {
+        db.create("sessions");
+        let past = (Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+        let future = (Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+        db.write_database("alice", "sessions", &[
+            json!({"_id": "gone1", "user": "ada", EXPIRES_AT_FIELD: past}),
+            json!({"_id": "gone2", "user": "bob", EXPIRES_AT_FIELD: past}),
+            json!({"_id": "live", "user": "ada", EXPIRES_AT_FIELD: future}),
+        ]).unwrap();
+    }
+
+    #[test]
+    fn expired_records_are_invisible_to_reads() {
+        let db = TestDb::new();
+        with_expired_sessions(&db);
+
+        let ids = |records: &[Value]| records.iter().map(|record| record["_id"].clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&db.records("sessions")), [json!("live")]);
+        let found = db.search_records("alice", "sessions", &SearchOptions { query: Some("ada"), ..Default::default() }).unwrap();
+        assert_eq!(ids(&found), [json!("live")]);
+        let listed = db.list_records("alice", "sessions", &ListOptions::default()).unwrap();
+        assert_eq!(ids(listed["data"].as_array().unwrap()), [json!("live")]);
+        let browsed = db.browse_records("alice", "sessions", None, None, false).unwrap();
+        assert_eq!(ids(browsed["data"].as_array().unwrap()), [json!("live")]);
+        assert_eq!(db.distinct_records("alice", "sessions", "user", None).unwrap(), (vec![json!("ada")], 1));
+        assert_eq!(db.get_stats("alice", "sessions").unwrap()["total_records"], 1);
+        assert_eq!(db.database_summary("alice", "sessions").unwrap()["total_records"], 1);
+    }
+
+    #[test]
+    fn purge_removes_expired_records_from_the_file() {
+        let db = TestDb::new();
+        with_expired_sessions(&db);
+
+        assert_eq!(db.purge_expired(), 2);
+
+        let stored = db.read_database("alice", "sessions").unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0]["_id"], "live");
+        assert_eq!(db.purge_expired(), 0);
+    }
+
+    #[test]
+    fn purge_leaves_the_cache_alone() {
+        let db = TestDb::new();
+        with_expired_sessions(&db);
+        db.create("people");
+        db.insert("people", json!({"name": "Ada"}));
+        db.invalidate_cache("alice", "people");
+
+        db.purge_expired();
+
+        for db_name in ["sessions", "people"] {
+            assert!(!db.is_cached(&db.get_db_path("alice", db_name).unwrap()), "{} was cached", db_name);
+        }
+    }
+
+    #[test]
+    fn metadata_holds_only_the_id_timestamps_and_version() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "name": "Ada", "email": "ada@example.com"}));
+
+        let metadata = db.record_metadata("alice", "people", "a", false).unwrap();
+
+        let keys: BTreeSet<&str> = metadata.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys, BTreeSet::from(["_id", "_created_at", "_updated_at", "_version"]));
+        assert_eq!(metadata["_id"], "a");
+        assert_eq!(metadata["_updated_at"], Value::Null);
+    }
+
+    #[test]
+    fn version_changes_with_the_content_only() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "name": "Ada"}));
+        let version = |db: &TestDb| db.record_metadata("alice", "people", "a", false).unwrap()["_version"].clone();
+
+        let first = version(&db);
+        assert_eq!(version(&db), first);
+        db.update_records("alice", "people", "alice", UpdateTarget::Id("a"), json!({"name": "Ada L."}), UpdateMode::Merge, false).unwrap();
+        assert_ne!(version(&db), first);
+    }
+
+    #[test]
+    fn version_is_stable_for_the_same_content() {
+        // Pinned so a change of hash is noticed: clients keep these between server upgrades
+        assert_eq!(DatabaseManager::record_version(&json!({"_id": "a"})).unwrap(), "f4f35e4b0e572664");
+    }
+
+    #[test]
+    fn compaction_shrinks_the_file_and_keeps_live_records() {
+        let db = TestDb::new();
+        with_expired_sessions(&db);
+        let path = db.get_stored_path("alice", "sessions").unwrap();
+        let size_before = fs::metadata(&path).unwrap().len();
+
+        let report = db.compact_database("alice", "sessions").unwrap();
+
+        let size_after = fs::metadata(&path).unwrap().len();
+        assert!(size_after < size_before);
+        assert_eq!(report["expired_removed"], 2);
+        assert_eq!(report["records"], 1);
+        assert_eq!(report["size_after"], size_after);
+        let stored = db.read_database("alice", "sessions").unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0]["_id"], "live");
+    }
+
+    #[test]
+    fn compaction_writes_compact_json_for_indented_databases() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"name": "Ada", "tags": ["math", "engines"]}));
+        let path = db.get_stored_path("alice", "people").unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains('\n'));
+
+        let report = db.compact_database("alice", "people").unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains('\n'));
+        assert!(report["bytes_saved"].as_u64().unwrap() > 0);
+        assert_eq!(db.records("people")[0]["tags"], json!(["math", "engines"]));
+    }
+
+    #[test]
+    fn no_cache_reads_see_the_file_without_replacing_the_cached_copy() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "name": "Ada"}));
+        let cached = db.read_database_cached("alice", "people").unwrap();
+
+        // Written behind the cache, as an edit by hand would be
+        let path = db.get_db_path("alice", "people").unwrap();
+        fs::write(&path, r#"[{"_id": "a", "name": "Grace"}]"#).unwrap();
+
+        let fresh = db.read_database_fresh_or_cached("alice", "people", true).unwrap();
+        assert_eq!(fresh[0]["name"], "Grace");
+        assert_eq!(db.read_database_cached("alice", "people").unwrap(), cached);
+    }
+
+    #[test]
+    fn replace_import_overwrites_the_records() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "old", "name": "Ada"}));
+
+        let message = db.import_records("alice", "people", vec![json!({"_id": "new", "name": "Grace"}), json!({"name": "Linus"})], false).unwrap();
+
+        assert_eq!(message, "Imported 2 records (2 total)");
+        let records = db.records("people");
+        assert_eq!(records[0]["_id"], "new");
+        assert!(records[1]["_id"].is_string());
+        assert!(records.iter().all(|record| record["_created_at"].is_string() && record["_seq"].is_u64()));
+    }
+
+    #[test]
+    fn append_import_keeps_live_records_and_drops_expired_ones() {
+        let db = TestDb::new();
+        with_expired_sessions(&db);
+
+        // The expired record's id is free again once it is gone
+        db.import_records("alice", "sessions", vec![json!({"_id": "gone1", "user": "ada"})], true).unwrap();
+
+        let stored = db.read_database("alice", "sessions").unwrap();
+        let ids: Vec<&Value> = stored.iter().map(|record| &record["_id"]).collect();
+        assert_eq!(ids, [&json!("live"), &json!("gone1")]);
+        assert!(stored[1].get(EXPIRES_AT_FIELD).is_none());
+    }
+
+    #[test]
+    fn import_treats_numeric_and_string_ids_as_the_same_id() {
+        let db = TestDb::new();
+        db.create("people");
+        // Only a custom id field may hold numbers through inserts; the file can hold one either way
+        db.write_database("alice", "people", &[json!({"_id": 1, "name": "Ada"})]).unwrap();
+
+        let appended = db.import_records("alice", "people", vec![json!({"_id": "1", "name": "Grace"})], true);
+        let in_batch = db.import_records("alice", "people", vec![json!({"_id": 2}), json!({"_id": "2"})], false);
+
+        assert!(matches!(appended, Err(DbError::Conflict(_))));
+        assert!(matches!(in_batch, Err(DbError::Conflict(_))));
+        assert_eq!(db.records("people").len(), 1);
+    }
+
+    #[test]
+    fn malformed_import_is_rejected_and_changes_nothing() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "a", "name": "Ada"}));
+        let before = db.read_database("alice", "people").unwrap();
+
+        let result = db.import_records("alice", "people", vec![json!({"name": "Grace"}), json!("not a record")], false);
+
+        match result {
+            Err(DbError::BadRequest(message)) => assert_eq!(message, "Record at index 1 is not a JSON object"),
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+        assert_eq!(db.read_database("alice", "people").unwrap(), before);
+    }
+
+    // Names of the listed records matching `filters`, in storage order
+    fn filtered_names(db: &TestDb, db_name: &str, filters: Value) -> Vec<String> {
+        let options = ListOptions { filters: Some(&filters), limit: Some(100), ..ListOptions::default() };
+        let listed = db.list_records("alice", db_name, &options).unwrap();
+        listed["data"].as_array().unwrap().iter()
+            .map(|record| record["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    // Products covering present, null and missing `discount` values
+    fn with_products(db: &TestDb) {
+        db.create("products");
+        db.insert("products", json!({"name": "Tent", "category": "camping", "discount": 10}));
+        db.insert("products", json!({"name": "Torch", "category": "camping", "discount": null}));
+        db.insert("products", json!({"name": "Laptop", "category": "electronics"}));
+        db.insert("products", json!({"name": "Tent pegs", "category": "accessories", "discount": 5}));
+    }
+
+    #[test]
+    fn exists_filters_tell_present_fields_from_missing_ones() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        assert_eq!(filtered_names(&db, "products", json!({"discount": {"$exists": true}})), ["Tent", "Torch", "Tent pegs"]);
+        assert_eq!(filtered_names(&db, "products", json!({"discount": {"$exists": false}})), ["Laptop"]);
+    }
+
+    #[test]
+    fn null_filter_matches_explicit_null_but_not_a_missing_field() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        assert_eq!(filtered_names(&db, "products", json!({"discount": null})), ["Torch"]);
+    }
+
+    #[test]
+    fn in_filter_and_its_array_shorthand_match_any_listed_value() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        let explicit = filtered_names(&db, "products", json!({"category": {"$in": ["electronics", "accessories"]}}));
+        let shorthand = filtered_names(&db, "products", json!({"category": ["electronics", "accessories"]}));
+
+        assert_eq!(explicit, ["Laptop", "Tent pegs"]);
+        assert_eq!(shorthand, explicit);
+    }
+
+    #[test]
+    fn nin_filter_excludes_listed_values_and_keeps_missing_fields() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        assert_eq!(filtered_names(&db, "products", json!({"category": {"$nin": ["camping"]}})), ["Laptop", "Tent pegs"]);
+        assert_eq!(filtered_names(&db, "products", json!({"discount": {"$nin": [10, 5]}})), ["Torch", "Laptop"]);
+        assert!(filtered_names(&db, "products", json!({"category": {"$in": "camping"}})).is_empty());
+    }
+
+    #[test]
+    fn contains_filter_combines_with_equality_as_an_implicit_and() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        let names = filtered_names(&db, "products", json!({"name": {"$contains": "Ten"}, "category": "camping"}));
+
+        assert_eq!(names, ["Tent"]);
+        assert!(filtered_names(&db, "products", json!({"discount": {"$contains": "1"}})).is_empty());
+    }
+
+    #[test]
+    fn regex_filter_matches_strings_and_rejects_invalid_patterns() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        assert_eq!(filtered_names(&db, "products", json!({"name": {"$regex": "^T"}})), ["Tent", "Torch", "Tent pegs"]);
+        assert_eq!(filtered_names(&db, "products", json!({"name": {"$regex": "(?i)^l"}})), ["Laptop"]);
+
+        let invalid = json!({"$or": [{"name": {"$regex": "(unclosed"}}]});
+        let options = ListOptions { filters: Some(&invalid), ..ListOptions::default() };
+        assert!(matches!(db.list_records("alice", "products", &options), Err(DbError::BadRequest(_))));
+    }
+
+    // `count` people named person0, person1, ...
+    fn with_people(db: &TestDb, count: usize) {
+        db.create("people");
+        for n in 0..count {
+            db.insert("people", json!({"name": format!("person{}", n)}));
+        }
+    }
+
+    #[test]
+    fn sample_returns_the_requested_size_or_every_live_record() {
+        let db = TestDb::new();
+        with_people(&db, 10);
+
+        let (sample, total) = db.sample_records("alice", "people", 4, None, false).unwrap();
+        assert_eq!((sample.len(), total), (4, 10));
+        let distinct: HashSet<&Value> = sample.iter().map(|record| &record["_id"]).collect();
+        assert_eq!(distinct.len(), 4);
+
+        with_expired_sessions(&db);
+        let (sample, total) = db.sample_records("alice", "sessions", 5, None, false).unwrap();
+        assert_eq!((sample.len(), total), (1, 1));
+        assert_eq!(sample[0]["_id"], "live");
+    }
+
+    #[test]
+    fn the_same_seed_picks_the_same_sample_from_disk_or_cache() {
+        let db = TestDb::new();
+        with_people(&db, 50);
+        db.invalidate_cache("alice", "people");
+
+        let streamed = db.sample_records("alice", "people", 5, Some(7), false).unwrap();
+        assert!(!db.is_cached(&db.get_db_path("alice", "people").unwrap()));
+        db.read_database_cached("alice", "people").unwrap();
+        let cached = db.sample_records("alice", "people", 5, Some(7), false).unwrap();
+        let reseeded = db.sample_records("alice", "people", 5, Some(8), false).unwrap();
+
+        assert_eq!(streamed, cached);
+        assert_ne!(streamed.0, reseeded.0);
+    }
+
+    fn searched_names(db: &TestDb, search: &str, fields: &[&str], filters: Option<Value>) -> Vec<String> {
+        let options = ListOptions {
+            search: Some(search),
+            search_fields: fields.to_vec(),
+            filters: filters.as_ref(),
+            ..ListOptions::default()
+        };
+        db.list_records("alice", "products", &options).unwrap()["data"].as_array().unwrap().iter()
+            .map(|record| record["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn text_search_narrows_the_list_case_insensitively() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        assert_eq!(searched_names(&db, "TENT", &[], None), ["Tent", "Tent pegs"]);
+        assert_eq!(searched_names(&db, "tent", &[], Some(json!({"category": "camping"}))), ["Tent"]);
+    }
+
+    #[test]
+    fn text_search_only_looks_in_the_listed_fields() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        assert_eq!(searched_names(&db, "camp", &["category"], None), ["Tent", "Torch"]);
+        assert!(searched_names(&db, "camp", &["name"], None).is_empty());
+        assert_eq!(searched_names(&db, "o", &["name", "nothere"], None), ["Torch", "Laptop"]);
+    }
+
+    #[test]
+    fn costly_fuzzy_search_needs_force() {
+        let db = TestDb::with_config(DatabaseConfig { max_query_cost: Some(1_000), ..DatabaseConfig::default() });
+        db.create("items");
+        let records: Vec<Value> = (0..200).map(|n| json!({"name": format!("item {}", n)})).collect();
+        db.write_database("alice", "items", &records).unwrap();
+        let fuzzy = |force| SearchOptions { query: Some("TensrFlow"), query_type: Some("fuzzy"), force, ..Default::default() };
+
+        // 200 records at 9 characters times 3 allowed distances each
+        match db.search_records("alice", "items", &fuzzy(false)) {
+            Err(DbError::BadRequest(message)) => assert!(message.starts_with("Estimated query cost 5400 exceeds the limit of 1000"), "{}", message),
+            other => panic!("expected the search to be rejected, got {:?}", other),
+        }
+        assert!(db.search_records("alice", "items", &fuzzy(true)).is_ok());
+        assert_eq!(db.search_records("alice", "items", &SearchOptions { query: Some("item"), ..Default::default() }).unwrap().len(), 200);
+    }
+
+    #[test]
+    fn csv_round_trips_scalars_commas_and_quotes() {
+        let records = vec![
+            json!({"name": "Doe, Jane", "quote": "she said \"hi\"", "age": 28, "active": true, "zip": "007"}),
+            json!({"name": "Multi\nline", "score": 1.5}),
+        ];
+
+        let csv = DatabaseManager::records_to_csv(&records).unwrap();
+
+        assert!(csv.starts_with("active,age,name,quote,zip,score\n"), "{}", csv);
+        // Missing fields are empty cells, and empty cells are left out again
+        assert_eq!(DatabaseManager::csv_to_records(csv.as_bytes()).unwrap(), records);
+    }
+
+    #[test]
+    fn nested_values_round_trip_as_flattened_columns() {
+        let record = json!({"name": "Ada", "address": {"city": "Lima"}, "tags": ["a", "b"]});
+
+        let plain = DatabaseManager::records_to_csv(std::slice::from_ref(&record)).unwrap();
+        let flattened = DatabaseManager::records_to_csv(&[DatabaseManager::flatten_value(&record)]).unwrap();
+
+        assert_eq!(plain, "name\nAda\n");
+        assert_eq!(
+            DatabaseManager::csv_to_records(flattened.as_bytes()).unwrap(),
+            [json!({"name": "Ada", "address.city": "Lima", "tags.0": "a", "tags.1": "b"})]
+        );
+    }
+
+    #[test]
+    fn distinct_keeps_first_seen_order_and_value_types() {
+        let records = [
+            json!({"city": "Lima"}),
+            json!({"city": 1}),
+            json!({"city": "1"}),
+            json!({"name": "no city"}),
+            json!({"city": "Lima"}),
+            json!({"city": null}),
+        ];
+
+        let (values, total) = DatabaseManager::distinct_values(&records, "city", None);
+
+        assert_eq!(values, [json!("Lima"), json!(1), json!("1"), Value::Null]);
+        assert_eq!(total, 4);
+    }
+
+    #[test]
+    fn distinct_limit_caps_the_values_but_not_the_count() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        let (values, total) = db.distinct_records("alice", "products", "category", Some(2)).unwrap();
+        let (none, _) = db.distinct_records("alice", "products", "category", Some(0)).unwrap();
+
+        assert_eq!(values, [json!("camping"), json!("electronics")]);
+        assert_eq!(total, 3);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn form_bodies_become_flat_typed_records() {
+        let record = DatabaseManager::form_to_record(b"name=Ada+Lovelace&age=36&active=true&zip=007&tag=math&tag=poetry&note=a%26b");
+
+        assert_eq!(record, json!({
+            "name": "Ada Lovelace",
+            "age": 36,
+            "active": true,
+            "zip": "007",
+            "tag": ["math", "poetry"],
+            "note": "a&b",
+        }));
+    }
+
+    #[test]
+    fn deleting_most_records_needs_confirm_bulk() {
+        let db = TestDb::new();
+        with_people(&db, 10);
+
+        let refused = db.delete_records("alice", "people", "person", false, false).unwrap_err();
+        assert!(matches!(&refused, DbError::Conflict(message) if message.starts_with("Delete would remove 10 of 10 records (more than 50%)")));
+        assert_eq!(db.records("people").len(), 10);
+
+        let confirmed = db.delete_records("alice", "people", "person", true, false).unwrap();
+        assert_eq!(confirmed.count, 10);
+        assert!(db.records("people").is_empty());
+    }
+
+    #[test]
+    fn deleting_a_single_record_never_needs_confirm_bulk() {
+        let db = TestDb::new();
+        with_people(&db, 1);
+
+        let outcome = db.delete_records("alice", "people", "person0", false, true).unwrap();
+
+        assert_eq!(outcome.count, 1);
+        assert_eq!(outcome.affected[0]["name"], "person0");
+        assert!(db.records("people").is_empty());
+    }
+
+    #[test]
+    fn array_push_creates_a_missing_array_and_appends_to_an_existing_one() {
+        let db = TestDb::new();
+        db.create("posts");
+        db.insert("posts", json!({"_id": "a", "tags": ["rust"]}));
+        db.insert("posts", json!({"_id": "b"}));
+
+        db.update_records("alice", "posts", "alice", UpdateTarget::Id("a"), json!("db"), UpdateMode::ArrayPush { field: "tags" }, false).unwrap();
+        db.update_records("alice", "posts", "alice", UpdateTarget::Id("b"), json!({"n": 1}), UpdateMode::ArrayPush { field: "tags" }, false).unwrap();
+
+        let records = db.records("posts");
+        assert_eq!(records[0]["tags"], json!(["rust", "db"]));
+        assert_eq!(records[1]["tags"], json!([{"n": 1}]));
+    }
+
+    #[test]
+    fn array_pull_removes_every_equal_element_and_ignores_absent_values() {
+        let db = TestDb::new();
+        db.create("posts");
+        db.insert("posts", json!({"_id": "a", "tags": ["rust", "db", "rust"]}));
+
+        db.update_records("alice", "posts", "alice", UpdateTarget::Id("a"), json!("rust"), UpdateMode::ArrayPull { field: "tags" }, false).unwrap();
+        assert_eq!(db.records("posts")[0]["tags"], json!(["db"]));
+
+        db.update_records("alice", "posts", "alice", UpdateTarget::Id("a"), json!("go"), UpdateMode::ArrayPull { field: "tags" }, false).unwrap();
+        db.update_records("alice", "posts", "alice", UpdateTarget::Id("a"), json!("go"), UpdateMode::ArrayPull { field: "missing" }, false).unwrap();
+        let record = &db.records("posts")[0];
+        assert_eq!(record["tags"], json!(["db"]));
+        assert!(record.get("missing").is_none());
+    }
+
+    #[test]
+    fn array_operations_reject_fields_that_are_not_arrays() {
+        let db = TestDb::new();
+        db.create("posts");
+        db.insert("posts", json!({"_id": "a", "tags": "rust"}));
+
+        for mode in [UpdateMode::ArrayPush { field: "tags" }, UpdateMode::ArrayPull { field: "tags" }] {
+            let error = db.update_records("alice", "posts", "alice", UpdateTarget::Id("a"), json!("db"), mode, false).unwrap_err();
+            assert!(matches!(error, DbError::BadRequest(message) if message == "Field 'tags' is not an array"));
+        }
+        assert_eq!(db.records("posts")[0]["tags"], "rust");
+    }
+
+    #[test]
+    fn or_filter_matches_records_passing_any_branch() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        let names = filtered_names(&db, "products", json!({"$or": [{"category": "electronics"}, {"discount": 5}]}));
+
+        assert_eq!(names, vec!["Laptop", "Tent pegs"]);
+    }
+
+    #[test]
+    fn and_nested_in_or_needs_every_condition_of_its_branch() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        let names = filtered_names(&db, "products", json!({"$or": [
+            {"$and": [{"category": "camping"}, {"discount": 10}]},
+            {"name": "Laptop"},
+        ]}));
+
+        assert_eq!(names, vec!["Tent", "Laptop"]);
+    }
+
+    #[test]
+    fn flat_filters_are_still_an_implicit_and() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        assert_eq!(filtered_names(&db, "products", json!({"category": "camping", "discount": 10})), vec!["Tent"]);
+        assert_eq!(filtered_names(&db, "products", json!({"category": "camping", "name": "Laptop"})), Vec::<String>::new());
+    }
+
+    #[test]
+    fn browse_and_list_clamp_the_limit_to_the_configured_maximum() {
+        let db = TestDb::with_config(DatabaseConfig { page_limits: PageLimits { default: 2, max: 3 }, ..Default::default() });
+        with_people(&db, 5);
+
+        let browsed = db.browse_records("alice", "people", Some(1), Some(100), false).unwrap();
+        let listed = db.list_records("alice", "people", &ListOptions { limit: Some(100), ..ListOptions::default() }).unwrap();
+
+        for response in [&browsed, &listed] {
+            assert_eq!(response["data"].as_array().unwrap().len(), 3);
+            assert_eq!(response["pagination"]["limit"], 3);
+            assert_eq!(response["pagination"]["limit_clamped"], true);
+            assert_eq!(response["pagination"]["total_pages"], 2);
+        }
+    }
+
+    #[test]
+    fn browse_and_list_use_the_configured_default_without_a_limit() {
+        let db = TestDb::with_config(DatabaseConfig { page_limits: PageLimits { default: 2, max: 3 }, ..Default::default() });
+        with_people(&db, 5);
+
+        let browsed = db.browse_records("alice", "people", None, None, false).unwrap();
+        let listed = db.list_records("alice", "people", &ListOptions::default()).unwrap();
+        let within_max = db.list_records("alice", "people", &ListOptions { limit: Some(3), ..ListOptions::default() }).unwrap();
+
+        for response in [&browsed, &listed] {
+            assert_eq!(response["data"].as_array().unwrap().len(), 2);
+            assert_eq!(response["pagination"]["limit"], 2);
+            assert_eq!(response["pagination"]["limit_clamped"], false);
+        }
+        assert_eq!(within_max["pagination"]["limit"], 3);
+        assert_eq!(within_max["pagination"]["limit_clamped"], false);
+    }
+
+    #[test]
+    fn stats_summarise_field_counts_and_value_types() {
+        let db = TestDb::new();
+        with_products(&db);
+        db.insert("products", json!({"name": "Bundle", "category": ["camping", "electronics"], "discount": "half", "in_stock": false, "extras": {"bag": true}}));
+
+        let stats = db.get_stats("alice", "products").unwrap();
+
+        let summary = &stats["schema_summary"];
+        assert_eq!(stats["total_records"], 5);
+        assert_eq!(summary["records_scanned"], 5);
+        assert_eq!(summary["sampled"], false);
+        assert_eq!(summary["non_object_records"], 0);
+        assert_eq!(summary["fields"]["name"], json!({"count": 5, "types": {"string": 5}}));
+        assert_eq!(summary["fields"]["category"], json!({"count": 5, "types": {"array": 1, "string": 4}}));
+        assert_eq!(summary["fields"]["discount"], json!({"count": 4, "types": {"null": 1, "number": 2, "string": 1}}));
+        assert_eq!(summary["fields"]["in_stock"], json!({"count": 1, "types": {"bool": 1}}));
+        assert_eq!(summary["fields"]["extras"], json!({"count": 1, "types": {"object": 1}}));
+    }
+
+    #[test]
+    fn stats_report_a_cold_read_then_a_cache_hit() {
+        let db = TestDb::new();
+        with_people(&db, 3);
+        db.invalidate_cache("alice", "people");
+
+        let cold = db.get_stats("alice", "people").unwrap();
+        let warm = db.get_stats("alice", "people").unwrap();
+
+        assert_eq!(cold["cache"]["last_read_hit"], false);
+        assert_eq!(warm["cache"]["last_read_hit"], true);
+        assert_eq!(warm["cache"]["hits"].as_u64().unwrap(), cold["cache"]["hits"].as_u64().unwrap() + 1);
+        assert_eq!(warm["cache"]["misses"], cold["cache"]["misses"]);
+        assert!(warm["cache"]["entry_age_ms"].is_u64());
+    }
+
+    #[test]
+    fn managers_over_different_data_dirs_do_not_share_databases() {
+        let first = TestDb::new();
+        let second = TestDb::new();
+        first.create("people");
+        first.insert("people", json!({"name": "Ada"}));
+
+        assert!(Path::new(&first.get_db_path("alice", "people").unwrap()).starts_with(&first.config.data_dir));
+        assert_eq!(first.records("people").len(), 1);
+        assert!(matches!(second.search_records("alice", "people", &SearchOptions::default()), Err(DbError::NotFound(_))));
+    }
+
+    #[test]
+    fn page_links_are_null_past_the_first_and_last_pages() {
+        let db = TestDb::new();
+        with_people(&db, 5);
+        let page = |page| db.list_records("alice", "people", &ListOptions { page: Some(page), limit: Some(2), ..ListOptions::default() }).unwrap()["pagination"].clone();
+
+        let links = |pagination: &Value| (pagination["first_page"].clone(), pagination["last_page"].clone(), pagination["prev_page"].clone(), pagination["next_page"].clone());
+        assert_eq!(links(&page(1)), (json!(1), json!(3), Value::Null, json!(2)));
+        assert_eq!(links(&page(2)), (json!(1), json!(3), json!(1), json!(3)));
+        assert_eq!(links(&page(3)), (json!(1), json!(3), json!(2), Value::Null));
+
+        let browsed = db.browse_records("alice", "people", Some(3), Some(2), false).unwrap();
+        assert_eq!(links(&browsed["pagination"]), (json!(1), json!(3), json!(2), Value::Null));
+        assert_eq!(browsed["pagination"]["has_next"], false);
+    }
+
+    #[test]
+    fn unknown_sort_orders_are_rejected() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        let options = ListOptions { sort_by: Some("name"), sort_order: Some("descending"), ..ListOptions::default() };
+        let error = db.list_records("alice", "products", &options).unwrap_err();
+
+        assert!(matches!(error, DbError::BadRequest(message) if message == "Invalid sortOrder 'descending'. Use 'asc' or 'desc'"));
+    }
+
+    #[test]
+    fn sorting_by_a_field_no_record_has_is_flagged() {
+        let db = TestDb::new();
+        with_products(&db);
+
+        let missing = db.list_records("alice", "products", &ListOptions { sort_by: Some("weight"), ..ListOptions::default() }).unwrap();
+        let present = db.list_records("alice", "products", &ListOptions { sort_by: Some("name"), sort_order: Some("desc"), ..ListOptions::default() }).unwrap();
+
+        assert_eq!(missing["sorting"]["sort_field_missing"], true);
+        assert_eq!(missing["data"].as_array().unwrap().len(), 4);
+        assert_eq!(present["sorting"], json!({"field": "name", "order": "desc", "sort_field_missing": false}));
+        assert_eq!(present["data"][0]["name"], "Torch");
+    }
+
+    #[test]
+    fn later_sort_fields_break_ties_on_earlier_ones() {
+        let db = TestDb::new();
+        db.create("people");
+        for (last, first) in [("Hopper", "Grace"), ("Lovelace", "Ada"), ("Hopper", "Anne"), ("Lovelace", "Byron")] {
+            db.insert("people", json!({"lastName": last, "firstName": first}));
+        }
+        let sorted = |sort_order| {
+            let options = ListOptions { sort_by: Some("lastName, firstName"), sort_order, ..ListOptions::default() };
+            db.list_records("alice", "people", &options).unwrap()["data"].as_array().unwrap().iter()
+                .map(|record| format!("{} {}", record["firstName"].as_str().unwrap(), record["lastName"].as_str().unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted(None), ["Anne Hopper", "Grace Hopper", "Ada Lovelace", "Byron Lovelace"]);
+        // A missing second order defaults to asc
+        assert_eq!(sorted(Some("desc")), ["Ada Lovelace", "Byron Lovelace", "Anne Hopper", "Grace Hopper"]);
+        assert_eq!(sorted(Some("asc,desc")), ["Grace Hopper", "Anne Hopper", "Byron Lovelace", "Ada Lovelace"]);
+    }
+
+    #[test]
+    fn a_client_supplied_id_that_already_exists_is_a_conflict() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "ada", "name": "Ada"}));
+
+        let error = db.insert_record("alice", "people", "alice", json!({"_id": "ada", "name": "Impostor"})).unwrap_err();
+
+        assert!(matches!(error, DbError::Conflict(message) if message == "A record with _id 'ada' already exists"));
+        let records = db.records("people");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["name"], "Ada");
+    }
+
+    #[test]
+    fn generated_ids_are_unique_uuids() {
+        let db = TestDb::new();
+        with_people(&db, 20);
+
+        let ids: HashSet<String> = db.records("people").iter()
+            .map(|record| record["_id"].as_str().unwrap().to_string())
+            .collect();
+
+        assert_eq!(ids.len(), 20);
+        assert!(ids.iter().all(|id| Uuid::parse_str(id).is_ok()));
+    }
+
+    #[test]
+    fn seq_keeps_increasing_across_deletes_and_restarts() {
+        let db = TestDb::new();
+        with_people(&db, 3);
+        let seqs = |manager: &DatabaseManager| -> Vec<u64> {
+            let options = ListOptions { sort_by: Some("_seq"), sort_order: Some("desc"), ..ListOptions::default() };
+            manager.list_records("alice", "people", &options).unwrap()["data"].as_array().unwrap().iter()
+                .map(|record| record["_seq"].as_u64().unwrap())
+                .collect()
+        };
+        assert_eq!(seqs(&db), [3, 2, 1]);
+
+        // A deleted last record's number is not handed out again
+        db.delete_records("alice", "people", "person2", false, false).unwrap();
+        db.insert("people", json!({"name": "person3"}));
+        assert_eq!(seqs(&db), [4, 2, 1]);
+
+        // After a restart the counter starts from the highest stored value
+        LAST_SEQ.lock().unwrap().remove(&db.get_db_path("alice", "people").unwrap());
+        let restarted = DatabaseManager::new(db.config.clone());
+        restarted.insert_record("alice", "people", "alice", json!({"name": "person4"})).unwrap();
+        assert_eq!(seqs(&restarted), [5, 4, 2, 1]);
+    }
+
+    #[test]
+    fn upsert_inserts_when_nothing_matches() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "ada", "name": "Ada"}));
+
+        let (outcome, inserted) = db.upsert_record("alice", "people", "alice", UpdateTarget::Id("grace"), json!({"name": "Grace"}), UpdateMode::Merge, true).unwrap();
+
+        assert!(inserted);
+        assert_eq!(outcome.count, 1);
+        assert_eq!(outcome.affected[0]["_id"], "grace");
+        let records = db.records("people");
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["name"], "Grace");
+        assert!(records[1]["_created_at"].is_string());
+    }
+
+    #[test]
+    fn upsert_updates_an_existing_match_without_inserting() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "ada", "name": "Ada", "born": 1815}));
+
+        let (outcome, inserted) = db.upsert_record("alice", "people", "alice", UpdateTarget::Id("ada"), json!({"name": "Ada Lovelace"}), UpdateMode::Merge, false).unwrap();
+
+        assert!(!inserted);
+        assert_eq!(outcome.count, 1);
+        let records = db.records("people");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["name"], "Ada Lovelace");
+        assert_eq!(records[0]["born"], 1815);
+        assert!(records[0]["_updated_at"].is_string());
+    }
+
+    #[test]
+    fn created_window_includes_its_start_and_excludes_its_end() {
+        let db = TestDb::new();
+        db.create("events");
+        db.write_database("alice", "events", &[
+            json!({"_id": "a", "kind": "login", "_created_at": "2024-01-01T00:00:00+00:00"}),
+            json!({"_id": "b", "kind": "login", "_created_at": "2024-01-02T00:00:00+00:00"}),
+            json!({"_id": "c", "kind": "logout", "_created_at": "2024-01-02T12:00:00+00:00"}),
+            json!({"_id": "d", "kind": "login", "_created_at": "2024-01-03T00:00:00+00:00"}),
+            json!({"_id": "e", "kind": "login"}),
+        ]).unwrap();
+        let at = |stamp: &str| DateTime::parse_from_rfc3339(stamp).unwrap().with_timezone(&Utc);
+        let ids = |options: ListOptions| -> Vec<String> {
+            db.list_records("alice", "events", &options).unwrap()["data"].as_array().unwrap().iter()
+                .map(|record| record["_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let window = || ListOptions { created_after: Some(at("2024-01-02T00:00:00Z")), created_before: Some(at("2024-01-03T00:00:00Z")), ..ListOptions::default() };
+        assert_eq!(ids(window()), ["b", "c"]);
+        assert_eq!(ids(ListOptions { created_after: Some(at("2024-01-02T06:00:00Z")), ..ListOptions::default() }), ["c", "d"]);
+        assert_eq!(ids(ListOptions { created_before: Some(at("2024-01-02T00:00:00Z")), ..ListOptions::default() }), ["a"]);
+        let logins = json!({"kind": "login"});
+        assert_eq!(ids(ListOptions { filters: Some(&logins), ..window() }), ["b"]);
+    }
+
+    #[tokio::test]
+    async fn background_cleanup_evicts_expired_databases_from_the_cache() {
+        let db = TestDb::with_config(DatabaseConfig { cache_ttl: Duration::from_secs(2), ..Default::default() });
+        with_people(&db, 2);
+        db.create("fresh");
+        db.records("people");
+        db.records("fresh");
+        let stale_key = db.get_db_path("alice", "people").unwrap();
+        let fresh_key = db.get_db_path("alice", "fresh").unwrap();
+        // Age one cached copy past the TTL instead of waiting for it
+        if let Some((_, timestamp)) = DB_CACHE.lock().unwrap().get_mut(&stale_key) {
+            *timestamp = Instant::now().checked_sub(db.config.cache_ttl).unwrap();
+        }
+
+        let cleaner = Arc::new(DatabaseManager::new(db.config.clone()));
+        crate::modules::server::spawn_periodic(Duration::from_millis(10), move || {
+            cleaner.evict_expired_cache();
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let cache = DB_CACHE.lock().unwrap();
+        assert!(!cache.contains_key(&stale_key));
+        assert!(cache.contains_key(&fresh_key));
+    }
+
+    #[test]
+    fn usage_adds_up_every_database_and_flags_missing_files() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"name": "Ada"}));
+        db.insert("people", json!({"name": "Grace"}));
+        db.create("notes");
+        db.insert("notes", json!({"text": "hello"}));
+        let size = |db_name| fs::metadata(db.get_db_path("alice", db_name).unwrap()).unwrap().len();
+
+        let usage = db.usage("alice", &["people", "notes", "ghost"]).unwrap();
+
+        assert_eq!(usage["databases"][0]["size_bytes"], size("people"));
+        assert_eq!(usage["databases"][0]["records"], 2);
+        assert_eq!(usage["databases"][1]["size_bytes"], size("notes"));
+        assert_eq!(usage["databases"][1]["records"], 1);
+        assert_eq!(usage["databases"][2]["missing"], true);
+        assert_eq!(usage["databases"][2]["size_bytes"], 0);
+        assert_eq!(usage["totals"], json!({
+            "databases": 3,
+            "size_bytes": size("people") + size("notes"),
+            "records": 3,
+            "missing_files": 1,
+        }));
+    }
+
+    #[test]
+    fn writes_past_the_quota_are_rejected_and_leave_the_file_alone() {
+        let db = TestDb::with_config(DatabaseConfig { user_quota: Some(1024), ..Default::default() });
+        db.create("people");
+        db.insert("people", json!({"name": "Ada"}));
+        let path = db.get_db_path("alice", "people").unwrap();
+        let before = fs::read(&path).unwrap();
+
+        let error = db.insert_record("alice", "people", "alice", json!({"bio": "x".repeat(2048)})).unwrap_err();
+
+        assert!(matches!(error, DbError::TooLarge(message) if message.starts_with("Storage quota exceeded")));
+        assert_eq!(fs::read(&path).unwrap(), before);
+        db.insert("people", json!({"name": "Grace"}));
+        assert_eq!(db.records("people").len(), 2);
+    }
+
+    fn patch(db: &TestDb, id: &str, body: Value) -> Result<WriteOutcome, DbError> {
+        let ops = PatchOp::parse_all(body)?;
+        db.update_records("alice", "people", "alice", UpdateTarget::Id(id), Value::Null, UpdateMode::Patch(&ops), false)
+    }
+
+    #[test]
+    fn patch_adds_replaces_and_removes_nested_values() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "ada", "address": {"city": "London", "zip": "N1"}, "tags": ["math"]}));
+
+        patch(&db, "ada", json!([
+            {"op": "replace", "path": "/address/city", "value": "Marylebone"},
+            {"op": "add", "path": "/address/street", "value": "St James's Square"},
+            {"op": "remove", "path": "/address/zip"},
+            {"op": "add", "path": "/tags/-", "value": "poetry"},
+            {"op": "add", "path": "/tags/0", "value": "engines"},
+        ])).unwrap();
+
+        let record = &db.records("people")[0];
+        assert_eq!(record["address"], json!({"city": "Marylebone", "street": "St James's Square"}));
+        assert_eq!(record["tags"], json!(["engines", "math", "poetry"]));
+    }
+
+    #[test]
+    fn a_failing_patch_step_leaves_the_record_unchanged() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"_id": "ada", "address": {"city": "London"}}));
+
+        let error = patch(&db, "ada", json!([
+            {"op": "replace", "path": "/address/city", "value": "Paris"},
+            {"op": "remove", "path": "/address/zip"},
+        ])).unwrap_err();
+
+        assert!(matches!(error, DbError::BadRequest(message) if message.contains("Patch path '/address/zip' does not exist")));
+        assert_eq!(db.records("people")[0]["address"]["city"], "London");
+        assert!(matches!(patch(&db, "ada", json!([{"op": "remove", "path": "/_id"}])), Err(DbError::BadRequest(_))));
+    }
+
+    #[test]
+    fn whitespace_queries_return_everything_on_get_and_are_refused_on_delete() {
+        let db = TestDb::new();
+        with_people(&db, 3);
+
+        let found = db.search_records("alice", "people", &SearchOptions { query: Some("   "), ..SearchOptions::default() }).unwrap();
+        let error = db.delete_records("alice", "people", " \t ", true, false).unwrap_err();
+
+        assert_eq!(found.len(), 3);
+        assert!(matches!(error, DbError::BadRequest(message) if message == "Delete query cannot be empty or whitespace"));
+        assert_eq!(db.records("people").len(), 3);
+    }
+
+    #[test]
+    fn tracked_databases_stamp_the_acting_user_on_inserts_and_updates() {
+        let db = TestDb::new();
+        db.create("shared");
+        db.update_settings("alice", "shared", &json!({"track_authors": true})).unwrap();
+
+        db.insert_record("alice", "shared", "bob", json!({"_id": "a", "note": "draft"})).unwrap();
+        assert_eq!(db.records("shared")[0]["_created_by"], "bob");
+        assert!(db.records("shared")[0].get("_updated_by").is_none());
+
+        db.update_records("alice", "shared", "carol", UpdateTarget::Id("a"), json!({"note": "final"}), UpdateMode::Merge, false).unwrap();
+        let record = &db.records("shared")[0];
+        assert_eq!(record["_created_by"], "bob");
+        assert_eq!(record["_updated_by"], "carol");
+    }
+
+    #[test]
+    fn untracked_databases_carry_no_authors() {
+        let db = TestDb::new();
+        db.create("private");
+
+        db.insert_record("alice", "private", "bob", json!({"_id": "a"})).unwrap();
+        db.update_records("alice", "private", "carol", UpdateTarget::Id("a"), json!({"note": "x"}), UpdateMode::Merge, false).unwrap();
+
+        let record = &db.records("private")[0];
+        assert!(record.get("_created_by").is_none());
+        assert!(record.get("_updated_by").is_none());
+    }
+
+    #[test]
+    fn gzip_storage_round_trips_the_records() {
+        let db = TestDb::new();
+        with_people(&db, 20);
+        let plain = db.get_db_path("alice", "people").unwrap();
+        let gzipped = format!("{}.gz", plain);
+
+        db.update_settings("alice", "people", &json!({"gzip": true})).unwrap();
+        db.insert("people", json!({"name": "person20"}));
+
+        assert!(!Path::new(&plain).exists());
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(fs::File::open(&gzipped).unwrap()), &mut decoded).unwrap();
+        let stored: Vec<Value> = serde_json::from_str(&decoded).unwrap();
+        assert_eq!(stored.len(), 21);
+        db.invalidate_cache("alice", "people");
+        assert_eq!(db.records("people"), stored);
+        let stats = db.get_stats("alice", "people").unwrap();
+        assert_eq!(stats["compressed"], true);
+        assert_eq!(stats["uncompressed_bytes"], decoded.len());
+        assert!(stats["size_bytes"].as_u64().unwrap() < decoded.len() as u64);
+
+        db.update_settings("alice", "people", &json!({"gzip": false})).unwrap();
+        assert!(!Path::new(&gzipped).exists());
+        assert_eq!(serde_json::from_str::<Vec<Value>>(&fs::read_to_string(&plain).unwrap()).unwrap(), stored);
+    }
+
+    #[test]
+    fn v7_ids_sort_in_insertion_order() {
+        let db = TestDb::with_config(DatabaseConfig { id_version: IdVersion::V7, ..Default::default() });
+        with_people(&db, 50);
+
+        let ids: Vec<String> = db.records("people").iter()
+            .map(|record| record["_id"].as_str().unwrap().to_string())
+            .collect();
+
+        assert!(ids.iter().all(|id| Uuid::parse_str(id).unwrap().get_version_num() == 7));
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", ids);
+        assert_eq!(IdVersion::default(), IdVersion::V4);
+    }
+
+    #[test]
+    fn flattening_turns_nesting_and_arrays_into_dotted_keys() {
+        let record = json!({
+            "name": "Ada",
+            "address": {"city": "London", "geo": {"lat": 51.5, "lng": -0.1}},
+            "tags": ["math", {"kind": "poetry"}],
+            "empty": {},
+            "none": [],
+        });
+
+        assert_eq!(DatabaseManager::flatten_value(&record), json!({
+            "name": "Ada",
+            "address.city": "London",
+            "address.geo.lat": 51.5,
+            "address.geo.lng": -0.1,
+            "tags.0": "math",
+            "tags.1.kind": "poetry",
+            "empty": {},
+            "none": [],
+        }));
+        assert_eq!(DatabaseManager::flatten_value(&json!([1, 2])), json!([1, 2]));
+    }
+
+    #[test]
+    fn dedupe_by_fields_keeps_the_first_of_each_group() {
+        let db = TestDb::new();
+        db.create("people");
+        for (name, email, city) in [("Ada", "ada@x", "London"), ("Ada L.", "ada@x", "Paris"), ("Grace", "grace@x", "NYC"), ("Grace H.", "grace@x", "NYC")] {
+            db.insert("people", json!({"name": name, "email": email, "city": city}));
+        }
+        db.insert("people", json!({"name": "No email"}));
+
+        let by_both = db.dedupe_records("alice", "people", &["email", "city"], false, true).unwrap();
+        assert_eq!(by_both.count, 1);
+        assert_eq!(by_both.affected[0]["name"], "Grace H.");
+
+        let by_email = db.dedupe_records("alice", "people", &["email"], false, false).unwrap();
+        assert_eq!(by_email.count, 1);
+        let names: Vec<Value> = db.records("people").iter().map(|record| record["name"].clone()).collect();
+        // Records missing a field are never duplicates
+        assert_eq!(names, [json!("Ada"), json!("Grace"), json!("No email")]);
+    }
+
+    #[test]
+    fn dedupe_without_fields_compares_whole_records_ignoring_metadata() {
+        let db = TestDb::new();
+        db.create("people");
+        db.insert("people", json!({"name": "Ada", "born": 1815}));
+        db.insert("people", json!({"born": 1815, "name": "Ada"}));
+        db.insert("people", json!({"name": "Ada", "born": 1816}));
+
+        let outcome = db.dedupe_records("alice", "people", &[], false, false).unwrap();
+        let again = db.dedupe_records("alice", "people", &[], false, false).unwrap();
+
+        assert_eq!(outcome.count, 1);
+        assert_eq!(outcome.message, "Removed 1 duplicate records");
+        assert_eq!(again.count, 0);
+        let born: Vec<Value> = db.records("people").iter().map(|record| record["born"].clone()).collect();
+        assert_eq!(born, [json!(1815), json!(1816)]);
+    }
+}
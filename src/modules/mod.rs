@@ -1,4 +1,5 @@
 pub mod search;
 pub mod auth;
 pub mod database;
-pub mod server;
\ No newline at end of file
+pub mod server;
+pub mod config;
\ No newline at end of file
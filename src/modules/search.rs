@@ -1,10 +1,16 @@
 use rayon::prelude::*;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde_json::Value;
-use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader};
+use flate2::bufread::GzDecoder;
+use memmap2::Mmap;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
 
 // Alias para el tipo de datos flexible
 pub type Item = Value;
@@ -12,10 +18,10 @@ pub type Item = Value;
 // ==================== CACHE SYSTEM ====================
 
 /// Cache entry with TTL
+/// Results are shared, so a cache hit only bumps a reference count
 #[derive(Clone)]
 struct CacheEntry {
-    query: String,
-    results: Vec<Value>,
+    results: Arc<Vec<Value>>,
     timestamp: u64,
     ttl_seconds: u64,
 }
@@ -34,36 +40,46 @@ impl CacheEntry {
 static SEARCH_CACHE: Lazy<Arc<Mutex<HashMap<String, CacheEntry>>>> = 
     Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
 
-/// Generate cache key from path and query
-fn cache_key(path: &str, query: &str) -> String {
-    format!("{}:{}", path, query)
+/// Modo de coincidencia por defecto: substring en cualquier valor
+pub const CONTAINS_MODE: &str = "contains";
+/// Coincidencia con una expresión regular sobre valores string
+pub const REGEX_MODE: &str = "regex";
+/// Coincidencia aproximada (Levenshtein) sobre valores string
+pub const FUZZY_MODE: &str = "fuzzy";
+/// Varios términos, todos deben aparecer en el item (queryLogic=and)
+pub const ALL_TERMS_MODE: &str = "all_terms";
+/// Varios términos, basta con que aparezca uno (queryLogic=or)
+pub const ANY_TERM_MODE: &str = "any_term";
+
+/// Generate cache key from path, match mode and query
+/// The mode keeps a regex and a plain substring with the same text apart
+fn cache_key(path: &str, mode: &str, query: &str) -> String {
+    format!("{}:{}:{}", path, mode, query)
 }
 
 /// Get cached search results if valid
-pub fn get_cached_search(path: &str, query: &str) -> Option<Vec<Value>> {
+pub fn get_cached_search(path: &str, mode: &str, query: &str) -> Option<Arc<Vec<Value>>> {
     let cache = SEARCH_CACHE.lock().unwrap();
-    let key = cache_key(path, query);
+    let key = cache_key(path, mode, query);
     
-    if let Some(entry) = cache.get(&key) {
-        if entry.is_valid() {
-            return Some(entry.results.clone());
-        }
+    if let Some(entry) = cache.get(&key)
+        && entry.is_valid() {
+        return Some(Arc::clone(&entry.results));
     }
     None
 }
 
 /// Store search results in cache
-pub fn cache_search_results(path: &str, query: &str, results: Vec<Value>, ttl_seconds: u64) {
+pub fn cache_search_results(path: &str, mode: &str, query: &str, results: Arc<Vec<Value>>, ttl_seconds: u64) {
     let mut cache = SEARCH_CACHE.lock().unwrap();
-    let key = cache_key(path, query);
+    let key = cache_key(path, mode, query);
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs();
     
     cache.insert(key, CacheEntry {
-        query: query.to_string(),
-        results: results.clone(),
+        results,
         timestamp,
         ttl_seconds,
     });
@@ -80,6 +96,25 @@ pub fn invalidate_cache_for_path(path: &str) {
     cache.retain(|key, _| !key.starts_with(&format!("{}:", path)));
 }
 
+/// Remove expired entries, returns how many were evicted
+/// The lock is only held to take them out; the results are dropped after it is released
+pub fn evict_expired_searches() -> usize {
+    let expired: Vec<CacheEntry> = {
+        let mut cache = SEARCH_CACHE.lock().unwrap();
+        let keys: Vec<String> = cache.iter()
+            .filter(|(_, entry)| !entry.is_valid())
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter().filter_map(|key| cache.remove(key)).collect()
+    };
+    expired.len()
+}
+
+/// Number of entries currently held in the search cache
+pub fn search_cache_len() -> usize {
+    SEARCH_CACHE.lock().unwrap().len()
+}
+
 /// Clear entire search cache
 pub fn clear_search_cache() {
     let mut cache = SEARCH_CACHE.lock().unwrap();
@@ -88,17 +123,242 @@ pub fn clear_search_cache() {
 
 // ==================== DATA LOADING ====================
 
-pub fn load_json(path: &str) -> Vec<Item> {
-    let data = fs::read_to_string(path).expect("No se pudo leer el archivo JSON");
-    serde_json::from_str::<Vec<Value>>(&data).expect("Error al parsear JSON")
+/// Lee un array JSON desde disco
+/// Parsea directamente desde un lector con buffer, sin cargar el archivo en un String
+/// (el pico de memoria es el de los valores parseados, no el doble del archivo)
+pub fn load_json(path: &str) -> Result<Vec<Item>, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Could not read '{}': {}", path, e))?;
+    serde_json::from_reader::<_, Vec<Value>>(BufReader::new(file))
+        .map_err(|e| format!("Invalid JSON in '{}': {}", path, e))
+}
+
+/// Igual que `load_json` pero parseando desde el archivo mapeado en memoria
+/// Evita las copias del buffer de lectura: el SO pagina el archivo bajo demanda
+/// y puede liberar esas páginas, útil para bases grandes de solo lectura.
+/// El mapeo solo vive durante el parseo; si otro proceso trunca el archivo
+/// mientras tanto el proceso puede recibir SIGBUS, así que las escrituras
+/// deben estar serializadas con la lectura.
+pub fn load_json_mmap(path: &str) -> Result<Vec<Item>, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Could not read '{}': {}", path, e))?;
+    // SAFETY: el archivo solo se modifica con escrituras completas del servidor,
+    // nunca mientras dura este parseo
+    let mmap = unsafe { Mmap::map(&file) }
+        .map_err(|e| format!("Could not map '{}': {}", path, e))?;
+    serde_json::from_slice::<Vec<Value>>(&mmap)
+        .map_err(|e| format!("Invalid JSON in '{}': {}", path, e))
+}
+
+/// Igual que `load_json` para bases comprimidas (`<db>.json.gz`)
+/// Se descomprime en streaming mientras se parsea, el JSON completo nunca está en memoria
+pub fn load_json_gz(path: &str) -> Result<Vec<Item>, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Could not read '{}': {}", path, e))?;
+    serde_json::from_reader::<_, Vec<Value>>(BufReader::new(GzDecoder::new(BufReader::new(file))))
+        .map_err(|e| format!("Invalid JSON in '{}': {}", path, e))
+}
+
+/// Tamaño del JSON dentro de un archivo gzip, descomprimiéndolo sin guardar el resultado
+pub fn gzip_uncompressed_size(path: &str) -> Result<u64, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Could not read '{}': {}", path, e))?;
+    io::copy(&mut GzDecoder::new(BufReader::new(file)), &mut io::sink())
+        .map_err(|e| format!("Invalid gzip data in '{}': {}", path, e))
+}
+
+/// Una página de un array JSON y los registros contados mientras se recorría
+#[derive(Debug, Default)]
+pub struct JsonPage {
+    /// Registros aceptados por `keep` a partir de `offset`, como máximo `limit`
+    pub records: Vec<Item>,
+    /// Registros aceptados por `keep` en todo el archivo
+    pub kept: usize,
+    /// Registros en todo el archivo
+    pub total: usize,
+}
+
+/// Lee una página de un archivo JSON (o `.json.gz`) sin construir el resto de registros
+/// Solo los registros de la página se convierten en `Value`; de los demás se lee únicamente
+/// `probe_field`, que `keep` usa para decidir si cuentan (y si pueden entrar en la página).
+/// El archivo se recorre entero para que `kept` y `total` sean exactos.
+pub fn load_json_page<F>(path: &str, offset: usize, limit: usize, probe_field: &str, keep: F) -> Result<JsonPage, String>
+where
+    F: Fn(Option<&Value>) -> bool,
+{
+    let file = File::open(path)
+        .map_err(|e| format!("Could not read '{}': {}", path, e))?;
+    let page = PageVisitor { offset, limit, probe_field, keep };
+    let result = if path.ends_with(".gz") {
+        let reader = BufReader::new(GzDecoder::new(BufReader::new(file)));
+        page.deserialize(&mut serde_json::Deserializer::from_reader(reader))
+    } else {
+        page.deserialize(&mut serde_json::Deserializer::from_reader(BufReader::new(file)))
+    };
+    result.map_err(|e| format!("Invalid JSON in '{}': {}", path, e))
+}
+
+// Recorre el array decidiendo registro a registro si se construye entero o solo se sondea
+struct PageVisitor<'a, F> {
+    offset: usize,
+    limit: usize,
+    probe_field: &'a str,
+    keep: F,
+}
+
+impl<'de, F: Fn(Option<&Value>) -> bool> DeserializeSeed<'de> for PageVisitor<'_, F> {
+    type Value = JsonPage;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<JsonPage, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: Fn(Option<&Value>) -> bool> Visitor<'de> for PageVisitor<'_, F> {
+    type Value = JsonPage;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON array of records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<JsonPage, A::Error> {
+        let mut page = JsonPage::default();
+        loop {
+            let in_page = page.kept >= self.offset && page.records.len() < self.limit;
+            if in_page {
+                let Some(record) = seq.next_element::<Value>()? else { break };
+                page.total += 1;
+                if (self.keep)(record.get(self.probe_field)) {
+                    page.kept += 1;
+                    page.records.push(record);
+                }
+            } else {
+                let Some(probed) = seq.next_element_seed(FieldProbe(self.probe_field))? else { break };
+                page.total += 1;
+                if (self.keep)(probed.as_ref()) {
+                    page.kept += 1;
+                }
+            }
+        }
+        Ok(page)
+    }
 }
 
+/// Recorre un archivo JSON (o `.json.gz`) registro a registro sin cargarlo entero
+/// Cada registro se construye, se pasa a `visit` y se suelta antes de leer el siguiente,
+/// así que la memoria depende de lo que `visit` se quede, no del tamaño del archivo
+pub fn for_each_json_record<F: FnMut(Item)>(path: &str, visit: F) -> Result<(), String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Could not read '{}': {}", path, e))?;
+    let records = RecordVisitor(visit);
+    let result = if path.ends_with(".gz") {
+        let reader = BufReader::new(GzDecoder::new(BufReader::new(file)));
+        records.deserialize(&mut serde_json::Deserializer::from_reader(reader))
+    } else {
+        records.deserialize(&mut serde_json::Deserializer::from_reader(BufReader::new(file)))
+    };
+    result.map_err(|e| format!("Invalid JSON in '{}': {}", path, e))
+}
+
+// Entrega cada elemento del array a la función en cuanto se ha leído
+struct RecordVisitor<F>(F);
+
+impl<'de, F: FnMut(Item)> DeserializeSeed<'de> for RecordVisitor<F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(Item)> Visitor<'de> for RecordVisitor<F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON array of records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(record) = seq.next_element::<Value>()? {
+            (self.0)(record);
+        }
+        Ok(())
+    }
+}
+
+// Lee un registro guardando solo el valor de un campo de primer nivel; el resto se descarta sin copiarlo
+struct FieldProbe<'a>(&'a str);
+
+impl<'de> DeserializeSeed<'de> for FieldProbe<'_> {
+    type Value = Option<Value>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Option<Value>, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FieldProbe<'_> {
+    type Value = Option<Value>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Option<Value>, A::Error> {
+        let mut found = None;
+        while let Some(is_probe) = map.next_key_seed(KeyIs(self.0))? {
+            if is_probe {
+                found = Some(map.next_value::<Value>()?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(found)
+    }
+
+    // Un registro que no es un objeto no tiene el campo
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Option<Value>, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(None)
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Option<Value>, E> { Ok(None) }
+    fn visit_i64<E>(self, _: i64) -> Result<Option<Value>, E> { Ok(None) }
+    fn visit_u64<E>(self, _: u64) -> Result<Option<Value>, E> { Ok(None) }
+    fn visit_f64<E>(self, _: f64) -> Result<Option<Value>, E> { Ok(None) }
+    fn visit_str<E>(self, _: &str) -> Result<Option<Value>, E> { Ok(None) }
+    fn visit_unit<E>(self) -> Result<Option<Value>, E> { Ok(None) }
+}
+
+// Compara una clave con el campo buscado sin reservar un String para ella
+struct KeyIs<'a>(&'a str);
+
+impl<'de> DeserializeSeed<'de> for KeyIs<'_> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeyIs<'_> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a key")
+    }
+
+    fn visit_str<E>(self, key: &str) -> Result<bool, E> {
+        Ok(key == self.0)
+    }
+}
 
 // ==================== NODE SPLITTING ====================
 
 /// Divide datos en chunks optimizados para procesamiento paralelo
 /// Usa el número de CPUs disponibles para maximizar el uso del procesador
-pub fn split_nodes(items: Vec<Item>, num_nodes: usize) -> Vec<Vec<Item>> {
+/// Los nodos son vistas sobre `items`, no se copia ningún registro
+pub fn split_nodes(items: &[Item], num_nodes: usize) -> Vec<&[Item]> {
     // Si num_nodes es 0, usar el número de CPUs lógicos disponibles
     let optimal_nodes = if num_nodes == 0 {
         rayon::current_num_threads()
@@ -106,48 +366,100 @@ pub fn split_nodes(items: Vec<Item>, num_nodes: usize) -> Vec<Vec<Item>> {
         num_nodes
     };
     
-    let chunk_size = (items.len() as f64 / optimal_nodes as f64).ceil() as usize;
-    items.chunks(chunk_size).map(|c| c.to_vec()).collect()
+    // chunks() no acepta 0, lo que pasaría con un dataset vacío
+    let chunk_size = ((items.len() as f64 / optimal_nodes as f64).ceil() as usize).max(1);
+    items.chunks(chunk_size).collect()
 }
 
 // ==================== SEARCH FUNCTIONS ====================
 
+/// Cómo se compara cada valor hoja con la consulta
+enum Matcher<'a> {
+    /// Substring sobre strings, números y booleanos
+    Contains(&'a str),
+    /// Regex solo sobre strings, el resto de valores se ignora
+    Regex(&'a Regex),
+    /// Strings a distancia de edición <= max de la consulta
+    Fuzzy { query: &'a str, max_distance: usize },
+}
+
 /// Función recursiva optimizada para buscar en cualquier valor JSON
 /// Usa early returns para mejorar performance
-fn search_in_json_value(value: &Value, query: &str) -> bool {
-    match value {
-        Value::String(s) => s.contains(query),
-        Value::Number(n) => n.to_string().contains(query),
-        Value::Bool(b) => b.to_string().contains(query),
-        Value::Array(arr) => {
+fn search_in_json_value(value: &Value, matcher: &Matcher) -> bool {
+    match (value, matcher) {
+        (Value::String(s), Matcher::Contains(query)) => s.contains(query),
+        (Value::String(s), Matcher::Regex(regex)) => regex.is_match(s),
+        (Value::String(s), Matcher::Fuzzy { query, max_distance }) => within_distance(s, query, *max_distance),
+        (Value::Number(n), Matcher::Contains(query)) => n.to_string().contains(query),
+        (Value::Bool(b), Matcher::Contains(query)) => b.to_string().contains(query),
+        (Value::Array(arr), _) => {
             // Early return: detiene en el primer match encontrado
             for item in arr {
-                if search_in_json_value(item, query) {
+                if search_in_json_value(item, matcher) {
                     return true;
                 }
             }
             false
         },
-        Value::Object(obj) => {
+        (Value::Object(obj), _) => {
             // Early return: detiene en el primer match encontrado
             for value in obj.values() {
-                if search_in_json_value(value, query) {
+                if search_in_json_value(value, matcher) {
                     return true;
                 }
             }
             false
         },
-        Value::Null => false,
+        _ => false,
     }
 }
 
 /// Buscar en un item completo
 fn item_contains_value(item: &Item, query: &str) -> bool {
-    search_in_json_value(item, query)
+    search_in_json_value(item, &Matcher::Contains(query))
+}
+
+/// Longitud máxima aceptada para un patrón regex
+pub const REGEX_MAX_PATTERN_LEN: usize = 512;
+/// Tamaño máximo del programa compilado de una regex (bytes)
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Compila un patrón de usuario con límites de tamaño, un patrón inválido es un error y no un panic
+pub fn compile_regex(pattern: &str) -> Result<Regex, String> {
+    if pattern.len() > REGEX_MAX_PATTERN_LEN {
+        return Err(format!("Regex pattern is too long (max {} characters)", REGEX_MAX_PATTERN_LEN));
+    }
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("Invalid regex pattern: {}", e))
+}
+
+/// Distancia de Levenshtein acotada; la diferencia de longitudes descarta rápido
+fn within_distance(value: &str, query: &str, max_distance: usize) -> bool {
+    value.chars().count().abs_diff(query.chars().count()) <= max_distance
+        && strsim::levenshtein(value, query) <= max_distance
+}
+
+/// Búsqueda aproximada paralela multinodo, solo compara valores string completos
+pub fn fuzzy_search<'a>(nodes: &[&'a [Item]], query: &str, max_distance: usize) -> Vec<&'a Item> {
+    let matcher = Matcher::Fuzzy { query, max_distance };
+    nodes.par_iter()
+        .flat_map(|n| n.par_iter().filter(|item| search_in_json_value(item, &matcher)))
+        .collect()
+}
+
+/// Regex paralelo multinodo, solo compara valores string
+pub fn regex_search<'a>(nodes: &[&'a [Item]], regex: &Regex) -> Vec<&'a Item> {
+    let matcher = Matcher::Regex(regex);
+    nodes.par_iter()
+        .flat_map(|n| n.par_iter().filter(|item| search_in_json_value(item, &matcher)))
+        .collect()
 }
 
 /// Búsqueda en un solo nodo (secuencial dentro del nodo)
-pub fn search_node<'a>(node: &'a Vec<Item>, query: &str) -> Vec<&'a Item> {
+pub fn search_node<'a>(node: &'a [Item], query: &str) -> Vec<&'a Item> {
     node.iter()
         .filter(|item| item_contains_value(item, query))
         .collect()
@@ -156,7 +468,7 @@ pub fn search_node<'a>(node: &'a Vec<Item>, query: &str) -> Vec<&'a Item> {
 // ==================== SEARCH MODES ====================
 
 /// Centralizado: todos los datos en un vector (para datasets pequeños)
-pub fn centralized_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a Item> {
+pub fn centralized_search<'a>(nodes: &[&'a [Item]], query: &str) -> Vec<&'a Item> {
     let all: Vec<&Item> = nodes.iter().flat_map(|n| n.iter()).collect();
     all.into_iter()
         .filter(|item| item_contains_value(item, query))
@@ -164,7 +476,7 @@ pub fn centralized_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a
 }
 
 /// Secuencial multinodo (para datasets pequeños sin overhead de threading)
-pub fn sequential_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a Item> {
+pub fn sequential_search<'a>(nodes: &[&'a [Item]], query: &str) -> Vec<&'a Item> {
     nodes.iter()
         .flat_map(|n| search_node(n, query))
         .collect()
@@ -172,9 +484,84 @@ pub fn sequential_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a
 
 /// Paralelo multinodo optimizado (usa todos los cores del CPU)
 /// Esta es la opción recomendada para datasets grandes
-pub fn parallel_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a Item> {
+/// Cada nodo también se recorre en paralelo, así un reparto desigual
+/// (pocos nodos grandes) no deja cores sin trabajo; el orden se conserva
+pub fn parallel_search<'a>(nodes: &[&'a [Item]], query: &str) -> Vec<&'a Item> {
     nodes.par_iter()
-        .flat_map(|n| search_node(n, query))
+        .flat_map(|n| n.par_iter().filter(|item| item_contains_value(item, query)))
+        .collect()
+}
+
+/// Igual que `smart_search` pero deja de recoger resultados al llegar a `max_results`
+/// En paralelo un contador atómico compartido entre las tareas de Rayon corta la evaluación
+/// del resto de items; qué matches se devuelven depende del reparto entre hilos
+pub fn smart_search_limited<'a>(nodes: &[&'a [Item]], query: &str, max_results: usize) -> Vec<&'a Item> {
+    let total_items: usize = nodes.iter().map(|n| n.len()).sum();
+
+    if total_items < parallel_threshold() {
+        nodes.iter()
+            .flat_map(|n| n.iter())
+            .filter(|item| item_contains_value(item, query))
+            .take(max_results)
+            .collect()
+    } else {
+        parallel_search_limited(nodes, query, max_results)
+    }
+}
+
+/// Igual que `smart_search_limited` pero siempre con los primeros `max_results` matches en el orden
+/// de los datos, así la misma búsqueda devuelve la misma secuencia y se puede paginar sobre ella.
+/// Los nodos se recorren en orden, cada uno en paralelo, parando al completar el límite: corta
+/// antes que una búsqueda completa, aunque en general más tarde que `parallel_search_limited`
+pub fn ordered_search_limited<'a>(nodes: &[&'a [Item]], query: &str, max_results: usize) -> Vec<&'a Item> {
+    let total_items: usize = nodes.iter().map(|n| n.len()).sum();
+    if total_items < parallel_threshold() {
+        // El recorrido secuencial ya es ordenado
+        return smart_search_limited(nodes, query, max_results);
+    }
+
+    let mut results = Vec::new();
+    for node in nodes {
+        if results.len() >= max_results {
+            break;
+        }
+        // collect conserva el orden del nodo aunque se filtre en paralelo
+        results.extend(node.par_iter().filter(|item| item_contains_value(item, query)).collect::<Vec<_>>());
+    }
+    results.truncate(max_results);
+    results
+}
+
+/// Paralelo multinodo con límite de resultados (ver `smart_search_limited`)
+pub fn parallel_search_limited<'a>(nodes: &[&'a [Item]], query: &str, max_results: usize) -> Vec<&'a Item> {
+    let found = AtomicUsize::new(0);
+    nodes.par_iter()
+        .flat_map(|n| n.par_iter().filter(|item| {
+            found.load(Ordering::Relaxed) < max_results
+                && item_contains_value(item, query)
+                && found.fetch_add(1, Ordering::Relaxed) < max_results
+        }))
+        .collect()
+}
+
+/// Separa una consulta en términos por espacios o comas, descartando los vacíos
+pub fn split_terms(query: &str) -> Vec<&str> {
+    query.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+/// Paralelo multinodo con varios términos: `all` exige todos en el mismo item, si no basta uno
+/// Cada término puede aparecer en un valor distinto del item
+pub fn multi_term_search<'a>(nodes: &[&'a [Item]], terms: &[&str], all: bool) -> Vec<&'a Item> {
+    nodes.par_iter()
+        .flat_map(|n| n.par_iter().filter(|item| {
+            if all {
+                terms.iter().all(|term| item_contains_value(item, term))
+            } else {
+                terms.iter().any(|term| item_contains_value(item, term))
+            }
+        }))
         .collect()
 }
 
@@ -184,35 +571,131 @@ pub fn parallel_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a It
 /// Primero busca en cache, si no existe realiza búsqueda paralela y cachea el resultado
 pub fn cached_parallel_search(
     path: &str,
-    nodes: &Vec<Vec<Item>>,
+    nodes: &[&[Item]],
     query: &str,
     ttl_seconds: u64
-) -> Vec<Value> {
+) -> Arc<Vec<Value>> {
     // Intenta obtener del cache
-    if let Some(cached) = get_cached_search(path, query) {
+    if let Some(cached) = get_cached_search(path, CONTAINS_MODE, query) {
         return cached;
     }
     
     // Si no está en cache, realiza búsqueda paralela
-    let results: Vec<Value> = parallel_search(nodes, query)
-        .into_iter()
-        .cloned()
-        .collect();
+    let results: Arc<Vec<Value>> = Arc::new(
+        parallel_search(nodes, query)
+            .into_iter()
+            .cloned()
+            .collect()
+    );
     
-    // Cachea los resultados
-    cache_search_results(path, query, results.clone(), ttl_seconds);
+    // Cachea los resultados (solo se comparte el Arc, sin copiar los registros)
+    cache_search_results(path, CONTAINS_MODE, query, Arc::clone(&results), ttl_seconds);
     
     results
 }
 
+/// Igual que `cached_parallel_search` pero eligiendo el método con `smart_search`
+/// Devuelve también si el resultado salió del cache
+pub fn cached_smart_search(
+    path: &str,
+    nodes: &[&[Item]],
+    query: &str,
+    ttl_seconds: u64
+) -> (Arc<Vec<Value>>, bool) {
+    cached_search(path, CONTAINS_MODE, query, ttl_seconds, || smart_search(nodes, query))
+}
+
+/// Búsqueda regex en paralelo con cache, `pattern` es el texto original de la regex
+pub fn cached_regex_search(
+    path: &str,
+    nodes: &[&[Item]],
+    pattern: &str,
+    regex: &Regex,
+    ttl_seconds: u64
+) -> (Arc<Vec<Value>>, bool) {
+    cached_search(path, REGEX_MODE, pattern, ttl_seconds, || regex_search(nodes, regex))
+}
+
+/// Búsqueda aproximada en paralelo con cache, la distancia forma parte de la clave
+pub fn cached_fuzzy_search(
+    path: &str,
+    nodes: &[&[Item]],
+    query: &str,
+    max_distance: usize,
+    ttl_seconds: u64
+) -> (Arc<Vec<Value>>, bool) {
+    let mode = format!("{}{}", FUZZY_MODE, max_distance);
+    cached_search(path, &mode, query, ttl_seconds, || fuzzy_search(nodes, query, max_distance))
+}
+
+/// Búsqueda de varios términos con cache, and y or se guardan con modos distintos
+pub fn cached_multi_term_search(
+    path: &str,
+    nodes: &[&[Item]],
+    query: &str,
+    all: bool,
+    ttl_seconds: u64
+) -> (Arc<Vec<Value>>, bool) {
+    let mode = if all { ALL_TERMS_MODE } else { ANY_TERM_MODE };
+    let terms = split_terms(query);
+    cached_search(path, mode, query, ttl_seconds, || multi_term_search(nodes, &terms, all))
+}
+
+/// Devuelve el resultado cacheado o ejecuta `search` y lo guarda
+fn cached_search<'a>(
+    path: &str,
+    mode: &str,
+    query: &str,
+    ttl_seconds: u64,
+    search: impl FnOnce() -> Vec<&'a Item>
+) -> (Arc<Vec<Value>>, bool) {
+    if let Some(cached) = get_cached_search(path, mode, query) {
+        return (cached, true);
+    }
+
+    let results = uncached_results(search());
+    cache_search_results(path, mode, query, Arc::clone(&results), ttl_seconds);
+
+    (results, false)
+}
+
+/// Resultados de una búsqueda sin pasar por el cache (noCache), en el mismo formato que los cacheados
+pub fn uncached_results(items: Vec<&Item>) -> Arc<Vec<Value>> {
+    Arc::new(items.into_iter().cloned().collect())
+}
+
+/// Umbral por defecto: datasets por debajo de este tamaño se recorren en secuencial
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 1000;
+
+static PARALLEL_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_PARALLEL_THRESHOLD);
+
+/// Cambia el umbral secuencial/paralelo de `smart_search`
+/// Con documentos grandes conviene bajarlo: cada item cuesta más y el paralelo compensa antes
+pub fn configure_parallel_threshold(threshold: usize) {
+    PARALLEL_THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+/// Umbral actual de `smart_search`
+pub fn parallel_threshold() -> usize {
+    PARALLEL_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Método que usará `smart_search` para este número de items ("sequential" o "parallel")
+pub fn smart_search_method(total_items: usize) -> &'static str {
+    if total_items < parallel_threshold() {
+        "sequential"
+    } else {
+        "parallel"
+    }
+}
+
 /// Búsqueda inteligente que elige el mejor método según el tamaño del dataset
-pub fn smart_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a Item> {
+pub fn smart_search<'a>(nodes: &[&'a [Item]], query: &str) -> Vec<&'a Item> {
     let total_items: usize = nodes.iter().map(|n| n.len()).sum();
     
-    // Para datasets pequeños (<1000 items), usar secuencial
-    // Para datasets medianos (1000-10000), usar paralelo simple
-    // Para datasets grandes (>10000), usar paralelo con optimizaciones
-    if total_items < 1000 {
+    // Para datasets pequeños (por debajo del umbral, 1000 por defecto), usar secuencial
+    // Para el resto, paralelo sobre todos los cores
+    if total_items < parallel_threshold() {
         sequential_search(nodes, query)
     } else {
         parallel_search(nodes, query)
@@ -221,19 +704,161 @@ pub fn smart_search<'a>(nodes: &'a Vec<Vec<Item>>, query: &str) -> Vec<&'a Item>
 
 // ==================== UTILITIES ====================
 
+/// Sorted identity of a result set, used to check that search methods agree
+/// Records are identified by `_id`, or by their JSON text when they have none
+pub fn result_fingerprint(results: &[&Item]) -> Vec<String> {
+    let mut ids: Vec<String> = results.iter()
+        .map(|item| match item.get("_id") {
+            Some(Value::String(id)) => id.clone(),
+            Some(id) => id.to_string(),
+            None => item.to_string(),
+        })
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
 /// Obtiene el número óptimo de nodos basado en el CPU
 pub fn get_optimal_node_count() -> usize {
     rayon::current_num_threads()
 }
 
-/// Configura el thread pool de Rayon para uso óptimo del CPU
-pub fn configure_thread_pool(num_threads: Option<usize>) {
+// Hilos del pool global una vez construido por configure_thread_pool
+static THREAD_POOL_SIZE: Mutex<Option<usize>> = Mutex::new(None);
+
+/// Construye el pool global de Rayon con `num_threads` hilos (None: uno por CPU lógico)
+/// Rayon solo permite construirlo una vez, así que las llamadas siguientes no lo tocan:
+/// devuelven el tamaño efectivo, o un error si pedían otro distinto. También es un error
+/// que el pool ya existiera (se crea solo al primer uso), y el mensaje incluye su tamaño.
+pub fn configure_thread_pool(num_threads: Option<usize>) -> Result<usize, String> {
+    let mut configured = THREAD_POOL_SIZE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(size) = *configured {
+        return match num_threads {
+            Some(requested) if requested != size => Err(format!(
+                "Thread pool already running with {} threads, {} requested", size, requested
+            )),
+            _ => Ok(size),
+        };
+    }
+
+    let mut builder = rayon::ThreadPoolBuilder::new();
     if let Some(threads) = num_threads {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(threads)
-            .build_global()
-            .unwrap_or_else(|_| {
-                eprintln!("Warning: Could not configure thread pool");
-            });
+        builder = builder.num_threads(threads);
+    }
+    let built = builder.build_global();
+    let size = rayon::current_num_threads();
+    *configured = Some(size);
+    built.map(|_| size)
+        .map_err(|e| format!("Could not configure the thread pool ({}), running with {} threads", e, size))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn configuring_the_thread_pool_again_reports_the_running_size() {
+        // La primera llamada puede encontrarse el pool ya creado por otro test, solo importa que no falle
+        let _ = configure_thread_pool(None);
+        let size = rayon::current_num_threads();
+
+        assert_eq!(configure_thread_pool(None), Ok(size));
+        assert_eq!(configure_thread_pool(Some(size)), Ok(size));
+        let error = configure_thread_pool(Some(size + 1)).unwrap_err();
+        assert!(error.contains(&format!("already running with {} threads", size)), "{}", error);
+    }
+
+    // Registros de ejemplo con valores string, numéricos y anidados
+    fn items() -> Vec<Item> {
+        vec![
+            json!({"_id": "1", "code": "T206", "name": "TensorFlow", "year": 2015}),
+            json!({"_id": "2", "code": "X100", "name": "PyTorch", "tags": ["T500", "ml"]}),
+            json!({"_id": "3", "code": 206, "name": "Tensor cores"}),
+        ]
+    }
+
+    fn ids(results: &[&Item]) -> Vec<String> {
+        results.iter().map(|item| item["_id"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn regex_search_matches_string_values_only() {
+        let items = items();
+        let nodes = split_nodes(&items, 2);
+
+        let regex = compile_regex("^T\\d+$").unwrap();
+        assert_eq!(ids(&regex_search(&nodes, &regex)), ["1", "2"]);
+
+        // 206 es un número, no se compara aunque su texto coincida
+        let digits = compile_regex("^206$").unwrap();
+        assert!(regex_search(&nodes, &digits).is_empty());
+    }
+
+    #[test]
+    fn invalid_or_oversized_patterns_are_errors() {
+        assert!(compile_regex("(unclosed").unwrap_err().starts_with("Invalid regex pattern"));
+        assert!(compile_regex(&"a".repeat(REGEX_MAX_PATTERN_LEN + 1)).unwrap_err().contains("too long"));
+    }
+
+    #[test]
+    fn fuzzy_search_finds_misspellings_within_the_distance() {
+        let items = items();
+        let nodes = split_nodes(&items, 3);
+
+        assert_eq!(ids(&fuzzy_search(&nodes, "TensrFlow", 2)), ["1"]);
+        assert!(fuzzy_search(&nodes, "TensrFlow", 0).is_empty());
+        assert_eq!(ids(&fuzzy_search(&nodes, "TensorFlow", 0)), ["1"]);
+    }
+
+    #[test]
+    fn a_lower_threshold_makes_small_datasets_parallel() {
+        let items = items();
+        let nodes = split_nodes(&items, 2);
+        assert_eq!(smart_search_method(items.len()), "sequential");
+        let sequential = ids(&smart_search(&nodes, "Tensor"));
+
+        configure_parallel_threshold(2);
+        let method = smart_search_method(items.len());
+        let parallel = ids(&smart_search(&nodes, "Tensor"));
+        configure_parallel_threshold(DEFAULT_PARALLEL_THRESHOLD);
+
+        assert_eq!(method, "parallel");
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn all_terms_must_appear_in_the_same_record() {
+        let items = items();
+        let nodes = split_nodes(&items, 2);
+        let terms = split_terms("Tensor, 2015");
+
+        assert_eq!(terms, ["Tensor", "2015"]);
+        assert_eq!(ids(&multi_term_search(&nodes, &terms, true)), ["1"]);
+    }
+
+    #[test]
+    fn any_term_is_enough_when_terms_are_ored() {
+        let items = items();
+        let nodes = split_nodes(&items, 2);
+
+        assert_eq!(ids(&multi_term_search(&nodes, &split_terms("PyTorch 2015"), false)), ["1", "2"]);
+        // Un solo término se comporta como la búsqueda normal
+        assert_eq!(ids(&multi_term_search(&nodes, &["Tensor"], true)), ids(&smart_search(&nodes, "Tensor")));
+    }
+
+    #[test]
+    fn every_search_method_returns_the_same_records() {
+        let items = items();
+        let nodes = split_nodes(&items, 2);
+
+        for query in ["T", "206", "ml", "2015", "nada"] {
+            let expected = result_fingerprint(&centralized_search(&nodes, query));
+            assert_eq!(result_fingerprint(&sequential_search(&nodes, query)), expected, "{}", query);
+            assert_eq!(result_fingerprint(&parallel_search(&nodes, query)), expected, "{}", query);
+            assert_eq!(result_fingerprint(&smart_search(&nodes, query)), expected, "{}", query);
+        }
+        // La huella no depende del orden de los resultados
+        let reversed: Vec<&Item> = items.iter().rev().collect();
+        assert_eq!(result_fingerprint(&reversed), ["1", "2", "3"]);
     }
 }
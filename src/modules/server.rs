@@ -1,18 +1,224 @@
 use warp::{Filter, Reply, Rejection};
+use warp::http::HeaderMap;
 use serde_json::Value;
 use std::collections::HashMap; 
-use crate::modules::auth::{AuthService, CreateUserRequest, CreateDbRequest};
-use crate::modules::database::DatabaseManager;
+use std::hash::Hasher;
+use bytes::{Buf, BufMut};
+use futures_util::{Stream, StreamExt};
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use once_cell::sync::{Lazy, OnceCell};
+use crate::modules::auth::{AccessLevel, AuthConfig, AuthService, CreateUserRequest, CreateDbRequest, GrantDbRequest, SetPublicRequest};
+use crate::modules::database::{AuditEntry, DatabaseConfig, DatabaseManager, DbError, EXPIRES_AT_FIELD, ListOptions, MAX_SEARCH_NODES, PatchOp, SearchOptions, StableHasher, TransactionOp, UpdateMode, UpdateTarget, WarmCacheRequest, WriteOutcome};
+use crate::modules::search::{evict_expired_searches, search_cache_len};
 
+// Server start time, used to report uptime
+static START_TIME: Lazy<Instant> = Lazy::new(Instant::now);
+
+// Crate version plus the commit and build time (unix seconds) set by build.rs
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("SARYCHDB_GIT_COMMIT");
+const BUILD_TIMESTAMP: &str = env!("SARYCHDB_BUILD_TIMESTAMP");
+
+// How long browsers may cache a CORS preflight when --cors-max-age is not set
+pub const DEFAULT_CORS_MAX_AGE: Duration = Duration::from_secs(600);
+
+static CORS_MAX_AGE: OnceCell<Duration> = OnceCell::new();
+
+// Set the preflight max-age once at startup
+pub fn configure_cors_max_age(max_age: Duration) {
+    let _ = CORS_MAX_AGE.set(max_age);
+}
+
+fn cors_max_age() -> Duration {
+    CORS_MAX_AGE.get().copied().unwrap_or(DEFAULT_CORS_MAX_AGE)
+}
+
+// Longest a /sarych operation may run before the request gets a 408, unset means no limit
+static OP_TIMEOUT: OnceCell<Duration> = OnceCell::new();
+
+// Set the operation timeout once at startup
+pub fn configure_op_timeout(limit: Duration) {
+    let _ = OP_TIMEOUT.set(limit);
+}
+
+fn op_timeout() -> Option<Duration> {
+    OP_TIMEOUT.get().copied()
+}
+
+/// Largest request body /sarych and import accept unless configured otherwise (16 MiB)
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+// Larger bodies get a 413 before they are buffered
+static MAX_BODY_SIZE: OnceCell<u64> = OnceCell::new();
+
+// Set the body size limit once at startup
+pub fn configure_max_body_size(limit: u64) {
+    let _ = MAX_BODY_SIZE.set(limit);
+}
+
+fn max_body_size() -> u64 {
+    MAX_BODY_SIZE.get().copied().unwrap_or(DEFAULT_MAX_BODY_SIZE)
+}
+
+// Request body capped at max_body_size: a larger Content-Length is refused without reading,
+// and a body sent without one (chunked) is dropped as soon as it passes the limit.
+// warp's content_length_limit would instead reject every request lacking Content-Length, bodyless GETs included
+fn limited_body() -> impl Filter<Extract = (bytes::Bytes,), Error = Rejection> + Clone {
+    warp::header::optional::<u64>("content-length")
+        .and(warp::body::stream())
+        .and_then(|length: Option<u64>, stream| collect_body(length, stream))
+}
+
+async fn collect_body<B: Buf>(length: Option<u64>, stream: impl Stream<Item = Result<B, warp::Error>>) -> Result<bytes::Bytes, Rejection> {
+    let limit = max_body_size();
+    if length.is_some_and(|length| length > limit) {
+        return Err(warp::reject::custom(RequestError::BodyTooLarge));
+    }
+    let mut stream = Box::pin(stream);
+    let mut body = bytes::BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| warp::reject::custom(RequestError::UnreadableBody))?;
+        if (body.len() + chunk.remaining()) as u64 > limit {
+            return Err(warp::reject::custom(RequestError::BodyTooLarge));
+        }
+        body.put(chunk);
+    }
+    Ok(body.freeze())
+}
+
+// The URL credentials are only checked for format, requests act as the header user
 #[derive(Debug)]
 pub struct SarychProtocol {
+    // User part of the URL, names the owner when a public database is read without credentials
     pub username: String,
-    pub password: String,
     pub database: String,
     pub operation: String,
     pub query: Option<String>,
 }
 
+// Optional headers accepted by the /sarych route
+#[derive(Debug, Default)]
+pub struct SarychHeaders {
+    pub query_type: Option<String>,
+    pub query_logic: Option<String>,
+    pub get_by_id: Option<String>,
+    pub max_distance: Option<String>,
+    pub max_results: Option<String>,
+    pub nodes: Option<String>,
+    pub id_update: Option<String>,
+    pub page: Option<String>,
+    pub limit: Option<String>,
+    pub sort_by: Option<String>,
+    pub sort_order: Option<String>,
+    pub filters: Option<String>,
+    pub replace_mode: Option<String>,
+    pub return_docs: bool,
+    pub confirm_bulk: bool,
+    pub confirm_truncate: Option<String>,
+    pub explain: bool,
+    pub force: bool,
+    pub stable_order: bool,
+    pub raw: bool,
+    pub pretty: bool,
+    pub flatten: bool,
+    pub no_cache: bool,
+    pub field: Option<String>,
+    pub amount: Option<String>,
+    pub search: Option<String>,
+    pub search_fields: Option<String>,
+    pub cursor: Option<String>,
+    pub expires_in: Option<String>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    pub format: Option<String>,
+    pub size: Option<String>,
+    pub seed: Option<String>,
+    pub if_none_match: Option<String>,
+}
+
+impl SarychHeaders {
+    /// Every request header the API reads; the CORS preflight allows exactly these
+    /// A header read in from_headers but missing here trips the debug assertion below
+    pub const NAMES: &'static [&'static str] = &[
+        // Credentials and body handling, also used by the /api routes
+        "content-type", "accept", "authorization", "username", "password", "mode",
+        // Search
+        "queryType", "queryLogic", "getById", "maxDistance", "maxResults", "nodes", "explain", "force", "stableOrder",
+        // Pagination and listing
+        "page", "limit", "sortBy", "sortOrder", "filters", "search", "searchFields", "cursor",
+        "createdAfter", "createdBefore",
+        // Sampling
+        "size", "seed",
+        // Writes
+        "idUpdate", "replaceMode", "returnDocs", "confirmBulk", "confirmTruncate", "field", "amount", "expiresIn",
+        // Output and caching
+        "raw", "pretty", "flatten", "format", "noCache", "cache-control", "if-none-match",
+    ];
+
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name: &str| {
+            debug_assert!(
+                Self::NAMES.iter().any(|allowed| allowed.eq_ignore_ascii_case(name)),
+                "header '{}' is read but missing from SarychHeaders::NAMES", name
+            );
+            headers.get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+
+        SarychHeaders {
+            query_type: get("queryType"),
+            query_logic: get("queryLogic"),
+            get_by_id: get("getById"),
+            max_distance: get("maxDistance"),
+            max_results: get("maxResults"),
+            nodes: get("nodes"),
+            id_update: get("idUpdate"),
+            page: get("page"),
+            limit: get("limit"),
+            sort_by: get("sortBy"),
+            sort_order: get("sortOrder"),
+            filters: get("filters"),
+            replace_mode: get("replaceMode"),
+            return_docs: get("returnDocs").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            confirm_bulk: get("confirmBulk").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            confirm_truncate: get("confirmTruncate"),
+            explain: get("explain").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            force: get("force").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            stable_order: get("stableOrder").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            raw: get("raw").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            // noCache: true, or the standard Cache-Control: no-cache
+            no_cache: get("noCache").is_some_and(|v| v.eq_ignore_ascii_case("true"))
+                || get("cache-control").is_some_and(|v| v.split(',').any(|d| d.trim().eq_ignore_ascii_case("no-cache"))),
+            // pretty: true, or a pretty=true parameter on Accept (application/json; pretty=true)
+            pretty: get("pretty").is_some_and(|v| v.eq_ignore_ascii_case("true"))
+                || get("accept").is_some_and(|accept| Self::accept_wants_pretty(&accept)),
+            flatten: get("flatten").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            field: get("field"),
+            amount: get("amount"),
+            search: get("search"),
+            search_fields: get("searchFields"),
+            cursor: get("cursor"),
+            expires_in: get("expiresIn"),
+            created_after: get("createdAfter"),
+            created_before: get("createdBefore"),
+            format: get("format"),
+            size: get("size"),
+            seed: get("seed"),
+            if_none_match: get("if-none-match"),
+        }
+    }
+
+    fn accept_wants_pretty(accept: &str) -> bool {
+        accept.split(',')
+            .flat_map(|media_range| media_range.split(';').skip(1))
+            .filter_map(|param| param.split_once('='))
+            .any(|(name, value)| name.trim().eq_ignore_ascii_case("pretty") && value.trim().trim_matches('"').eq_ignore_ascii_case("true"))
+    }
+}
+
 pub struct SarychServer {
 }
 
@@ -20,8 +226,6 @@ impl SarychServer {
 
     // Parsear el protocolo sarychdb://usuario@password/database/operacion?query=valor
     pub fn parse_sarych_url(url_str: &str) -> Result<SarychProtocol, String> {
-        println!("🔍 Parsing URL: {}", url_str);
-        
         // Verify it starts with sarychdb://
         if !url_str.starts_with("sarychdb://") {
             return Err("URL must start with sarychdb://".to_string());
@@ -29,7 +233,6 @@ impl SarychServer {
 
         // Remove protocol
         let without_protocol = url_str.strip_prefix("sarychdb://").unwrap();
-        println!("📝 Without protocol: {}", without_protocol);
 
         // Separar query parameters si existen
         let (main_part, query_string) = if let Some(pos) = without_protocol.find('?') {
@@ -39,11 +242,6 @@ impl SarychServer {
             (without_protocol, None)
         };
 
-        println!("🔧 Main part: {}", main_part);
-        if let Some(q) = query_string {
-            println!("🔍 Query string: {}", q);
-        }
-
         // Parse username@password/database/operation
         let parts: Vec<&str> = main_part.split('/').collect();
         
@@ -56,9 +254,9 @@ impl SarychServer {
         let database = parts[1].to_string();
         let operation = parts[2].to_string();
 
-        println!("🔐 Auth part: {}", auth_part);
-        println!("🗄️  Database: {}", database);
-        println!("⚡ Operation: {}", operation);
+        // Both segments end up in file paths or dispatch, never let them climb out of the user's folder
+        Self::validate_segment("database", &database)?;
+        Self::validate_segment("operation", &operation)?;
 
         // Separate username and password
         if !auth_part.contains('@') {
@@ -70,26 +268,23 @@ impl SarychServer {
             return Err("Invalid authentication format. Use: username@password".to_string());
         }
 
+        // Credentials come from the headers, only the owner part is used
         let username = auth_parts[0].to_string();
-        let password = auth_parts[1].to_string();
 
-        if username.is_empty() || password.is_empty() {
-            return Err("Username and password cannot be empty".to_string());
+        // The password may be empty for anonymous reads of a public database (sarychdb://owner@/db/get)
+        if username.is_empty() {
+            return Err("Username cannot be empty".to_string());
         }
 
-        println!("👤 Username: {}", username);
-        println!("🔑 Password: [HIDDEN]");
-
         // Parsear query parameters
         let query = if let Some(query_str) = query_string {
             // Buscar el parámetro "query"
             let mut found_query = None;
             for param in query_str.split('&') {
-                if let Some((key, value)) = param.split_once('=') {
-                    if key == "query" {
-                        found_query = Some(urlencoding::decode(value).map_err(|_| "Error decoding query")?.into_owned());
-                        break;
-                    }
+                if let Some((key, value)) = param.split_once('=')
+                    && key == "query" {
+                    found_query = Some(urlencoding::decode(value).map_err(|_| "Error decoding query")?.into_owned());
+                    break;
                 }
             }
             found_query
@@ -97,125 +292,429 @@ impl SarychServer {
             None
         };
 
-        if let Some(ref q) = query {
-            println!("🔎 Query: {}", q);
-        }
-
         Ok(SarychProtocol {
             username,
-            password,
             database,
             operation,
             query,
         })
     }
 
+    // Reject empty path segments and anything that could traverse directories
+    fn validate_segment(label: &str, value: &str) -> Result<(), String> {
+        if value.is_empty() {
+            return Err(format!("Invalid {} name: cannot be empty", label));
+        }
+        if value.contains("..") || value.contains('/') || value.contains('\\') || value.contains('\0') {
+            return Err(format!("Invalid {} name '{}': cannot contain '..', '/', '\\' or NUL", label, value));
+        }
+        Ok(())
+    }
+
     // Handle SarychDB protocol operations with header authentication
     pub async fn handle_sarych_request(
+        auth_service: Arc<AuthService>,
+        db_manager: Arc<DatabaseManager>,
         url_str: String, 
         body: Option<Value>, 
-        username: String, 
-        password: String,
-        query_type: Option<String>,
-        id_update: Option<String>,
-        page: Option<String>,
-        limit: Option<String>,
-        sort_by: Option<String>,
-        sort_order: Option<String>,
-        filters: Option<String>
-    ) -> Result<impl Reply, Rejection> {
+        username: Option<String>, 
+        password: Option<String>,
+        headers: SarychHeaders
+    ) -> Result<warp::reply::Response, Rejection> {
         let operation_start = std::time::Instant::now();
-        let auth_service = AuthService::new();
-        let db_manager = DatabaseManager::new();
         
         // Parse URL but ignore username/password from URL since we use headers
         let protocol = match Self::parse_sarych_url(&url_str) {
             Ok(p) => p,
-            Err(e) => return Ok(warp::reply::with_status(e, warp::http::StatusCode::BAD_REQUEST)),
+            Err(e) => return Ok(Self::error_reply_with_code(e, ErrorCode::InvalidUrl, operation_start.elapsed().as_millis() as u64).into_response()),
         };
 
-        // Verify authentication using headers
-        if let Err(e) = auth_service.authenticate(&username, &password) {
-            return Ok(warp::reply::with_status(
-                format!("Authentication error: {}", e),
-                warp::http::StatusCode::UNAUTHORIZED,
-            ));
-        }
+        // bcrypt verification is CPU bound, so it runs on the blocking pool
+        let required = Self::required_access(&protocol.operation, body.is_some());
+        let access = {
+            let (database, operation) = (protocol.database.clone(), protocol.operation.clone());
+            let owner_hint = protocol.username.clone();
+            let auth_service = Arc::clone(&auth_service);
+            Self::run_blocking(move || {
+                let (username, password) = match (username, password) {
+                    (Some(username), Some(password)) => (username, password),
+                    // Without credentials only reads of a database its owner made public are allowed
+                    _ => {
+                        let public = Self::is_public_read(&operation)
+                            && auth_service.is_public_database(&owner_hint, &database)
+                                .map_err(|e| (format!("Authentication error: {}", e), warp::http::StatusCode::INTERNAL_SERVER_ERROR))?;
+                        // Anonymous requests never write, so there is no author to record
+                        return if public {
+                            Ok((owner_hint, String::new()))
+                        } else {
+                            Err(("Authentication required: send username and password headers".to_string(), warp::http::StatusCode::UNAUTHORIZED))
+                        };
+                    }
+                };
+                // Verify the header credentials once, then check access on the resolved user
+                let user = auth_service.authenticated_user(&username, &password)
+                    .map_err(|e| (format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED))?
+                    .ok_or_else(|| ("Authentication error: Invalid credentials".to_string(), warp::http::StatusCode::UNAUTHORIZED))?;
+                // Exact, case-sensitive name match
+                let access = AuthService::database_access(&user, &database)
+                    .ok_or_else(|| ("Database not found for this user".to_string(), warp::http::StatusCode::NOT_FOUND))?;
+                if access.level < required {
+                    return Err((
+                        format!("Operation '{}' requires {} access, you have {}", operation, required.as_str(), access.level.as_str()),
+                        warp::http::StatusCode::FORBIDDEN,
+                    ));
+                }
+                Ok((access.owner, user.user))
+            }).await.unwrap_or_else(|e| Err((e, warp::http::StatusCode::INTERNAL_SERVER_ERROR)))
+        };
+        // Granted databases are stored in their owner's folder
+        let (owner, actor) = match access {
+            Ok(access) => access,
+            Err((message, status)) => return Ok(Self::error_reply(message, status, operation_start.elapsed().as_millis() as u64).into_response()),
+        };
 
-        // Verify user has access to database
-        if let Err(e) = auth_service.user_has_database(&username, &password, &protocol.database) {
-            return Ok(warp::reply::with_status(
-                format!("Database access denied: {}", e),
-                warp::http::StatusCode::FORBIDDEN,
-            ));
-        }
+        // CSV output replaces the JSON envelope with the returned records
+        let wants_csv = headers.format.as_deref().is_some_and(|f| f.eq_ignore_ascii_case("csv"));
+        // raw=true sends the bare records array, without the envelope or time
+        let wants_raw = headers.raw;
+        let pretty = headers.pretty;
+        // Reads get an ETag so pollers can revalidate with If-None-Match instead of downloading again
+        let conditional = matches!(protocol.operation.to_lowercase().as_str(), "get" | "browse" | "list");
+        let if_none_match = headers.if_none_match.clone();
 
-        // Process operation with new parameters
-        let result = match protocol.operation.to_lowercase().as_str() {
-            "get" => Self::handle_get(&db_manager, &protocol, query_type.as_deref()).await,
-            "browse" => Self::handle_browse(&db_manager, &protocol, page.as_deref(), limit.as_deref()).await,
-            "list" => Self::handle_list(&db_manager, &protocol, page.as_deref(), limit.as_deref(), sort_by.as_deref(), sort_order.as_deref(), filters.as_deref()).await,
-            "post" => Self::handle_post(&db_manager, &protocol, body, &username).await,
-            "put" => Self::handle_put(&db_manager, &protocol, body, &username, id_update.as_deref()).await,
-            "delete" => Self::handle_delete(&db_manager, &protocol, &username).await,
-            "stats" => Self::handle_stats(&db_manager, &protocol, &username).await,
-            "health" => Self::health().await,
-            _ => Err("Unsupported operation. Use: get, browse, list, post, put, delete, stats".to_string()),
+        // Only reads are held to the timeout: an abandoned task still runs to the end, so a timed out
+        // write would land on disk after the client got a 408 and a retry would apply it twice
+        let limit = op_timeout().filter(|_| required == AccessLevel::Read);
+        let joined = Self::run_limited(limit, move || {
+            Self::run_operation(&auth_service, &db_manager, &protocol, body, &owner, &actor, &headers)
+        }).await;
+        let Some(joined) = joined else {
+            let error_response = Self::error_body(
+                format!("Operation timed out after {} ms", limit.unwrap_or_default().as_millis()),
+                ErrorCode::Timeout,
+                operation_start.elapsed().as_millis() as u64,
+            );
+            return Ok(warp::reply::with_status(
+                Self::to_json(&error_response, pretty),
+                warp::http::StatusCode::REQUEST_TIMEOUT,
+            ).into_response());
         };
+        let result = joined.unwrap_or_else(|e| Err(DbError::Internal(e)));
 
         let operation_time = operation_start.elapsed().as_millis();
 
+        let etag = match &result {
+            Ok(response) if conditional => Some(Self::etag(response, wants_csv, wants_raw, pretty)),
+            _ => None,
+        };
+        if let (Some(etag), Some(if_none_match)) = (&etag, &if_none_match)
+            && Self::etag_matches(if_none_match, etag) {
+            let not_modified = warp::reply::with_status(warp::reply(), warp::http::StatusCode::NOT_MODIFIED).into_response();
+            return Ok(Self::with_etag(not_modified, &Some(etag.clone())));
+        }
+
+        let result = match result {
+            Ok(response) if wants_csv => {
+                let records = response.get("results").or_else(|| response.get("data")).and_then(Value::as_array);
+                match records {
+                    Some(records) => match DatabaseManager::records_to_csv(records) {
+                        Ok(csv) => {
+                            return Ok(Self::with_etag(warp::reply::with_header(
+                                csv,
+                                warp::http::header::CONTENT_TYPE,
+                                "text/csv; charset=utf-8",
+                            ).into_response(), &etag));
+                        }
+                        Err(e) => Err(DbError::Internal(format!("Error generating CSV: {}", e))),
+                    },
+                    None => Err(DbError::BadRequest("format=csv is only supported for get, browse and list".to_string())),
+                }
+            }
+            Ok(response) if wants_raw => {
+                match response.get("results").or_else(|| response.get("data")).filter(|records| records.is_array()) {
+                    Some(records) => {
+                        return Ok(Self::with_etag(warp::reply::with_header(
+                            Self::to_json(records, pretty),
+                            warp::http::header::CONTENT_TYPE,
+                            "application/json",
+                        ).into_response(), &etag));
+                    }
+                    None => Err(DbError::BadRequest("raw=true is only supported for get, browse and list".to_string())),
+                }
+            }
+            other => other,
+        };
+
         match result {
             Ok(mut response) => {
                 // Add operation time to all responses
                 if let Some(obj) = response.as_object_mut() {
                     obj.insert("time".to_string(), serde_json::Value::Number((operation_time as u64).into()));
                 }
-                Ok(warp::reply::with_status(
-                    serde_json::to_string(&response).unwrap_or_default(),
+                Ok(Self::with_etag(warp::reply::with_status(
+                    Self::to_json(&response, pretty),
                     warp::http::StatusCode::OK,
-                ))
+                ).into_response(), &etag))
             },
             Err(e) => {
-                let error_response = serde_json::json!({
-                    "error": e,
-                    "time": operation_time
-                });
+                let error_response = Self::error_body(e.message(), ErrorCode::from_status(Self::error_status(&e)), operation_time as u64);
                 Ok(warp::reply::with_status(
-                    serde_json::to_string(&error_response).unwrap_or_default(),
-                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                ))
+                    Self::to_json(&error_response, pretty),
+                    Self::error_status(&e),
+                ).into_response())
             },
         }
     }
 
-    async fn handle_get(db_manager: &DatabaseManager, protocol: &SarychProtocol, query_type: Option<&str>) -> Result<Value, String> {
-        let results = db_manager.search_records(&protocol.username, &protocol.database, protocol.query.as_deref(), query_type)?;
-        Ok(serde_json::json!({
+    // Strong ETag of a read response: its data plus the output variant. `explain` is left out, its cache
+    // flags differ between reads of the same data (and `time` is only added afterwards)
+    fn etag(response: &Value, csv: bool, raw: bool, pretty: bool) -> String {
+        let mut hasher = StableHasher::new();
+        if let Some(fields) = response.as_object() {
+            for (key, value) in fields.iter().filter(|(key, _)| key.as_str() != "explain") {
+                // Both are written as JSON, which can't run into the next field
+                let _ = serde_json::to_writer(&mut hasher, key);
+                let _ = serde_json::to_writer(&mut hasher, value);
+            }
+        }
+        hasher.write(&[u8::from(csv), u8::from(raw), u8::from(pretty)]);
+        format!("\"{:016x}\"", hasher.finish())
+    }
+
+    // If-None-Match holds a comma-separated list of tags (weak ones included) or *
+    fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+        if_none_match.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    }
+
+    fn with_etag(mut response: warp::reply::Response, etag: &Option<String>) -> warp::reply::Response {
+        if let Some(value) = etag.as_deref().and_then(|etag| warp::http::HeaderValue::from_str(etag).ok()) {
+            response.headers_mut().insert(warp::http::header::ETAG, value);
+        }
+        response
+    }
+
+    // Compact by default, indented when the client asked for pretty output
+    fn to_json(value: &Value, pretty: bool) -> String {
+        let serialized = if pretty { serde_json::to_string_pretty(value) } else { serde_json::to_string(value) };
+        serialized.unwrap_or_default()
+    }
+
+    // Every error response has this shape: {"error": {"message", "code"}, "time"}
+    fn error_body(message: impl Into<String>, code: ErrorCode, time: u64) -> Value {
+        serde_json::json!({
+            "error": {
+                "message": message.into(),
+                "code": code
+            },
+            "time": time
+        })
+    }
+
+    fn error_reply(message: impl Into<String>, status: warp::http::StatusCode, time: u64) -> warp::reply::WithStatus<String> {
+        warp::reply::with_status(Self::error_body(message, ErrorCode::from_status(status), time).to_string(), status)
+    }
+
+    // For codes more specific than the status, which keeps the status the code maps to
+    fn error_reply_with_code(message: impl Into<String>, code: ErrorCode, time: u64) -> warp::reply::WithStatus<String> {
+        warp::reply::with_status(Self::error_body(message, code, time).to_string(), code.status())
+    }
+
+    // HTTP status for each kind of database error
+    fn error_status(error: &DbError) -> warp::http::StatusCode {
+        match error {
+            DbError::NotFound(_) => warp::http::StatusCode::NOT_FOUND,
+            DbError::BadRequest(_) => warp::http::StatusCode::BAD_REQUEST,
+            DbError::Conflict(_) => warp::http::StatusCode::CONFLICT,
+            DbError::TooLarge(_) => warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+            DbError::Internal(_) => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // Level needed for an operation: reads need read, record changes write, settings changes admin
+    fn required_access(operation: &str, has_body: bool) -> AccessLevel {
+        match operation.to_lowercase().as_str() {
+            "post" | "put" | "upsert" | "patch" | "increment" | "arraypush" | "arraypull" | "delete" | "dedupe" | "truncate" => AccessLevel::Write,
+            "settings" if has_body => AccessLevel::Admin,
+            // Snapshots take space in the owner's folder and quota without changing any record
+            "snapshot" => AccessLevel::Admin,
+            _ => AccessLevel::Read,
+        }
+    }
+
+    // Operations anonymous clients may run on a public database
+    fn is_public_read(operation: &str) -> bool {
+        matches!(operation.to_lowercase().as_str(), "get" | "browse" | "list" | "exists" | "metadata" | "sample" | "stats")
+    }
+
+    // Dispatch a parsed /sarych request to its operation handler, `username` is the database owner
+    // and `actor` the authenticated user making the request
+    fn run_operation(
+        auth_service: &AuthService,
+        db_manager: &DatabaseManager,
+        protocol: &SarychProtocol,
+        body: Option<Value>,
+        username: &str,
+        actor: &str,
+        headers: &SarychHeaders
+    ) -> Result<Value, DbError> {
+        let operation = protocol.operation.to_lowercase();
+        let result = match operation.as_str() {
+            "get" => Self::handle_get(db_manager, protocol, username, headers),
+            "browse" => Self::handle_browse(db_manager, protocol, username, headers.page.as_deref(), headers.limit.as_deref(), headers.no_cache),
+            "list" => Self::handle_list(db_manager, protocol, username, headers),
+            "post" => Self::handle_post(db_manager, protocol, body, username, actor, headers),
+            "put" => Self::handle_put(db_manager, protocol, body, username, actor, headers),
+            "upsert" => Self::handle_upsert(db_manager, protocol, body, username, actor, headers),
+            "patch" => Self::handle_patch(db_manager, protocol, body, username, actor, headers),
+            "increment" => Self::handle_increment(db_manager, protocol, username, actor, headers),
+            "arraypush" => Self::handle_array_update(db_manager, protocol, body, username, actor, headers, true),
+            "arraypull" => Self::handle_array_update(db_manager, protocol, body, username, actor, headers, false),
+            "delete" => Self::handle_delete(db_manager, protocol, username, headers),
+            "dedupe" => Self::handle_dedupe(db_manager, protocol, username, headers),
+            "truncate" => Self::handle_truncate(db_manager, protocol, username, headers),
+            "distinct" => Self::handle_distinct(db_manager, protocol, username, headers),
+            "exists" => Self::handle_exists(db_manager, protocol, username, headers),
+            "metadata" => Self::handle_metadata(db_manager, protocol, username, headers),
+            "sample" => Self::handle_sample(db_manager, protocol, username, headers),
+            "stats" => Self::handle_stats(db_manager, protocol, username),
+            "settings" => Self::handle_settings(db_manager, protocol, body, username),
+            "snapshot" => Self::handle_snapshot(db_manager, protocol, username),
+            "health" => Self::health(auth_service),
+            _ => Err(DbError::BadRequest("Unsupported operation. Use: get, browse, list, distinct, exists, metadata, sample, post, put, upsert, patch, increment, arrayPush, arrayPull, delete, dedupe, truncate, stats, settings, snapshot".to_string())),
+        };
+
+        // Every successful record write goes to the owner's audit log, inserts touch one record
+        if let Ok(response) = &result
+            && Self::required_access(&operation, false) == AccessLevel::Write {
+            let count = response.get("count").and_then(Value::as_u64).unwrap_or(1) as usize;
+            db_manager.audit(username, AuditEntry::new(actor, &protocol.database, &operation, count, protocol.query.as_deref()));
+        }
+        result
+    }
+
+    // A page or limit header as a number for get, browse, list and distinct; anything else is rejected rather
+    // than ignored, which would return everything
+    fn page_header(header: &str, value: Option<&str>) -> Result<Option<usize>, DbError> {
+        value
+            .map(|v| v.trim().parse::<usize>().map_err(|_| {
+                DbError::BadRequest(format!("Invalid {} '{}'. Use a non-negative integer", header, v))
+            }))
+            .transpose()
+    }
+
+    fn handle_get(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        // getById is shorthand for queryType=id with the id as query
+        let (query, query_type) = match headers.get_by_id.as_deref() {
+            Some("") => return Err(DbError::BadRequest("getById header cannot be empty".to_string())),
+            Some(id) => (Some(id), Some("id")),
+            None => (protocol.query.as_deref(), headers.query_type.as_deref()),
+        };
+        let max_distance = match headers.max_distance.as_deref() {
+            Some(d) => Some(d.trim().parse::<usize>().map_err(|_| {
+                DbError::BadRequest(format!("Invalid maxDistance '{}'. Use a non-negative integer", d))
+            })?),
+            None => None,
+        };
+        let max_results = match headers.max_results.as_deref() {
+            Some(m) => match m.trim().parse::<usize>() {
+                Ok(max) if max > 0 => Some(max),
+                _ => return Err(DbError::BadRequest(format!("Invalid maxResults '{}'. Use a positive integer", m))),
+            },
+            None => None,
+        };
+        // Several terms only make sense for the default substring search
+        let all_terms = match headers.query_logic.as_deref().map(str::to_lowercase).as_deref() {
+            None => None,
+            Some(_) if query_type.is_some() => {
+                return Err(DbError::BadRequest("queryLogic only applies to full-text searches, not to queryType or getById".to_string()));
+            }
+            Some("and") => Some(true),
+            Some("or") => Some(false),
+            Some(other) => return Err(DbError::BadRequest(format!("Invalid queryLogic '{}'. Use: and, or", other))),
+        };
+        // Per-request split for tuning, capped so one request can't fan out into thousands of chunks
+        let nodes = match headers.nodes.as_deref() {
+            Some(n) => match n.trim().parse::<usize>() {
+                Ok(count) if count > 0 => Some(count.min(MAX_SEARCH_NODES)),
+                _ => return Err(DbError::BadRequest(format!("Invalid nodes '{}'. Use a positive integer", n))),
+            },
+            None => None,
+        };
+        let options = SearchOptions { query, query_type, max_distance, max_results, no_cache: headers.no_cache, nodes, all_terms, force: headers.force, stable_order: headers.stable_order };
+        let (mut results, plan) = db_manager.search_records_explained(username, &protocol.database, &options)?;
+        let total_matches = results.len();
+
+        // Sorting and pagination go through the same helpers as list, but only when asked;
+        // otherwise every match comes back in search order
+        let list_options = ListOptions {
+            page: Self::page_header("page", headers.page.as_deref())?,
+            limit: Self::page_header("limit", headers.limit.as_deref())?,
+            sort_by: headers.sort_by.as_deref(),
+            sort_order: headers.sort_order.as_deref(),
+            ..ListOptions::default()
+        };
+        let sorting = if list_options.sort_by.is_some() || list_options.sort_order.is_some() {
+            // Results may be shared with the search cache, so sort a copy
+            db_manager.sort_records(Arc::make_mut(&mut results).as_mut_slice(), &list_options)?
+        } else {
+            Value::Null
+        };
+        let (results, pagination) = if list_options.page.is_some() || list_options.limit.is_some() {
+            db_manager.page_of(&results, total_matches, &list_options)
+        } else {
+            (results.as_slice(), Value::Null)
+        };
+        // flatten: true turns nested objects into dotted keys, only on the page being returned
+        let flattened: Vec<Value>;
+        let results = if headers.flatten {
+            flattened = results.iter().map(DatabaseManager::flatten_value).collect();
+            flattened.as_slice()
+        } else {
+            results
+        };
+
+        let mut response = serde_json::json!({
             "operation": "get",
             "database": protocol.database,
-            "query": protocol.query,
+            "query": query,
             "query_type": query_type,
-            "results": results,
-            "count": results.len()
-        }))
+            "query_logic": headers.query_logic,
+            "count": results.len(),
+            "returned": results.len(),
+            "total_matches": total_matches,
+            // When set, total_matches stops counting at this cap
+            "max_results": max_results,
+            "pagination": pagination,
+            "sorting": sorting,
+            "results": results
+        });
+        // explain: true reports how the search ran
+        if headers.explain {
+            response["explain"] = serde_json::to_value(&plan).map_err(|e| DbError::Internal(e.to_string()))?;
+        }
+        Ok(response)
     }
 
-    async fn handle_browse(
+    fn handle_browse(
         db_manager: &DatabaseManager,
         protocol: &SarychProtocol,
+        username: &str,
         page: Option<&str>,
-        limit: Option<&str>
-    ) -> Result<Value, String> {
+        limit: Option<&str>,
+        no_cache: bool
+    ) -> Result<Value, DbError> {
         // Parse pagination parameters
-        let limit_num = limit.and_then(|l| l.parse::<usize>().ok());
-        let page_num = page.and_then(|p| p.parse::<usize>().ok());
+        let limit_num = Self::page_header("limit", limit)?;
+        let page_num = Self::page_header("page", page)?;
 
         let result = db_manager.browse_records(
-            &protocol.username,
+            username,
             &protocol.database,
             page_num,
-            limit_num
+            limit_num,
+            no_cache
         )?;
 
         Ok(serde_json::json!({
@@ -226,46 +725,88 @@ impl SarychServer {
         }))
     }
 
-    async fn handle_list(
+    fn handle_list(
         db_manager: &DatabaseManager,
         protocol: &SarychProtocol,
-        page: Option<&str>,
-        limit: Option<&str>,
-        sort_by: Option<&str>,
-        sort_order: Option<&str>,
-        filters: Option<&str>
-    ) -> Result<Value, String> {
-        // Parse pagination parameters
-        let page_num = page.and_then(|p| p.parse::<usize>().ok());
-        let limit_num = limit.and_then(|l| l.parse::<usize>().ok());
-        
-        // Parse filters JSON
-        let filters_obj = filters.and_then(|f| {
-            serde_json::from_str::<Value>(f).ok()
-        });
+        username: &str,
+        headers: &SarychHeaders
+    ) -> Result<Value, DbError> {
+        // Parse filters JSON; a malformed one is an error, not a list without filters
+        let filters_obj = headers.filters.as_deref()
+            .map(|f| serde_json::from_str::<Value>(f)
+                .map_err(|e| DbError::BadRequest(format!("Invalid filters JSON: {}", e))))
+            .transpose()?;
 
-        let result = db_manager.list_records(
-            &protocol.username,
-            &protocol.database,
-            page_num,
-            limit_num,
-            sort_by,
-            sort_order,
-            filters_obj.as_ref()
-        )?;
+        // Creation window, RFC3339 timestamps
+        let timestamp = |header: &str, value: Option<&str>| value
+            .map(|v| chrono::DateTime::parse_from_rfc3339(v.trim())
+                .map(|ts| ts.with_timezone(&chrono::Utc))
+                .map_err(|_| DbError::BadRequest(format!("{} must be an RFC3339 timestamp, e.g. 2024-01-31T00:00:00Z", header))))
+            .transpose();
+
+        let options = ListOptions {
+            // Parse pagination parameters
+            page: Self::page_header("page", headers.page.as_deref())?,
+            limit: Self::page_header("limit", headers.limit.as_deref())?,
+            sort_by: headers.sort_by.as_deref(),
+            sort_order: headers.sort_order.as_deref(),
+            filters: filters_obj.as_ref(),
+            search: headers.search.as_deref(),
+            search_fields: headers.search_fields.as_deref()
+                .map(|fields| fields.split(',').map(str::trim).filter(|f| !f.is_empty()).collect())
+                .unwrap_or_default(),
+            created_after: timestamp("createdAfter", headers.created_after.as_deref())?,
+            created_before: timestamp("createdBefore", headers.created_before.as_deref())?,
+            no_cache: headers.no_cache,
+        };
+
+        // A cursor header (even empty) switches to keyset pagination
+        let result = match headers.cursor.as_deref() {
+            Some(cursor) => db_manager.list_records_cursor(
+                username,
+                &protocol.database,
+                &options,
+                cursor.trim()
+            )?,
+            None => db_manager.list_records(
+                username,
+                &protocol.database,
+                &options
+            )?,
+        };
+
+        let data = match result.get("data") {
+            Some(Value::Array(records)) if headers.flatten => Some(Value::Array(records.iter().map(DatabaseManager::flatten_value).collect())),
+            other => other.cloned(),
+        };
 
         Ok(serde_json::json!({
             "operation": "list",
             "database": protocol.database,
-            "data": result.get("data"),
+            "data": data,
             "pagination": result.get("pagination"),
             "sorting": result.get("sorting")
         }))
     }
 
-    async fn handle_post(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str) -> Result<Value, String> {
-        let record = body.ok_or("Body required for POST operation")?;
-        let message = db_manager.insert_record(username, &protocol.database, record)?;
+    fn handle_post(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str, actor: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let mut record = body.ok_or_else(|| DbError::BadRequest("Body required for POST operation".to_string()))?;
+        // expiresIn: seconds until the record expires, stored as an absolute _expires_at
+        if let Some(expires_in) = headers.expires_in.as_deref() {
+            let seconds = expires_in.trim().parse::<i64>().ok()
+                .filter(|s| *s > 0)
+                .and_then(chrono::Duration::try_seconds)
+                .ok_or_else(|| DbError::BadRequest("expiresIn must be a positive number of seconds".to_string()))?;
+            let expires_at = chrono::Utc::now().checked_add_signed(seconds)
+                .ok_or_else(|| DbError::BadRequest("expiresIn is too large".to_string()))?;
+            match record.as_object_mut() {
+                Some(obj) => {
+                    obj.insert(EXPIRES_AT_FIELD.to_string(), Value::String(expires_at.to_rfc3339()));
+                }
+                None => return Err(DbError::BadRequest("expiresIn needs a JSON object body".to_string())),
+            }
+        }
+        let message = db_manager.insert_record(username, &protocol.database, actor, record)?;
         Ok(serde_json::json!({
             "operation": "post",
             "database": protocol.database,
@@ -273,70 +814,379 @@ impl SarychServer {
         }))
     }
 
-    async fn handle_put(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str, id_update: Option<&str>) -> Result<Value, String> {
-        let update_data = body.ok_or("Body required for PUT operation")?;
+    fn handle_put(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str, actor: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let update_data = body.ok_or_else(|| DbError::BadRequest("Body required for PUT operation".to_string()))?;
+        let mode = UpdateMode::parse(headers.replace_mode.as_deref())?;
+        let replace_mode = if mode == UpdateMode::Replace { "replace" } else { "merge" };
+        
+        let target = Self::update_target(protocol, headers, "PUT")?;
+        let outcome = db_manager.update_records(username, &protocol.database, actor, target, update_data, mode, headers.return_docs)?;
         
-        let message = if let Some(id) = id_update {
+        let mut response = serde_json::json!({
+            "operation": "put",
+            "database": protocol.database,
+            "query": protocol.query,
+            "id_update": headers.id_update,
+            "replace_mode": replace_mode,
+        });
+        Self::attach_outcome(&mut response, outcome, headers.return_docs);
+        Ok(response)
+    }
+
+    fn handle_upsert(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str, actor: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let update_data = body.ok_or_else(|| DbError::BadRequest("Body required for upsert operation".to_string()))?;
+        let mode = UpdateMode::parse(headers.replace_mode.as_deref())?;
+        let replace_mode = if mode == UpdateMode::Replace { "replace" } else { "merge" };
+
+        let target = Self::update_target(protocol, headers, "upsert")?;
+        let (outcome, inserted) = db_manager.upsert_record(username, &protocol.database, actor, target, update_data, mode, headers.return_docs)?;
+
+        let mut response = serde_json::json!({
+            "operation": "upsert",
+            "database": protocol.database,
+            "query": protocol.query,
+            "id_update": headers.id_update,
+            "replace_mode": replace_mode,
+            "action": if inserted { "inserted" } else { "updated" },
+        });
+        Self::attach_outcome(&mut response, outcome, headers.return_docs);
+        Ok(response)
+    }
+
+    fn handle_patch(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str, actor: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let body = body.ok_or_else(|| DbError::BadRequest("Body required for patch operation".to_string()))?;
+        let ops = PatchOp::parse_all(body)?;
+
+        let target = Self::update_target(protocol, headers, "patch")?;
+        let outcome = db_manager.update_records(username, &protocol.database, actor, target, Value::Null, UpdateMode::Patch(&ops), headers.return_docs)?;
+
+        let mut response = serde_json::json!({
+            "operation": "patch",
+            "database": protocol.database,
+            "query": protocol.query,
+            "id_update": headers.id_update,
+            "ops": ops.len(),
+        });
+        Self::attach_outcome(&mut response, outcome, headers.return_docs);
+        Ok(response)
+    }
+
+    fn handle_increment(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, actor: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let field = headers.field.as_deref().ok_or_else(|| DbError::BadRequest("field header required for increment operation".to_string()))?;
+        // Amount defaults to 1, negative values decrement
+        let amount = match headers.amount.as_deref() {
+            Some(a) => serde_json::from_str::<serde_json::Number>(a)
+                .map_err(|_| DbError::BadRequest(format!("Invalid amount '{}'. Must be a number", a)))?,
+            None => serde_json::Number::from(1),
+        };
+
+        let target = Self::update_target(protocol, headers, "increment")?;
+        let mode = UpdateMode::Increment { field, amount: amount.clone() };
+        let outcome = db_manager.update_records(username, &protocol.database, actor, target, Value::Null, mode, headers.return_docs)?;
+
+        let mut response = serde_json::json!({
+            "operation": "increment",
+            "database": protocol.database,
+            "query": protocol.query,
+            "id_update": headers.id_update,
+            "field": field,
+            "amount": amount,
+        });
+        Self::attach_outcome(&mut response, outcome, headers.return_docs);
+        Ok(response)
+    }
+
+    fn handle_array_update(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str, actor: &str, headers: &SarychHeaders, push: bool) -> Result<Value, DbError> {
+        let operation = if push { "arrayPush" } else { "arrayPull" };
+        let field = headers.field.as_deref().ok_or_else(|| DbError::BadRequest(format!("field header required for {} operation", operation)))?;
+        let value = body.ok_or_else(|| DbError::BadRequest(format!("Body required for {} operation", operation)))?;
+
+        let target = Self::update_target(protocol, headers, operation)?;
+        let mode = if push { UpdateMode::ArrayPush { field } } else { UpdateMode::ArrayPull { field } };
+        let outcome = db_manager.update_records(username, &protocol.database, actor, target, value, mode, headers.return_docs)?;
+
+        let mut response = serde_json::json!({
+            "operation": operation,
+            "database": protocol.database,
+            "query": protocol.query,
+            "id_update": headers.id_update,
+            "field": field,
+        });
+        Self::attach_outcome(&mut response, outcome, headers.return_docs);
+        Ok(response)
+    }
+
+    // Resolve which records an update applies to: idUpdate header first, then the URL query
+    fn update_target<'a>(protocol: &'a SarychProtocol, headers: &'a SarychHeaders, operation: &str) -> Result<UpdateTarget<'a>, DbError> {
+        if let Some(id) = headers.id_update.as_deref() {
             // Update by ID
-            db_manager.update_records(username, &protocol.database, "", update_data, Some(id))?
+            Ok(UpdateTarget::Id(id))
         } else {
             // Update by query (existing behavior)
-            let query = protocol.query.as_deref().ok_or("Query or idUpdate header required for PUT operation")?;
-            db_manager.update_records(username, &protocol.database, query, update_data, None)?
+            protocol.query.as_deref()
+                .map(UpdateTarget::Query)
+                .ok_or_else(|| DbError::BadRequest(format!("Query or idUpdate header required for {} operation", operation)))
+        }
+    }
+
+    fn handle_delete(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let query = protocol.query.as_deref().ok_or_else(|| DbError::BadRequest("Query required for DELETE operation".to_string()))?;
+        let outcome = db_manager.delete_records(username, &protocol.database, query, headers.confirm_bulk, headers.return_docs)?;
+        let mut response = serde_json::json!({
+            "operation": "delete",
+            "database": protocol.database,
+            "query": query,
+        });
+        Self::attach_outcome(&mut response, outcome, headers.return_docs);
+        Ok(response)
+    }
+
+    // field names one field or a comma-separated set; without it, whole documents are compared
+    fn handle_dedupe(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let fields: Vec<&str> = headers.field.as_deref()
+            .map(|fields| fields.split(',').map(str::trim).filter(|f| !f.is_empty()).collect())
+            .unwrap_or_default();
+        if headers.field.is_some() && fields.is_empty() {
+            return Err(DbError::BadRequest("field header cannot be empty, omit it to compare whole records".to_string()));
+        }
+
+        let outcome = db_manager.dedupe_records(username, &protocol.database, &fields, headers.confirm_bulk, headers.return_docs)?;
+        let mut response = serde_json::json!({
+            "operation": "dedupe",
+            "database": protocol.database,
+            "fields": fields,
+        });
+        Self::attach_outcome(&mut response, outcome, headers.return_docs);
+        Ok(response)
+    }
+
+    // Remove every record, only when confirmTruncate names the database so a wrong URL can't wipe another one
+    fn handle_truncate(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        match headers.confirm_truncate.as_deref() {
+            None => return Err(DbError::BadRequest(format!(
+                "confirmTruncate header required for truncate operation, send the database name ({})", protocol.database
+            ))),
+            Some(confirmed) if confirmed != protocol.database => return Err(DbError::BadRequest(format!(
+                "confirmTruncate '{}' does not match the database '{}'", confirmed, protocol.database
+            ))),
+            Some(_) => {}
+        }
+
+        let outcome = db_manager.truncate_database(username, &protocol.database)?;
+        let mut response = serde_json::json!({
+            "operation": "truncate",
+            "database": protocol.database,
+        });
+        Self::attach_outcome(&mut response, outcome, false);
+        Ok(response)
+    }
+
+    // Add message, count and (optionally) the affected documents to a write response
+    fn attach_outcome(response: &mut Value, outcome: WriteOutcome, return_docs: bool) {
+        if let Some(obj) = response.as_object_mut() {
+            obj.insert("message".to_string(), Value::String(outcome.message));
+            obj.insert("count".to_string(), Value::from(outcome.count));
+            if return_docs {
+                obj.insert("affected".to_string(), Value::Array(outcome.affected));
+            }
+        }
+    }
+
+    fn handle_distinct(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let field = headers.field.as_deref().ok_or_else(|| DbError::BadRequest("field header required for distinct operation".to_string()))?;
+        // Optional cap on how many values come back, count still reports them all
+        let limit = Self::page_header("limit", headers.limit.as_deref())?;
+        let (values, total_distinct) = db_manager.distinct_records(username, &protocol.database, field, limit)?;
+
+        Ok(serde_json::json!({
+            "operation": "distinct",
+            "database": protocol.database,
+            "field": field,
+            "count": total_distinct,
+            "returned": values.len(),
+            "values": values
+        }))
+    }
+
+    // size random records (capped like a page limit), seed makes the selection repeatable
+    fn handle_sample(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let requested = match headers.size.as_deref() {
+            Some(size) => match size.trim().parse::<usize>() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(DbError::BadRequest(format!("Invalid size '{}'. Use a positive integer", size))),
+            },
+            None => return Err(DbError::BadRequest("size header required for sample operation".to_string())),
         };
-        
+        let seed = match headers.seed.as_deref() {
+            Some(seed) => Some(seed.trim().parse::<u64>()
+                .map_err(|_| DbError::BadRequest(format!("Invalid seed '{}'. Use a non-negative integer", seed)))?),
+            None => None,
+        };
+        let (size, size_clamped) = db_manager.clamp_limit(Some(requested));
+
+        let (sample, total_records) = db_manager.sample_records(username, &protocol.database, size, seed, headers.no_cache)?;
         Ok(serde_json::json!({
-            "operation": "put",
+            "operation": "sample",
             "database": protocol.database,
-            "query": protocol.query,
-            "id_update": id_update,
-            "message": message
+            "size": size,
+            "size_clamped": size_clamped,
+            "seed": seed,
+            "count": sample.len(),
+            "total_records": total_records,
+            "results": sample,
         }))
     }
 
-    async fn handle_delete(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str) -> Result<Value, String> {
-        let query = protocol.query.as_deref().ok_or("Query required for DELETE operation")?;
-        let message = db_manager.delete_records(username, &protocol.database, query)?;
+    // Lets sync clients check whether a record changed without downloading it
+    fn handle_metadata(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let id = match headers.get_by_id.as_deref() {
+            Some("") => return Err(DbError::BadRequest("getById header cannot be empty".to_string())),
+            Some(id) => id,
+            None => return Err(DbError::BadRequest("getById header required for metadata operation".to_string())),
+        };
+
+        let metadata = db_manager.record_metadata(username, &protocol.database, id, headers.no_cache)?;
         Ok(serde_json::json!({
-            "operation": "delete",
+            "operation": "metadata",
             "database": protocol.database,
-            "query": query,
-            "message": message
+            "get_by_id": id,
+            "metadata": metadata,
+        }))
+    }
+
+    // Point-in-time copy of the database file, see DatabaseManager::snapshot_database
+    fn handle_snapshot(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str) -> Result<Value, DbError> {
+        let mut snapshot = db_manager.snapshot_database(username, &protocol.database)?;
+        snapshot["operation"] = serde_json::json!("snapshot");
+        Ok(snapshot)
+    }
+
+    // Whether any record matches, without returning them; count stops at maxResults (1 by default)
+    fn handle_exists(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str, headers: &SarychHeaders) -> Result<Value, DbError> {
+        let target = match headers.get_by_id.as_deref() {
+            Some("") => return Err(DbError::BadRequest("getById header cannot be empty".to_string())),
+            Some(id) => UpdateTarget::Id(id),
+            None => protocol.query.as_deref()
+                .map(UpdateTarget::Query)
+                .ok_or_else(|| DbError::BadRequest("Query or getById header required for exists operation".to_string()))?,
+        };
+        let limit = match headers.max_results.as_deref() {
+            Some(m) => match m.trim().parse::<usize>() {
+                Ok(max) if max > 0 => max,
+                _ => return Err(DbError::BadRequest(format!("Invalid maxResults '{}'. Use a positive integer", m))),
+            },
+            None => 1,
+        };
+
+        let count = db_manager.count_matches(username, &protocol.database, &target, limit, headers.no_cache)?;
+        Ok(serde_json::json!({
+            "operation": "exists",
+            "database": protocol.database,
+            "query": protocol.query,
+            "get_by_id": headers.get_by_id,
+            "exists": count > 0,
+            "count": count,
         }))
     }
 
-    async fn handle_stats(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str) -> Result<Value, String> {
+    fn handle_stats(db_manager: &DatabaseManager, protocol: &SarychProtocol, username: &str) -> Result<Value, DbError> {
         db_manager.get_stats(username, &protocol.database)
     }
-    async fn health() -> Result<Value, String> {
+    // Read the database settings, or update them when a JSON body is sent
+    fn handle_settings(db_manager: &DatabaseManager, protocol: &SarychProtocol, body: Option<Value>, username: &str) -> Result<Value, DbError> {
+        let settings = match body {
+            Some(changes) => db_manager.update_settings(username, &protocol.database, &changes)?,
+            None => {
+                db_manager.require_database(username, &protocol.database)?;
+                db_manager.read_settings(username, &protocol.database)?
+            }
+        };
+
+        Ok(serde_json::json!({
+            "operation": "settings",
+            "database": protocol.database,
+            "settings": settings
+        }))
+    }
+
+    fn health(auth_service: &AuthService) -> Result<Value, DbError> {
+        let (healthy, checks) = Self::health_checks(auth_service);
         Ok(serde_json::json!({
             "operation": "health",
-            "status": "ok",
-            "message": "SarychDB is healthy"
+            "status": if healthy { "ok" } else { "degraded" },
+            "message": if healthy { "SarychDB is healthy" } else { "SarychDB is degraded" },
+            "checks": checks
         }))
     }
 
-    // Public health check endpoint (no authentication required)
-    pub async fn public_health() -> Result<impl Reply, Rejection> {
-        let start_time = std::time::Instant::now();
-        let operation_time = start_time.elapsed().as_millis();
-        
+    // Check that users.json parses, the users folder is writable, and report cache sizes and uptime
+    fn health_checks(auth_service: &AuthService) -> (bool, Value) {
+        let users_file = match auth_service.load_users() {
+            Ok(users) => serde_json::json!({ "status": "ok", "users": users.len() }),
+            Err(e) => serde_json::json!({ "status": "error", "error": e.to_string() }),
+        };
+
+        // Probe writability with a temporary file
+        let users_path = auth_service.users_dir();
+        let users_dir = if !users_path.is_dir() {
+            serde_json::json!({ "status": "error", "error": format!("'{}' directory does not exist", users_path.display()) })
+        } else {
+            let probe = users_path.join(format!(".health_probe_{}", std::process::id()));
+            match fs::write(&probe, b"ok").and_then(|_| fs::remove_file(&probe)) {
+                Ok(()) => serde_json::json!({ "status": "ok" }),
+                Err(e) => serde_json::json!({ "status": "error", "error": format!("'{}' is not writable: {}", users_path.display(), e) }),
+            }
+        };
+
+        let healthy = users_file["status"] == "ok" && users_dir["status"] == "ok";
+        (healthy, serde_json::json!({
+            "users_file": users_file,
+            "users_dir": users_dir,
+            "cache": {
+                "databases": DatabaseManager::cache_len(),
+                "searches": search_cache_len()
+            },
+            "uptime_secs": START_TIME.elapsed().as_secs()
+        }))
+    }
+
+    // Which build is running (no authentication required)
+    pub async fn version() -> Result<impl Reply, Rejection> {
+        let built_at = BUILD_TIMESTAMP.parse::<i64>().ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|built_at| built_at.to_rfc3339());
+        Ok(warp::reply::json(&serde_json::json!({
+            "service": "SarychDB",
+            "version": VERSION,
+            "git_commit": GIT_COMMIT,
+            "built_at": built_at
+        })))
+    }
+
+    // Public health check endpoint (no authentication required)
+    pub async fn public_health(auth_service: Arc<AuthService>) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+        let (healthy, checks) = Self::run_blocking(move || Self::health_checks(&auth_service))
+            .await
+            .unwrap_or_else(|e| (false, serde_json::json!({ "error": e })));
+        let operation_time = start_time.elapsed().as_millis();
+        
         Ok(warp::reply::with_status(
             serde_json::json!({
-                "status": "ok",
-                "message": "SarychDB is healthy and running",
+                "status": if healthy { "ok" } else { "degraded" },
+                "message": if healthy { "SarychDB is healthy and running" } else { "SarychDB is degraded" },
                 "service": "SarychDB",
-                "version": "2.0",
+                "version": VERSION,
+                "checks": checks,
                 "time": operation_time as u64
             }).to_string(),
-            warp::http::StatusCode::OK,
+            if healthy { warp::http::StatusCode::OK } else { warp::http::StatusCode::SERVICE_UNAVAILABLE },
         ))
     } 
     // Create user
-    pub async fn create_user(request: CreateUserRequest) -> Result<impl Reply, Rejection> {
+    pub async fn create_user(auth_service: Arc<AuthService>, request: CreateUserRequest) -> Result<impl Reply, Rejection> {
         let start_time = std::time::Instant::now();
-        let auth_service = AuthService::new();
-        match auth_service.create_user(request) {
+        match Self::run_blocking(move || auth_service.create_user(request)).await.and_then(|result| result) {
             Ok(message) => {
                 let operation_time = start_time.elapsed().as_millis();
                 Ok(warp::reply::with_status(
@@ -348,23 +1198,37 @@ impl SarychServer {
                 ))
             },
             Err(e) => {
+                let operation_time = start_time.elapsed().as_millis();
+                Ok(Self::error_reply(e, warp::http::StatusCode::BAD_REQUEST, operation_time as u64))
+            },
+        }
+    }
+
+    // Create database
+    pub async fn create_database(auth_service: Arc<AuthService>, request: CreateDbRequest) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+        match Self::run_blocking(move || auth_service.create_database(request)).await.and_then(|result| result) {
+            Ok(message) => {
                 let operation_time = start_time.elapsed().as_millis();
                 Ok(warp::reply::with_status(
                     serde_json::json!({
-                        "error": e,
+                        "message": message,
                         "time": operation_time as u64
                     }).to_string(),
-                    warp::http::StatusCode::BAD_REQUEST,
+                    warp::http::StatusCode::CREATED,
                 ))
             },
+            Err(e) => {
+                let operation_time = start_time.elapsed().as_millis();
+                Ok(Self::error_reply(e, warp::http::StatusCode::BAD_REQUEST, operation_time as u64))
+            },
         }
     }
 
-    // Create database
-    pub async fn create_database(request: CreateDbRequest) -> Result<impl Reply, Rejection> {
+    // Grant another user access to a database, or change the level already granted
+    pub async fn grant_database(auth_service: Arc<AuthService>, request: GrantDbRequest) -> Result<impl Reply, Rejection> {
         let start_time = std::time::Instant::now();
-        let auth_service = AuthService::new();
-        match auth_service.create_database(request) {
+        match Self::run_blocking(move || auth_service.grant_database(request)).await.and_then(|result| result) {
             Ok(message) => {
                 let operation_time = start_time.elapsed().as_millis();
                 Ok(warp::reply::with_status(
@@ -372,66 +1236,438 @@ impl SarychServer {
                         "message": message,
                         "time": operation_time as u64
                     }).to_string(),
-                    warp::http::StatusCode::CREATED,
+                    warp::http::StatusCode::OK,
                 ))
             },
             Err(e) => {
+                let operation_time = start_time.elapsed().as_millis();
+                Ok(Self::error_reply(e, warp::http::StatusCode::BAD_REQUEST, operation_time as u64))
+            },
+        }
+    }
+
+    // Make a database public (readable without credentials) or private again
+    pub async fn set_database_public(auth_service: Arc<AuthService>, request: SetPublicRequest) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+        match Self::run_blocking(move || auth_service.set_database_public(request)).await.and_then(|result| result) {
+            Ok(message) => {
                 let operation_time = start_time.elapsed().as_millis();
                 Ok(warp::reply::with_status(
                     serde_json::json!({
-                        "error": e,
+                        "message": message,
                         "time": operation_time as u64
                     }).to_string(),
-                    warp::http::StatusCode::BAD_REQUEST,
+                    warp::http::StatusCode::OK,
                 ))
             },
+            Err(e) => {
+                let operation_time = start_time.elapsed().as_millis();
+                Ok(Self::error_reply(e, warp::http::StatusCode::BAD_REQUEST, operation_time as u64))
+            },
         }
     }
 
-    // List user databases
-    pub async fn list_databases(username: String, password: String) -> Result<impl Reply, Rejection> {
+    // Account details of the authenticated user
+    pub async fn user_info(auth_service: Arc<AuthService>, username: String, password: String) -> Result<impl Reply, Rejection> {
         let start_time = std::time::Instant::now();
-        let auth_service = AuthService::new();
-        match auth_service.get_user_databases(&username, &password) {
-            Ok(databases) => {
+        match Self::run_blocking(move || auth_service.get_user_info(&username, &password)).await.and_then(|result| result) {
+            Ok(info) => {
                 let operation_time = start_time.elapsed().as_millis();
                 Ok(warp::reply::with_status(
                     serde_json::json!({
-                        "user": username,
-                        "databases": databases,
+                        "user": info.user,
+                        "created_at": info.created_at,
+                        "databases": info.db,
                         "time": operation_time as u64
                     }).to_string(),
                     warp::http::StatusCode::OK,
                 ))
             },
             Err(e) => {
+                let operation_time = start_time.elapsed().as_millis();
+                Ok(Self::error_reply(e, warp::http::StatusCode::UNAUTHORIZED, operation_time as u64))
+            },
+        }
+    }
+
+    // List user databases
+    pub async fn list_databases(auth_service: Arc<AuthService>, username: String, password: String) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+        let lookup = {
+            let (username, password) = (username.clone(), password);
+            Self::run_blocking(move || auth_service.get_user_databases(&username, &password)).await.and_then(|result| result)
+        };
+        match lookup {
+            Ok(databases) => {
                 let operation_time = start_time.elapsed().as_millis();
                 Ok(warp::reply::with_status(
                     serde_json::json!({
-                        "error": e,
+                        "user": username,
+                        "databases": databases,
                         "time": operation_time as u64
                     }).to_string(),
-                    warp::http::StatusCode::UNAUTHORIZED,
+                    warp::http::StatusCode::OK,
                 ))
             },
+            Err(e) => {
+                let operation_time = start_time.elapsed().as_millis();
+                Ok(Self::error_reply(e, warp::http::StatusCode::UNAUTHORIZED, operation_time as u64))
+            },
+        }
+    }
+
+    // Export a database file as a download, streamed from disk without loading it into memory
+    pub async fn export_database(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String, db_name: String) -> Result<warp::reply::Response, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        // Verify the requesting user can read the database, granted ones live in the owner's folder
+        let access = {
+            let db_name = db_name.clone();
+            Self::run_blocking(move || auth_service.user_database_access(&username, &password, &db_name)).await.and_then(|result| result)
+        };
+        let owner = match access {
+            Ok(Some(access)) => Ok(access.owner),
+            Ok(None) => Err(("Database not found for this user".to_string(), warp::http::StatusCode::NOT_FOUND)),
+            Err(e) => Err((format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED)),
+        };
+        let owner = match owner {
+            Ok(owner) => owner,
+            Err((message, status)) => {
+                let operation_time = start_time.elapsed().as_millis();
+                return Ok(Self::error_reply(message, status, operation_time as u64).into_response());
+            }
+        };
+
+        let opened = match db_manager.get_stored_path(&owner, &db_name) {
+            Ok(filepath) => tokio::fs::File::open(&filepath).await
+                .map(|file| (file, filepath.ends_with(".gz")))
+                .map_err(|e| (format!("Error opening database file: {}", e), warp::http::StatusCode::NOT_FOUND)),
+            Err(e) => Err((e.to_string(), Self::error_status(&e))),
+        };
+        let (file, gzipped) = match opened {
+            Ok(opened) => opened,
+            Err((message, status)) => {
+                let operation_time = start_time.elapsed().as_millis();
+                return Ok(Self::error_reply(message, status, operation_time as u64).into_response());
+            }
+        };
+
+        // The download is always plain JSON, gzipped databases are decompressed as they stream
+        let body = if gzipped {
+            warp::hyper::Body::wrap_stream(Self::gunzip_stream(file.into_std().await))
+        } else {
+            warp::hyper::Body::wrap_stream(tokio_util::io::ReaderStream::new(file))
+        };
+        let mut response = warp::reply::Response::new(body);
+        let headers = response.headers_mut();
+        headers.insert(
+            warp::http::header::CONTENT_TYPE,
+            warp::http::HeaderValue::from_static("application/json"),
+        );
+        if let Ok(disposition) = warp::http::HeaderValue::from_str(&format!("attachment; filename=\"{}.json\"", db_name)) {
+            headers.insert(warp::http::header::CONTENT_DISPOSITION, disposition);
+        }
+        Ok(response)
+    }
+
+    // Decompress a gzip file in chunks on the blocking pool, so large exports never sit in memory
+    fn gunzip_stream(file: std::fs::File) -> impl Stream<Item = Result<bytes::Bytes, std::io::Error>> {
+        let decoder = flate2::read::GzDecoder::new(std::io::BufReader::new(file));
+        futures_util::stream::unfold(Some(decoder), |decoder| async move {
+            let mut decoder = decoder?;
+            let chunk = tokio::task::spawn_blocking(move || {
+                let mut buffer = vec![0u8; 64 * 1024];
+                let read = std::io::Read::read(&mut decoder, &mut buffer)?;
+                buffer.truncate(read);
+                Ok::<_, std::io::Error>((decoder, buffer))
+            }).await.unwrap_or_else(|e| Err(std::io::Error::other(e)));
+            match chunk {
+                Ok((_, buffer)) if buffer.is_empty() => None,
+                Ok((decoder, buffer)) => Some((Ok(bytes::Bytes::from(buffer)), Some(decoder))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    // Import (restore) a database from a JSON array body
+    #[allow(clippy::too_many_arguments)]
+    pub async fn import_database(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String, db_name: String, mode: Option<String>, content_type: Option<String>, body: bytes::Bytes) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        let target = db_name.clone();
+        let result = Self::run_blocking(move || match auth_service.user_database_access(&username, &password, &target) {
+            // Importing rewrites records, so it needs write access; granted databases live in the owner's folder
+            Ok(Some(access)) if access.level < AccessLevel::Write => Err((
+                format!("Import requires write access, you have {}", access.level.as_str()),
+                warp::http::StatusCode::FORBIDDEN,
+            )),
+            Ok(Some(access)) => {
+                let mode = mode.unwrap_or_else(|| "replace".to_string());
+                let is_csv = content_type.as_deref().is_some_and(|ct| ct.starts_with("text/csv"));
+                let records = if is_csv {
+                    DatabaseManager::csv_to_records(&body)
+                } else {
+                    serde_json::from_slice::<Vec<Value>>(&body)
+                        .map_err(|e| DbError::BadRequest(format!("Import payload must be a JSON array of records: {}", e)))
+                };
+                match mode.as_str() {
+                    "replace" | "append" => records
+                        .and_then(|records| {
+                            let count = records.len();
+                            let message = db_manager.import_records(&access.owner, &target, records, mode == "append")?;
+                            db_manager.audit(&access.owner, AuditEntry::new(&username, &target, "import", count, None));
+                            Ok(message)
+                        })
+                        .map_err(|e| (e.to_string(), Self::error_status(&e)))
+                        .map(|message| (message, mode)),
+                    other => Err((format!("Invalid mode '{}'. Use: replace, append", other), warp::http::StatusCode::BAD_REQUEST)),
+                }
+            },
+            Ok(None) => Err(("Database not found for this user".to_string(), warp::http::StatusCode::NOT_FOUND)),
+            Err(e) => Err((format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED)),
+        }).await.unwrap_or_else(|e| Err((e, warp::http::StatusCode::INTERNAL_SERVER_ERROR)));
+
+        let operation_time = start_time.elapsed().as_millis();
+        match result {
+            Ok((message, mode)) => Ok(warp::reply::with_status(
+                serde_json::json!({
+                    "message": message,
+                    "database": db_name,
+                    "mode": mode,
+                    "time": operation_time as u64
+                }).to_string(),
+                warp::http::StatusCode::OK,
+            )),
+            Err((e, status)) => Ok(Self::error_reply(e, status, operation_time as u64)),
+        }
+    }
+
+    // Rewrite a database file without its expired records, reporting the size before and after
+    pub async fn compact_database(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String, db_name: String) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        let result = Self::run_blocking(move || match auth_service.user_database_access(&username, &password, &db_name) {
+            // Dropping expired records is a write, same as a purge would be
+            Ok(Some(access)) if access.level < AccessLevel::Write => Err((
+                format!("Compaction requires write access, you have {}", access.level.as_str()),
+                warp::http::StatusCode::FORBIDDEN,
+            )),
+            Ok(Some(access)) => db_manager.compact_database(&access.owner, &db_name)
+                .inspect(|report| {
+                    let removed = report["expired_removed"].as_u64().unwrap_or(0) as usize;
+                    db_manager.audit(&access.owner, AuditEntry::new(&username, &db_name, "compact", removed, None));
+                })
+                .map_err(|e| (e.to_string(), Self::error_status(&e))),
+            Ok(None) => Err(("Database not found for this user".to_string(), warp::http::StatusCode::NOT_FOUND)),
+            Err(e) => Err((format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED)),
+        }).await.unwrap_or_else(|e| Err((e, warp::http::StatusCode::INTERNAL_SERVER_ERROR)));
+
+        let operation_time = start_time.elapsed().as_millis();
+        match result {
+            Ok(mut report) => {
+                report["time"] = serde_json::json!(operation_time as u64);
+                Ok(warp::reply::with_status(report.to_string(), warp::http::StatusCode::OK))
+            },
+            Err((e, status)) => Ok(Self::error_reply(e, status, operation_time as u64)),
+        }
+    }
+
+    // Apply a batch of writes atomically: either every operation is persisted or none is
+    pub async fn transaction(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String, ops: Vec<TransactionOp>) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        let operations = ops.len();
+        let result = Self::run_blocking(move || {
+            let user = match auth_service.authenticated_user(&username, &password) {
+                Ok(Some(user)) => user,
+                Ok(None) => return Err(("Authentication error: Invalid credentials".to_string(), warp::http::StatusCode::UNAUTHORIZED)),
+                Err(e) => return Err((format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED)),
+            };
+            // Batches stay inside the caller's own databases, granted ones are written through /sarych only
+            for op in &ops {
+                match AuthService::database_access(&user, &op.database) {
+                    None => return Err((format!("Database '{}' not found for this user", op.database), warp::http::StatusCode::NOT_FOUND)),
+                    Some(access) if access.owner != user.user || access.level < AccessLevel::Write => return Err((
+                        format!("Transactions are limited to your own databases, '{}' is shared with you", op.database),
+                        warp::http::StatusCode::FORBIDDEN,
+                    )),
+                    Some(_) => {}
+                }
+            }
+            let results = db_manager.run_transaction(&user.user, &ops).map_err(|e| (e.to_string(), Self::error_status(&e)))?;
+            // One audit line per operation, written only once the whole batch is committed
+            for (op, result) in ops.iter().zip(&results) {
+                let count = result.get("count").and_then(Value::as_u64).unwrap_or(0) as usize;
+                db_manager.audit(&user.user, AuditEntry::new(&user.user, &op.database, &op.op.to_lowercase(), count, op.query.as_deref()));
+            }
+            Ok(results)
+        }).await.unwrap_or_else(|e| Err((e, warp::http::StatusCode::INTERNAL_SERVER_ERROR)));
+
+        let operation_time = start_time.elapsed().as_millis();
+        match result {
+            Ok(results) => Ok(warp::reply::with_status(
+                serde_json::json!({
+                    "message": "Transaction committed",
+                    "operations": operations,
+                    "results": results,
+                    "time": operation_time as u64
+                }).to_string(),
+                warp::http::StatusCode::OK,
+            )),
+            Err((e, status)) => Ok(Self::error_reply(e, status, operation_time as u64)),
+        }
+    }
+
+    // Storage used by the databases the user owns, for quota and billing displays
+    pub async fn usage(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        let result = Self::run_blocking(move || {
+            let user = match auth_service.authenticated_user(&username, &password) {
+                Ok(Some(user)) => user,
+                Ok(None) => return Err(("Authentication error: Invalid credentials".to_string(), warp::http::StatusCode::UNAUTHORIZED)),
+                Err(e) => return Err((format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED)),
+            };
+            // Granted databases are stored, and counted, in their owner's folder
+            let owned: Vec<&str> = user.db.iter()
+                .filter(|db| db.owner.as_deref().is_none_or(|owner| owner == user.user))
+                .map(|db| db.namedb.as_str())
+                .collect();
+            db_manager.usage(&user.user, &owned).map_err(|e| (e.to_string(), Self::error_status(&e)))
+        }).await.unwrap_or_else(|e| Err((e, warp::http::StatusCode::INTERNAL_SERVER_ERROR)));
+
+        let operation_time = start_time.elapsed().as_millis();
+        match result {
+            Ok(mut usage) => {
+                usage["time"] = Value::from(operation_time as u64);
+                Ok(warp::reply::with_status(usage.to_string(), warp::http::StatusCode::OK))
+            }
+            Err((e, status)) => Ok(Self::error_reply(e, status, operation_time as u64)),
+        }
+    }
+
+    // Page through the audit log of the user's databases, including writes made by grantees
+    pub async fn audit_log(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String, page: Option<String>, limit: Option<String>) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        let page = page.and_then(|p| p.parse::<usize>().ok()).filter(|p| *p > 0).unwrap_or(1);
+        let limit = limit.and_then(|l| l.parse::<usize>().ok()).filter(|l| *l > 0);
+        let result = Self::run_blocking(move || {
+            let user = match auth_service.authenticated_user(&username, &password) {
+                Ok(Some(user)) => user,
+                Ok(None) => return Err(("Authentication error: Invalid credentials".to_string(), warp::http::StatusCode::UNAUTHORIZED)),
+                Err(e) => return Err((format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED)),
+            };
+            db_manager.read_audit(&user.user, page, limit).map_err(|e| (e.to_string(), Self::error_status(&e)))
+        }).await.unwrap_or_else(|e| Err((e, warp::http::StatusCode::INTERNAL_SERVER_ERROR)));
+
+        let operation_time = start_time.elapsed().as_millis();
+        match result {
+            Ok(mut audit) => {
+                audit["time"] = Value::from(operation_time as u64);
+                Ok(warp::reply::with_status(audit.to_string(), warp::http::StatusCode::OK))
+            }
+            Err((e, status)) => Ok(Self::error_reply(e, status, operation_time as u64)),
+        }
+    }
+
+    // Load databases (and optionally common searches) into the caches before traffic arrives
+    pub async fn warm_cache(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String, request: WarmCacheRequest) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        let result = Self::run_blocking(move || {
+            let user = match auth_service.authenticated_user(&username, &password) {
+                Ok(Some(user)) => user,
+                Ok(None) => return Err(("Authentication error: Invalid credentials".to_string(), warp::http::StatusCode::UNAUTHORIZED)),
+                Err(e) => return Err((format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED)),
+            };
+            if request.databases.is_empty() {
+                return Err(("databases must list at least one database".to_string(), warp::http::StatusCode::BAD_REQUEST));
+            }
+            // Check every name first so a typo doesn't leave the caches half warmed; granted databases are read from the owner's folder
+            let owners = request.databases.iter()
+                .map(|db_name| AuthService::database_access(&user, db_name)
+                    .map(|access| access.owner)
+                    .ok_or_else(|| (format!("Database '{}' not found for this user", db_name), warp::http::StatusCode::NOT_FOUND)))
+                .collect::<Result<Vec<_>, _>>()?;
+            request.databases.iter().zip(&owners)
+                .map(|(db_name, owner)| db_manager.warm_cache(owner, db_name, &request.queries)
+                    .map_err(|e| (e.to_string(), Self::error_status(&e))))
+                .collect::<Result<Vec<_>, _>>()
+        }).await.unwrap_or_else(|e| Err((e, warp::http::StatusCode::INTERNAL_SERVER_ERROR)));
+
+        let operation_time = start_time.elapsed().as_millis();
+        match result {
+            Ok(databases) => Ok(warp::reply::with_status(
+                serde_json::json!({
+                    "message": format!("Warmed {} databases", databases.len()),
+                    "databases": databases,
+                    "time": operation_time as u64
+                }).to_string(),
+                warp::http::StatusCode::OK,
+            )),
+            Err((e, status)) => Ok(Self::error_reply(e, status, operation_time as u64)),
+        }
+    }
+
+    // List user databases with their record count and size, a database that can't be read gets an error instead
+    pub async fn list_databases_detailed(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>, username: String, password: String) -> Result<impl Reply, Rejection> {
+        let start_time = std::time::Instant::now();
+
+        let result = Self::run_blocking(move || {
+            let user = match auth_service.authenticated_user(&username, &password) {
+                Ok(Some(user)) => user,
+                Ok(None) => return Err("Invalid credentials".to_string()),
+                Err(e) => return Err(e),
+            };
+            let databases: Vec<Value> = user.db.iter().map(|db| {
+                let mut entry = serde_json::to_value(db).unwrap_or_default();
+                // Granted databases are read from the owner's folder
+                let owner = db.owner.as_deref().unwrap_or(&user.user);
+                match db_manager.database_summary(owner, &db.namedb) {
+                    Ok(Value::Object(summary)) => {
+                        if let Some(obj) = entry.as_object_mut() {
+                            obj.extend(summary);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        entry["total_records"] = Value::Null;
+                        entry["size_bytes"] = Value::Null;
+                        entry["error"] = Value::String(e.to_string());
+                    }
+                }
+                entry
+            }).collect();
+            Ok((user.user, databases))
+        }).await.and_then(|result| result);
+
+        let operation_time = start_time.elapsed().as_millis();
+        match result {
+            Ok((user, databases)) => Ok(warp::reply::with_status(
+                serde_json::json!({
+                    "user": user,
+                    "databases": databases,
+                    "time": operation_time as u64
+                }).to_string(),
+                warp::http::StatusCode::OK,
+            )),
+            Err(e) => Ok(Self::error_reply(e, warp::http::StatusCode::UNAUTHORIZED, operation_time as u64)),
         }
     }
 
     // Clear search cache endpoint
-    pub async fn clear_cache(username: String, password: String) -> Result<impl Reply, Rejection> {
+    pub async fn clear_cache(auth_service: Arc<AuthService>, username: String, password: String) -> Result<impl Reply, Rejection> {
         let start_time = std::time::Instant::now();
-        let auth_service = AuthService::new();
         
         // Verify authentication
-        if let Err(e) = auth_service.authenticate(&username, &password) {
+        let authenticated = Self::run_blocking(move || auth_service.authenticate(&username, &password))
+            .await
+            .and_then(|result| result)
+            .and_then(|valid| if valid { Ok(()) } else { Err("Invalid credentials".to_string()) });
+        if let Err(e) = authenticated {
             let operation_time = start_time.elapsed().as_millis();
-            return Ok(warp::reply::with_status(
-                serde_json::json!({
-                    "error": format!("Authentication error: {}", e),
-                    "time": operation_time as u64
-                }).to_string(),
-                warp::http::StatusCode::UNAUTHORIZED,
-            ));
+            return Ok(Self::error_reply(format!("Authentication error: {}", e), warp::http::StatusCode::UNAUTHORIZED, operation_time as u64));
         }
 
         // Clear both database and search caches
@@ -448,117 +1684,1351 @@ impl SarychServer {
         ))
     }
 
+    // Run blocking file and bcrypt work on Tokio's blocking pool so the async workers stay free
+    async fn run_blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> Result<T, String> {
+        tokio::task::spawn_blocking(work)
+            .await
+            .map_err(|e| format!("Operation failed: {}", e))
+    }
+
+    // run_blocking that gives up waiting after `limit`, None when it did; the work itself is abandoned, not stopped
+    async fn run_limited<T: Send + 'static>(limit: Option<Duration>, work: impl FnOnce() -> T + Send + 'static) -> Option<Result<T, String>> {
+        match limit {
+            Some(limit) => tokio::time::timeout(limit, Self::run_blocking(work)).await.ok(),
+            None => Some(Self::run_blocking(work).await),
+        }
+    }
+
     // Configurar rutas del servidor
-    pub fn routes() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    pub fn routes(auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        // Every request shares the services built at startup
+        let with_auth = warp::any().map(move || Arc::clone(&auth_service));
+        let with_db = warp::any().map(move || Arc::clone(&db_manager));
+
         // CORS configuration
         let cors = warp::cors()
             .allow_any_origin()
-            .allow_headers(vec!["content-type", "username", "password", "querytype", "idupdate", "page", "limit", "sortby", "sortorder", "filters", "authorization"])
-            .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"]);
+            .allow_headers(SarychHeaders::NAMES.iter().copied())
+            .expose_headers(vec!["etag"])
+            .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+            // Browsers reuse the preflight for this long instead of sending OPTIONS before every request
+            .max_age(cors_max_age());
 
         // Ruta para el protocolo SarychDB con autenticación por headers
         let sarych_route = warp::path("sarych")
+            .and(warp::path::end())
             .and(warp::query::<HashMap<String, String>>())
-            .and(warp::body::bytes())
-            .and(warp::header::<String>("username"))
-            .and(warp::header::<String>("password"))
-            .and(warp::header::optional::<String>("queryType"))
-            .and(warp::header::optional::<String>("idUpdate"))
-            .and(warp::header::optional::<String>("page"))
-            .and(warp::header::optional::<String>("limit"))
-            .and(warp::header::optional::<String>("sortBy"))
-            .and(warp::header::optional::<String>("sortOrder"))
-            .and(warp::header::optional::<String>("filters"))
-            .and_then(|params: HashMap<String, String>, body: bytes::Bytes, username: String, password: String, query_type: Option<String>, id_update: Option<String>, page: Option<String>, limit: Option<String>, sort_by: Option<String>, sort_order: Option<String>, filters: Option<String>| async move {
+            .and(warp::header::optional::<String>("username"))
+            .and(warp::header::optional::<String>("password"))
+            .and(warp::header::headers_cloned())
+            .and(limited_body())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, username: Option<String>, password: Option<String>, headers: HeaderMap, body: bytes::Bytes, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
                  let url = params.get("url").ok_or_else(|| warp::reject::custom(RequestError::MissingUrl))?;
+                 // Only an empty body counts as absent, a broken one is reported instead of "Body required"
+                 // Form-encoded bodies become a flat object, unless they are JSON: `curl -d` labels JSON as a form too
+                 let is_form = headers.get(warp::http::header::CONTENT_TYPE)
+                     .and_then(|ct| ct.to_str().ok())
+                     .is_some_and(|ct| ct.starts_with("application/x-www-form-urlencoded"));
                  let json_body = if !body.is_empty() {
-                     serde_json::from_slice(&body).ok()
+                     let value = match serde_json::from_slice(&body) {
+                         Ok(value) => value,
+                         Err(_) if is_form => DatabaseManager::form_to_record(&body),
+                         Err(e) => return Err(warp::reject::custom(RequestError::InvalidJsonBody(e.to_string()))),
+                     };
+                     Some(value)
                  } else {
                      None
                  };
-                 SarychServer::handle_sarych_request(url.clone(), json_body, username, password, query_type, id_update, page, limit, sort_by, sort_order, filters).await
+                 let mut options = SarychHeaders::from_headers(&headers);
+                 if let Some(format) = params.get("format") {
+                     options.format = Some(format.clone());
+                 }
+                 SarychServer::handle_sarych_request(auth_service, db_manager, url.clone(), json_body, username, password, options).await
              });
 
         // Route to create users
         let create_user_route = warp::path("api")
             .and(warp::path("users"))
+            .and(warp::path::end())
             .and(warp::post())
             .and(warp::body::json())
-            .and_then(|request: CreateUserRequest| async move {
-                SarychServer::create_user(request).await
+            .and(with_auth.clone())
+            .and_then(|request: CreateUserRequest, auth_service: Arc<AuthService>| async move {
+                SarychServer::create_user(auth_service, request).await
+            });
+
+        // Account details of the authenticated user
+        let user_info_route = warp::path("api")
+            .and(warp::path("users"))
+            .and(warp::path("me"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_auth.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                SarychServer::user_info(auth_service, username, password).await
             });
 
         // Ruta para crear bases de datos
         let create_db_route = warp::path("api")
             .and(warp::path("databases"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_auth.clone())
+            .and_then(|request: CreateDbRequest, auth_service: Arc<AuthService>| async move {
+                SarychServer::create_database(auth_service, request).await
+            });
+
+        // Grant or change another user's access level on a database
+        let grant_db_route = warp::path("api")
+            .and(warp::path("databases"))
+            .and(warp::path("grant"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_auth.clone())
+            .and_then(|request: GrantDbRequest, auth_service: Arc<AuthService>| async move {
+                SarychServer::grant_database(auth_service, request).await
+            });
+
+        // Flag one of the user's databases as public or private
+        let public_db_route = warp::path("api")
+            .and(warp::path("databases"))
+            .and(warp::path("public"))
+            .and(warp::path::end())
             .and(warp::post())
             .and(warp::body::json())
-            .and_then(|request: CreateDbRequest| async move {
-                SarychServer::create_database(request).await
+            .and(with_auth.clone())
+            .and_then(|request: SetPublicRequest, auth_service: Arc<AuthService>| async move {
+                SarychServer::set_database_public(auth_service, request).await
+            });
+
+        // Export a database as a file download (must be matched before the list route)
+        let export_db_route = warp::path("api")
+            .and(warp::path("databases"))
+            .and(warp::path("export"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                let db_name = params.get("db_name").ok_or_else(|| warp::reject::custom(RequestError::MissingDatabase))?.clone();
+                SarychServer::export_database(auth_service, db_manager, username, password, db_name).await
+            });
+
+        // Import (restore) a database from a JSON array, mode header selects replace or append
+        let import_db_route = warp::path("api")
+            .and(warp::path("databases"))
+            .and(warp::path("import"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::header::optional::<String>("mode"))
+            .and(warp::header::optional::<String>("content-type"))
+            .and(limited_body())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, mode: Option<String>, content_type: Option<String>, body: bytes::Bytes, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                let db_name = params.get("db_name").ok_or_else(|| warp::reject::custom(RequestError::MissingDatabase))?.clone();
+                SarychServer::import_database(auth_service, db_manager, username, password, db_name, mode, content_type, body).await
+            });
+
+        // Rewrite a database file without expired records
+        let compact_db_route = warp::path("api")
+            .and(warp::path("databases"))
+            .and(warp::path("compact"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                let db_name = params.get("db_name").ok_or_else(|| warp::reject::custom(RequestError::MissingDatabase))?.clone();
+                SarychServer::compact_database(auth_service, db_manager, username, password, db_name).await
+            });
+
+        // Databases with record counts and sizes in one call
+        let list_db_detailed_route = warp::path("api")
+            .and(warp::path("databases"))
+            .and(warp::path("detailed"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                SarychServer::list_databases_detailed(auth_service, db_manager, username, password).await
             });
 
         // Ruta para listar bases de datos
         let list_db_route = warp::path("api")
             .and(warp::path("databases"))
+            .and(warp::path::end())
             .and(warp::get())
             .and(warp::query::<HashMap<String, String>>())
-            .and_then(|params: HashMap<String, String>| async move {
+            .and(with_auth.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                SarychServer::list_databases(auth_service, username, password).await
+            });
+
+        // Batch of writes applied atomically
+        let transaction_route = warp::path("api")
+            .and(warp::path("transaction"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::body::json())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, ops: Vec<TransactionOp>, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
                 let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
                 let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
-                SarychServer::list_databases(username, password).await
+                SarychServer::transaction(auth_service, db_manager, username, password, ops).await
             });
 
         // Public health check endpoint
         let health_route = warp::path("health")
+            .and(warp::path::end())
             .and(warp::get())
-            .and_then(|| async move {
-                SarychServer::public_health().await
+            .and(with_auth.clone())
+            .and_then(|auth_service: Arc<AuthService>| async move {
+                SarychServer::public_health(auth_service).await
             });
 
+        // Version and build info
+        let version_route = warp::path("api")
+            .and(warp::path("version"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and_then(SarychServer::version);
+
         // Clear cache endpoint
         let clear_cache_route = warp::path("api")
             .and(warp::path("cache"))
             .and(warp::path("clear"))
+            .and(warp::path::end())
             .and(warp::delete())
             .and(warp::query::<HashMap<String, String>>())
-            .and_then(|params: HashMap<String, String>| async move {
+            .and(with_auth.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                SarychServer::clear_cache(auth_service, username, password).await
+            });
+
+        // Disk usage across the user's databases
+        let usage_route = warp::path("api")
+            .and(warp::path("usage"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                SarychServer::usage(auth_service, db_manager, username, password).await
+            });
+
+        // Audit log of the writes to the user's databases
+        let audit_route = warp::path("api")
+            .and(warp::path("audit"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
+                let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
+                let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
+                SarychServer::audit_log(auth_service, db_manager, username, password, params.get("page").cloned(), params.get("limit").cloned()).await
+            });
+
+        // Preload databases into the caches
+        let warm_cache_route = warp::path("api")
+            .and(warp::path("cache"))
+            .and(warp::path("warm"))
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::body::json())
+            .and(with_auth.clone())
+            .and(with_db.clone())
+            .and_then(|params: HashMap<String, String>, request: WarmCacheRequest, auth_service: Arc<AuthService>, db_manager: Arc<DatabaseManager>| async move {
                 let username = params.get("username").ok_or_else(|| warp::reject::custom(RequestError::MissingUsername))?.clone();
                 let password = params.get("password").ok_or_else(|| warp::reject::custom(RequestError::MissingPassword))?.clone();
-                SarychServer::clear_cache(username, password).await
+                SarychServer::warm_cache(auth_service, db_manager, username, password, request).await
             });
 
-        sarych_route
+        let api_routes = sarych_route
             .or(create_user_route)
+            .or(user_info_route)
             .or(create_db_route)
+            .or(grant_db_route)
+            .or(public_db_route)
+            .or(import_db_route)
+            .or(compact_db_route)
             .or(list_db_route)
+            .or(list_db_detailed_route)
+            .or(transaction_route)
             .or(health_route)
+            .or(version_route)
             .or(clear_cache_route)
+            .or(warm_cache_route)
+            .or(usage_route)
+            .or(audit_route);
+
+        // Compress responses when the client accepts it; exports stay uncompressed so they keep streaming
+        let gzip_routes = Self::accepts_encoding("gzip")
+            .and(api_routes.clone())
+            .with(warp::compression::gzip());
+        let deflate_routes = Self::accepts_encoding("deflate")
+            .and(api_routes.clone())
+            .with(warp::compression::deflate());
+
+        export_db_route
+            .or(gzip_routes)
+            .or(deflate_routes)
+            .or(api_routes)
+            .recover(Self::handle_rejection)
             .with(cors)
     }
+
+    // Render every rejection as a JSON error with a status matching the cause
+    async fn handle_rejection(rejection: Rejection) -> Result<warp::reply::Response, std::convert::Infallible> {
+        use warp::http::StatusCode;
+
+        let (message, status) = if rejection.is_not_found() {
+            ("Route not found".to_string(), StatusCode::NOT_FOUND)
+        } else if let Some(error) = rejection.find::<RequestError>() {
+            (error.message(), error.status())
+        } else if let Some(error) = rejection.find::<warp::reject::MissingHeader>() {
+            (format!("Missing '{}' header", error.name()), StatusCode::BAD_REQUEST)
+        } else if let Some(error) = rejection.find::<warp::reject::InvalidHeader>() {
+            (format!("Invalid '{}' header", error.name()), StatusCode::BAD_REQUEST)
+        } else if let Some(error) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+            (format!("Invalid JSON body: {}", error), StatusCode::BAD_REQUEST)
+        } else if rejection.find::<warp::reject::InvalidQuery>().is_some() {
+            ("Invalid query string".to_string(), StatusCode::BAD_REQUEST)
+        } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+            ("HTTP method not allowed".to_string(), StatusCode::METHOD_NOT_ALLOWED)
+        } else if rejection.find::<warp::reject::UnsupportedMediaType>().is_some() {
+            ("Unsupported content type".to_string(), StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+            ("Request body too large".to_string(), StatusCode::PAYLOAD_TOO_LARGE)
+        } else if rejection.find::<warp::reject::LengthRequired>().is_some() {
+            ("Content-Length header required".to_string(), StatusCode::LENGTH_REQUIRED)
+        } else {
+            eprintln!("Unhandled rejection: {:?}", rejection);
+            ("Internal server error".to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        };
+
+        // Rejections happen before any handler runs, so there is no operation time to report
+        Ok(Self::error_reply(message, status, 0).into_response())
+    }
+
+    // Passes only when the Accept-Encoding header lists the given encoding
+    fn accepts_encoding(encoding: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        warp::header::optional::<String>("accept-encoding")
+            .and_then(move |accepted: Option<String>| async move {
+                let accepted = accepted.is_some_and(|value| {
+                    value.split(',').any(|e| e.trim().split(';').next() == Some(encoding))
+                });
+                if accepted {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            })
+            .untuple_one()
+    }
+}
+
+/// Machine-readable `error.code` of an error response, so clients can branch without parsing the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The sarychdb:// URL could not be parsed
+    InvalidUrl,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    Timeout,
+    Conflict,
+    LengthRequired,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn from_status(status: warp::http::StatusCode) -> Self {
+        use warp::http::StatusCode;
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            StatusCode::REQUEST_TIMEOUT => ErrorCode::Timeout,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::LENGTH_REQUIRED => ErrorCode::LengthRequired,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorCode::UnsupportedMediaType,
+            status if status.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    pub fn status(self) -> warp::http::StatusCode {
+        use warp::http::StatusCode;
+        match self {
+            ErrorCode::InvalidUrl | ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::Timeout => StatusCode::REQUEST_TIMEOUT,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 // Errores personalizados
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum RequestError {
     MissingUrl,
     MissingUsername,
     MissingPassword,
+    MissingDatabase,
+    BodyTooLarge,
+    UnreadableBody,
+    InvalidJsonBody(String),
+}
+
+impl RequestError {
+    fn message(&self) -> String {
+        match self {
+            RequestError::MissingUrl => "Missing 'url' query parameter".to_string(),
+            RequestError::MissingUsername => "Missing 'username' query parameter".to_string(),
+            RequestError::MissingPassword => "Missing 'password' query parameter".to_string(),
+            RequestError::MissingDatabase => "Missing 'db_name' query parameter".to_string(),
+            RequestError::BodyTooLarge => "Request body too large (see --max-body-size)".to_string(),
+            RequestError::UnreadableBody => "Could not read the request body".to_string(),
+            RequestError::InvalidJsonBody(e) => format!("Invalid JSON body: {}", e),
+        }
+    }
+
+    fn status(&self) -> warp::http::StatusCode {
+        match self {
+            RequestError::BodyTooLarge => warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+            _ => warp::http::StatusCode::BAD_REQUEST,
+        }
+    }
 }
 
 impl warp::reject::Reject for RequestError {}
 
-pub async fn start_server(port: u16) {
-    let routes = SarychServer::routes();
+// Run `task` on the blocking pool every `period`; a zero period never starts it
+pub(crate) fn spawn_periodic(period: Duration, task: impl Fn() + Send + Sync + 'static) {
+    if period.is_zero() {
+        return;
+    }
+    let task = Arc::new(task);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // The first tick completes immediately, wait a full period before the first run
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let task = Arc::clone(&task);
+            let _ = tokio::task::spawn_blocking(move || task()).await;
+        }
+    });
+}
+
+pub async fn start_server(host: std::net::IpAddr, port: u16, auth_config: AuthConfig, db_config: DatabaseConfig, silent: bool) {
+    Lazy::force(&START_TIME);
+    // Build the services once, every handler shares them
+    let auth_service = Arc::new(AuthService::new(auth_config));
+    let db_manager = Arc::new(DatabaseManager::new(db_config));
+
+    // Periodically drop expired records from the files, reads already hide them
+    let purge_manager = Arc::clone(&db_manager);
+    spawn_periodic(db_manager.config().purge_interval, move || {
+        let purged = purge_manager.purge_expired();
+        if purged > 0 && !silent {
+            println!("🧹 Purged {} expired records", purged);
+        }
+    });
+    // Evict expired cache entries so memory is bounded even when nobody reads them again
+    let cache_manager = Arc::clone(&db_manager);
+    spawn_periodic(db_manager.config().cache_cleanup_interval, move || {
+        cache_manager.evict_expired_cache();
+        evict_expired_searches();
+    });
+    let routes = SarychServer::routes(auth_service, db_manager);
 
         println!("🚀 SarychDB server started on port {}", port);
         println!("📖 API documentation:");
         println!("  GET /health - Health check (public)");
+        println!("  GET /api/version - Version and build info (public)");
         println!("  POST /api/users - Create user");
         println!("  POST /api/databases - Create database");
+        println!("  POST /api/databases/public - Make a database public or private");
         println!("  GET /api/databases - List databases");
+        println!("  GET /api/databases/detailed - List databases with record counts and sizes");
+        println!("  GET /api/databases/export - Download a database file");
+        println!("  POST /api/databases/import - Restore a database from a JSON array or CSV");
+        println!("  POST /api/databases/compact - Rewrite a database file without expired records");
+        println!("  GET /api/audit - Page through the audit log of writes");
         println!("  GET /sarych?url=sarychdb://user@pass/db/operation - SarychDB protocol");
 
     warp::serve(routes)
-        .run(([127, 0, 0, 1], port))
+        .run((host, port))
         .await;
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::auth::BCRYPT_MIN_COST;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    // Services over their own data directory with user alice (password secret) owning the database `people`
+    struct TestServer {
+        data_dir: PathBuf,
+        auth_service: Arc<AuthService>,
+        db_manager: Arc<DatabaseManager>,
+    }
+
+    impl TestServer {
+        fn new() -> Self {
+            let data_dir = std::env::temp_dir().join(format!("sarychdb-test-{}", uuid::Uuid::new_v4()));
+            let auth_service = Arc::new(AuthService::new(AuthConfig { data_dir: data_dir.clone(), bcrypt_cost: BCRYPT_MIN_COST }));
+            let db_manager = Arc::new(DatabaseManager::new(DatabaseConfig { data_dir: data_dir.clone(), ..Default::default() }));
+            auth_service.create_user(CreateUserRequest { username: "alice".to_string(), password: "secret".to_string() }).unwrap();
+            auth_service.create_database(CreateDbRequest {
+                username: "alice".to_string(),
+                password: "secret".to_string(),
+                db_name: "people".to_string(),
+                public: false,
+            }).unwrap();
+            TestServer { data_dir, auth_service, db_manager }
+        }
+
+        // Send `sarychdb://alice@secret/<path>` to /sarych as alice
+        async fn sarych(&self, path: &str, headers: &[(&str, &str)], body: Option<Value>) -> warp::http::Response<bytes::Bytes> {
+            self.sarych_as("alice", path, headers, body).await
+        }
+
+        // Same as sarych, authenticated as another user with password secret; `path` starts at alice's database
+        async fn sarych_as(&self, username: &str, path: &str, headers: &[(&str, &str)], body: Option<Value>) -> warp::http::Response<bytes::Bytes> {
+            let url = format!("sarychdb://alice@secret/{}", path);
+            let mut request = warp::test::request()
+                .method("POST")
+                .path(&format!("/sarych?url={}", urlencoding::encode(&url)))
+                .header("username", username)
+                .header("password", "secret");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            if let Some(body) = body {
+                request = request.header("content-type", "application/json").body(body.to_string());
+            }
+            request.reply(&SarychServer::routes(Arc::clone(&self.auth_service), Arc::clone(&self.db_manager))).await
+        }
+
+        // Same as sarych with a raw body; `headers` are set after it, so they can override its Content-Length
+        async fn sarych_bytes(&self, path: &str, headers: &[(&str, &str)], body: Vec<u8>) -> warp::http::Response<bytes::Bytes> {
+            let url = format!("sarychdb://alice@secret/{}", path);
+            let mut request = warp::test::request()
+                .method("POST")
+                .path(&format!("/sarych?url={}", urlencoding::encode(&url)))
+                .header("username", "alice")
+                .header("password", "secret")
+                .body(body);
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            request.reply(&SarychServer::routes(Arc::clone(&self.auth_service), Arc::clone(&self.db_manager))).await
+        }
+
+        // Send any request through the full route set
+        async fn send(&self, request: warp::test::RequestBuilder) -> warp::http::Response<bytes::Bytes> {
+            request.reply(&SarychServer::routes(Arc::clone(&self.auth_service), Arc::clone(&self.db_manager))).await
+        }
+
+        // Send `sarychdb://alice@/<path>` to /sarych without credentials
+        async fn anonymous(&self, path: &str) -> warp::http::Response<bytes::Bytes> {
+            let url = format!("sarychdb://alice@/{}", path);
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/sarych?url={}", urlencoding::encode(&url)))
+                .reply(&SarychServer::routes(Arc::clone(&self.auth_service), Arc::clone(&self.db_manager)))
+                .await
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.data_dir);
+        }
+    }
+
+    fn json_body(response: &warp::http::Response<bytes::Bytes>) -> Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    // Stands in for a search over a huge database
+    fn slow_search() -> usize {
+        std::thread::sleep(Duration::from_millis(300));
+        42
+    }
+
+    #[tokio::test]
+    async fn slow_operations_are_abandoned_after_the_limit() {
+        let started = Instant::now();
+
+        let result = SarychServer::run_limited(Some(Duration::from_millis(20)), slow_search).await;
+
+        assert_eq!(result, None);
+        assert!(started.elapsed() < Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn operations_without_a_limit_run_to_the_end() {
+        assert_eq!(SarychServer::run_limited(None, slow_search).await, Some(Ok(42)));
+    }
+
+    #[tokio::test]
+    async fn missing_database_is_not_found() {
+        let server = TestServer::new();
+
+        let response = server.sarych("nothere/get", &[], None).await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+        assert_eq!(json_body(&response)["error"]["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn post_without_body_is_a_bad_request() {
+        let server = TestServer::new();
+
+        let response = server.sarych("people/post", &[], None).await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(&response)["error"]["code"], "bad_request");
+    }
+
+    #[tokio::test]
+    async fn duplicate_id_is_a_conflict() {
+        let server = TestServer::new();
+        let record = json!({"_id": "ada", "name": "Ada"});
+
+        let first = server.sarych("people/post", &[], Some(record.clone())).await;
+        let second = server.sarych("people/post", &[], Some(record)).await;
+
+        assert_eq!(first.status(), warp::http::StatusCode::OK);
+        assert_eq!(second.status(), warp::http::StatusCode::CONFLICT);
+        assert_eq!(json_body(&second)["error"]["code"], "conflict");
+    }
+
+    #[test]
+    fn url_segments_that_traverse_directories_are_rejected() {
+        for url in [
+            "sarychdb://alice@secret/../get",
+            "sarychdb://alice@secret/..secret/get",
+            "sarychdb://alice@secret/bob\\people/get",
+            "sarychdb://alice@secret/peo\0ple/get",
+            "sarychdb://alice@secret/people/..",
+            "sarychdb://alice@secret/people/get\\x",
+        ] {
+            assert!(SarychServer::parse_sarych_url(url).is_err(), "{} was accepted", url.escape_debug());
+        }
+        assert!(SarychServer::parse_sarych_url("sarychdb://alice@secret/people/get").is_ok());
+    }
+
+    #[tokio::test]
+    async fn traversal_in_the_database_segment_is_a_bad_request() {
+        let server = TestServer::new();
+
+        let response = server.sarych("../get", &[], None).await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(&response)["error"]["code"], "invalid_url");
+    }
+
+    fn etag_of(response: &warp::http::Response<bytes::Bytes>) -> String {
+        response.headers()[warp::http::header::ETAG].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn unchanged_data_revalidates_with_not_modified() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        // The first read loads the database, the second one hits the cache: explain differs, the data doesn't
+        let first = server.sarych("people/get", &[("explain", "true")], None).await;
+        let etag = etag_of(&first);
+        let second = server.sarych("people/get", &[("explain", "true"), ("if-none-match", &etag)], None).await;
+
+        assert_eq!(first.status(), warp::http::StatusCode::OK);
+        assert_eq!(second.status(), warp::http::StatusCode::NOT_MODIFIED);
+        assert_eq!(etag_of(&second), etag);
+    }
+
+    #[tokio::test]
+    async fn a_write_changes_the_etag() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+        let before = etag_of(&server.sarych("people/browse", &[], None).await);
+
+        server.sarych("people/post", &[], Some(json!({"name": "Grace"}))).await;
+        let response = server.sarych("people/browse", &[("if-none-match", &before)], None).await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        assert_ne!(etag_of(&response), before);
+    }
+
+    #[test]
+    fn etag_depends_on_the_output_variant() {
+        let response = json!({"operation": "get", "results": [{"name": "Ada"}]});
+
+        let plain = SarychServer::etag(&response, false, false, false);
+
+        assert_eq!(plain, SarychServer::etag(&response, false, false, false));
+        assert_ne!(plain, SarychServer::etag(&response, true, false, false));
+        assert_ne!(plain, SarychServer::etag(&response, false, true, false));
+        assert_ne!(plain, SarychServer::etag(&response, false, false, true));
+    }
+
+    #[tokio::test]
+    async fn truncate_needs_the_database_name_as_confirmation() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        let unconfirmed = server.sarych("people/truncate", &[], None).await;
+        let mismatched = server.sarych("people/truncate", &[("confirmTruncate", "orders")], None).await;
+
+        assert_eq!(unconfirmed.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(mismatched.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(server.db_manager.read_database("alice", "people").unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn confirmed_truncate_empties_the_database() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+        server.sarych("people/post", &[], Some(json!({"name": "Grace"}))).await;
+        // Loaded into the cache, which the truncate must invalidate
+        server.sarych("people/get", &[], None).await;
+
+        let response = server.sarych("people/truncate", &[("confirmTruncate", "people")], None).await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        assert_eq!(json_body(&response)["count"], 2);
+        assert_eq!(server.db_manager.read_database("alice", "people").unwrap(), Vec::<Value>::new());
+        let after = server.sarych("people/get", &[], None).await;
+        assert_eq!(json_body(&after)["results"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn paged_get_keeps_the_total_and_returns_one_page() {
+        let server = TestServer::new();
+        for name in ["Ada", "Grace", "Linus", "Barbara", "Alan"] {
+            server.sarych("people/post", &[], Some(json!({"name": name}))).await;
+        }
+
+        let first = json_body(&server.sarych("people/get", &[("page", "1"), ("limit", "2")], None).await);
+        let last = json_body(&server.sarych("people/get", &[("page", "3"), ("limit", "2")], None).await);
+
+        assert_eq!((first["total_matches"].clone(), first["returned"].clone()), (json!(5), json!(2)));
+        assert_eq!((last["total_matches"].clone(), last["returned"].clone()), (json!(5), json!(1)));
+    }
+
+    #[tokio::test]
+    async fn get_rejects_page_and_limit_that_are_not_numbers() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        for header in [("page", "two"), ("limit", "-1")] {
+            let response = server.sarych("people/get", &[header], None).await;
+
+            assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST, "{:?}", header);
+            assert!(json_body(&response)["error"]["message"].as_str().unwrap().starts_with(&format!("Invalid {}", header.0)));
+        }
+    }
+
+    #[tokio::test]
+    async fn paged_get_splits_the_sorted_matches_into_consistent_pages() {
+        let server = TestServer::new();
+        for name in ["Linus", "Ada", "Grace", "Alan", "Barbara"] {
+            server.sarych("people/post", &[], Some(json!({"name": name}))).await;
+        }
+        let sorted = [("sortBy", "name")];
+        let everything = json_body(&server.sarych("people/get", &sorted, None).await)["results"].clone();
+
+        let mut paged = Vec::new();
+        for page in ["1", "2", "3"] {
+            let response = json_body(&server.sarych("people/get", &[sorted[0], ("page", page), ("limit", "2")], None).await);
+            assert_eq!(response["pagination"]["filtered_records"], 5);
+            assert_eq!(response["pagination"]["total_pages"], 3);
+            paged.extend(response["results"].as_array().unwrap().iter().cloned());
+        }
+
+        assert_eq!(Value::Array(paged), everything);
+    }
+
+    #[tokio::test]
+    async fn browse_and_list_reject_page_and_limit_that_are_not_numbers() {
+        let server = TestServer::new();
+
+        for operation in ["people/browse", "people/list"] {
+            for headers in [[("page", "1.5"), ("limit", "5")], [("page", "1"), ("limit", "ten")]] {
+                let response = server.sarych(operation, &headers, None).await;
+
+                assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST, "{} {:?}", operation, headers);
+                assert_eq!(json_body(&response)["error"]["code"], "bad_request");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_filters_are_a_bad_request() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        let response = server.sarych("people/list", &[("filters", "{\"name\": ")], None).await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert!(json_body(&response)["error"]["message"].as_str().unwrap().starts_with("Invalid filters JSON: "));
+    }
+
+    #[tokio::test]
+    async fn anonymous_reads_only_reach_public_databases() {
+        let server = TestServer::new();
+        server.auth_service.create_database(CreateDbRequest {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+            db_name: "open".to_string(),
+            public: true,
+        }).unwrap();
+        server.sarych("open/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        let public_read = server.anonymous("open/get").await;
+        let private_read = server.anonymous("people/get").await;
+        let public_write = server.anonymous("open/delete?query=Ada").await;
+
+        assert_eq!(public_read.status(), warp::http::StatusCode::OK);
+        assert_eq!(json_body(&public_read)["results"][0]["name"], "Ada");
+        assert_eq!(private_read.status(), warp::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(public_write.status(), warp::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn read_only_grants_are_refused_writes() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+        server.auth_service.create_user(CreateUserRequest { username: "bob".to_string(), password: "secret".to_string() }).unwrap();
+        server.auth_service.grant_database(GrantDbRequest {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+            db_name: "people".to_string(),
+            grantee: "bob".to_string(),
+            access: AccessLevel::Read,
+        }).unwrap();
+
+        let read = server.sarych_as("bob", "people/get", &[], None).await;
+        let write = server.sarych_as("bob", "people/post", &[], Some(json!({"name": "Grace"}))).await;
+
+        assert_eq!(read.status(), warp::http::StatusCode::OK);
+        assert_eq!(json_body(&read)["results"][0]["name"], "Ada");
+        assert_eq!(write.status(), warp::http::StatusCode::FORBIDDEN);
+        assert_eq!(json_body(&write)["error"]["code"], "forbidden");
+        assert_eq!(server.db_manager.read_database("alice", "people").unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_regex_query_is_a_bad_request() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        let response = server.sarych("people/get?query=(unclosed", &[("queryType", "regex")], None).await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert!(json_body(&response)["error"]["message"].as_str().unwrap().starts_with("Invalid regex pattern"));
+    }
+
+    #[tokio::test]
+    async fn oversized_content_length_is_refused_before_the_body_is_read() {
+        let server = TestServer::new();
+        let announced = (DEFAULT_MAX_BODY_SIZE + 1).to_string();
+        let response = server.sarych_bytes("people/post", &[("content-type", "application/json"), ("content-length", &announced)], b"{}".to_vec()).await;
+
+        assert_eq!(response.status(), 413);
+        assert_eq!(json_body(&response)["error"]["code"], "payload_too_large");
+        assert_eq!(json_body(&server.sarych("people/get", &[], None).await)["results"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn body_growing_past_the_limit_is_refused_while_it_streams() {
+        let server = TestServer::new();
+        // warp::test always sends a Content-Length, so a small one stands in for the chunked body that has none
+        let mut body = b"{\"name\":\"".to_vec();
+        body.resize(DEFAULT_MAX_BODY_SIZE as usize + 1, b'a');
+        let response = server.sarych_bytes("people/post", &[("content-type", "application/json"), ("content-length", "16")], body).await;
+
+        assert_eq!(response.status(), 413);
+        assert_eq!(json_body(&response)["error"]["code"], "payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn rejections_get_the_error_schema_and_their_status() {
+        let server = TestServer::new();
+        let missing_url = server.send(warp::test::request().method("POST").path("/sarych")).await;
+        let invalid_json = server.sarych_bytes("people/post", &[("content-type", "application/json")], b"{\"name\":".to_vec()).await;
+        let unknown_route = server.send(warp::test::request().path("/api/nothing-here")).await;
+        let wrong_method = server.send(warp::test::request().method("GET").path("/api/databases/grant")).await;
+
+        for (response, status, code) in [
+            (&missing_url, 400, "bad_request"),
+            (&invalid_json, 400, "bad_request"),
+            (&unknown_route, 404, "not_found"),
+            (&wrong_method, 405, "method_not_allowed"),
+        ] {
+            assert_eq!(response.status(), status);
+            let body = json_body(response);
+            assert_eq!(body["error"]["code"], code);
+            assert!(body["error"]["message"].is_string());
+            assert_eq!(body["time"], 0);
+        }
+        assert_eq!(json_body(&missing_url)["error"]["message"], "Missing 'url' query parameter");
+        assert!(json_body(&invalid_json)["error"]["message"].as_str().unwrap().starts_with("Invalid JSON body"));
+    }
+
+    #[tokio::test]
+    async fn only_an_empty_body_counts_as_missing() {
+        let server = TestServer::new();
+        let json = [("content-type", "application/json")];
+        let empty = server.sarych_bytes("people/post", &json, Vec::new()).await;
+        let valid = server.sarych_bytes("people/post", &json, br#"{"name":"Ada"}"#.to_vec()).await;
+        let malformed = server.sarych_bytes("people/post", &json, br#"{"name":"Ada""#.to_vec()).await;
+
+        assert_eq!(empty.status(), 400);
+        assert_eq!(json_body(&empty)["error"]["message"], "Body required for POST operation");
+        assert_eq!(valid.status(), 200);
+        assert_eq!(malformed.status(), 400);
+        assert!(json_body(&malformed)["error"]["message"].as_str().unwrap().starts_with("Invalid JSON body"));
+        let stored = json_body(&server.sarych("people/get", &[], None).await);
+        assert_eq!(stored["results"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn form_bodies_are_stored_and_json_labelled_as_a_form_stays_json() {
+        let server = TestServer::new();
+        let form = [("content-type", "application/x-www-form-urlencoded")];
+        let posted = server.sarych_bytes("people/post", &form, b"name=Ada&age=36".to_vec()).await;
+        let curl_json = server.sarych_bytes("people/post", &form, br#"{"name":"Grace","age":85}"#.to_vec()).await;
+        // Not JSON, so it is read as a form: one key holding the whole text
+        let broken_json = server.sarych_bytes("people/post", &form, br#"{"name":"Linus""#.to_vec()).await;
+
+        assert_eq!(posted.status(), 200);
+        assert_eq!(curl_json.status(), 200);
+        assert_eq!(broken_json.status(), 200);
+        let stored = json_body(&server.sarych("people/get", &[], None).await)["results"].clone();
+        let stored = stored.as_array().unwrap();
+        assert_eq!(stored.len(), 3);
+        assert!(stored.iter().any(|r| r["name"] == "Ada" && r["age"] == 36));
+        assert!(stored.iter().any(|r| r["name"] == "Grace" && r["age"] == 85));
+        assert!(stored.iter().any(|r| r.get(r#"{"name":"Linus""#) == Some(&json!(""))));
+    }
+
+    #[tokio::test]
+    async fn responses_are_gzipped_only_when_the_client_accepts_it() {
+        let server = TestServer::new();
+        for n in 0..50 {
+            server.sarych("people/post", &[], Some(json!({"name": format!("person{}", n)}))).await;
+        }
+
+        let gzipped = server.sarych("people/get", &[("accept-encoding", "gzip, br")], None).await;
+        let plain = server.sarych("people/get", &[], None).await;
+
+        assert_eq!(gzipped.status(), 200);
+        assert_eq!(gzipped.headers()["content-encoding"], "gzip");
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(gzipped.body().as_ref()), &mut decoded).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&decoded).unwrap()["results"].as_array().unwrap().len(), 50);
+        assert!(plain.headers().get("content-encoding").is_none());
+        assert!(gzipped.body().len() < plain.body().len());
+    }
+
+    #[tokio::test]
+    async fn health_is_ok_while_the_users_file_and_folder_work() {
+        let server = TestServer::new();
+
+        let response = server.send(warp::test::request().path("/health")).await;
+
+        assert_eq!(response.status(), 200);
+        let body = json_body(&response);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["checks"]["users_file"], json!({"status": "ok", "users": 1}));
+        assert_eq!(body["checks"]["users_dir"]["status"], "ok");
+        assert!(body["checks"]["uptime_secs"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn unreadable_users_file_degrades_health_with_a_503() {
+        let server = TestServer::new();
+        let users_file = server.auth_service.users_file();
+        fs::write(&users_file, "not json").unwrap();
+        // A later modification time makes sure the cached users are not reused
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        fs::File::options().write(true).open(&users_file).unwrap().set_modified(later).unwrap();
+
+        let response = server.send(warp::test::request().path("/health")).await;
+
+        assert_eq!(response.status(), 503);
+        let body = json_body(&response);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["checks"]["users_file"]["status"], "error");
+        assert_eq!(body["checks"]["users_dir"]["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn get_by_id_returns_only_the_exact_id() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"_id": "a1", "name": "Ada"}))).await;
+        server.sarych("people/post", &[], Some(json!({"_id": "a10", "name": "Grace"}))).await;
+        server.sarych("people/post", &[], Some(json!({"_id": "b2", "name": "a1 fan"}))).await;
+
+        let by_header = json_body(&server.sarych("people/get", &[("getById", "a1")], None).await);
+        let by_query_type = json_body(&server.sarych("people/get?query=a1", &[("queryType", "id")], None).await);
+
+        for response in [&by_header, &by_query_type] {
+            assert_eq!(response["query_type"], "id");
+            assert_eq!(response["count"], 1);
+            assert_eq!(response["results"][0]["name"], "Ada");
+        }
+    }
+
+    #[tokio::test]
+    async fn get_by_an_unknown_id_returns_no_results() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"_id": "a1", "name": "Ada"}))).await;
+
+        let response = server.sarych("people/get", &[("getById", "zz")], None).await;
+        let empty_id = server.sarych("people/get", &[("getById", "")], None).await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(json_body(&response)["count"], 0);
+        assert_eq!(json_body(&response)["results"], json!([]));
+        assert_eq!(empty_id.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn explain_reports_how_the_search_ran() {
+        let server = TestServer::new();
+        for name in ["Ada", "Grace", "Adam"] {
+            server.sarych("people/post", &[], Some(json!({"name": name}))).await;
+        }
+        let explain = [("explain", "true"), ("nodes", "2")];
+
+        let cold = json_body(&server.sarych("people/get?query=Ada", &explain, None).await);
+        let warm = json_body(&server.sarych("people/get?query=Ada", &explain, None).await);
+        let by_key = json_body(&server.sarych("people/get?query=name", &[("explain", "true"), ("queryType", "key")], None).await);
+        let unexplained = json_body(&server.sarych("people/get?query=Ada", &[], None).await);
+
+        // The sequential/parallel choice depends on a process-wide threshold other tests change, so it isn't checked
+        assert_eq!(cold["count"], 2);
+        assert_eq!(cold["explain"]["nodes"], 2);
+        assert_eq!(cold["explain"]["total_items"], 3);
+        assert_eq!(cold["explain"]["search_cache_hit"], false);
+        assert_eq!(cold["explain"]["index_used"], false);
+        assert!(cold["explain"]["estimated_cost"].is_u64());
+        assert_eq!(warm["explain"]["method"], "search_cache");
+        assert_eq!(warm["explain"]["search_cache_hit"], true);
+        assert_eq!(warm["explain"]["database_cache_hit"], true);
+        assert_eq!(by_key["explain"]["method"], "key_scan");
+        assert_eq!(by_key["explain"]["search_cache_hit"], Value::Null);
+        assert!(unexplained.get("explain").is_none());
+    }
+
+    #[tokio::test]
+    async fn raw_output_is_the_bare_records_array() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+        server.sarych("people/post", &[], Some(json!({"name": "Grace"}))).await;
+
+        for (operation, records_key) in [("get", "results"), ("browse", "data"), ("list", "data")] {
+            let path = format!("people/{}", operation);
+            let wrapped = json_body(&server.sarych(&path, &[], None).await);
+            let raw = server.sarych(&path, &[("raw", "true")], None).await;
+
+            assert_eq!(raw.status(), 200);
+            assert_eq!(raw.headers()["content-type"], "application/json");
+            assert!(wrapped["time"].is_u64());
+            assert_eq!(json_body(&raw), wrapped[records_key]);
+        }
+        let write = server.sarych("people/post", &[("raw", "true")], Some(json!({"name": "Linus"}))).await;
+        assert_eq!(write.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn pretty_output_is_indented_and_still_timed() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        let compact = server.sarych("people/get", &[], None).await;
+        let pretty = server.sarych("people/get", &[("pretty", "true")], None).await;
+        let by_accept = server.sarych("people/get", &[("accept", "application/json; pretty=true")], None).await;
+
+        assert!(!compact.body().contains(&b'\n'));
+        for response in [&pretty, &by_accept] {
+            assert!(response.body().contains(&b'\n'));
+            assert!(json_body(response)["time"].is_u64());
+            assert_eq!(json_body(response)["results"], json_body(&compact)["results"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn warming_makes_the_next_read_a_cache_hit() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+
+        let warmed = server.send(warp::test::request()
+            .method("POST")
+            .path("/api/cache/warm?username=alice&password=secret")
+            .json(&json!({"databases": ["people"], "queries": ["Ada"]}))).await;
+        let read = json_body(&server.sarych("people/get?query=Ada", &[("explain", "true")], None).await);
+
+        assert_eq!(warmed.status(), 200);
+        assert_eq!(json_body(&warmed)["databases"], json!([{
+            "database": "people",
+            "records": 1,
+            "already_cached": false,
+            "queries": [{"query": "Ada", "matches": 1}],
+        }]));
+        assert_eq!(read["explain"]["database_cache_hit"], true);
+        assert_eq!(read["explain"]["search_cache_hit"], true);
+    }
+
+    #[tokio::test]
+    async fn warming_an_unknown_database_warms_nothing() {
+        let server = TestServer::new();
+
+        let response = server.send(warp::test::request()
+            .method("POST")
+            .path("/api/cache/warm?username=alice&password=secret")
+            .json(&json!({"databases": ["people", "missing"]}))).await;
+
+        assert_eq!(response.status(), 404);
+        let read = json_body(&server.sarych("people/get", &[("explain", "true")], None).await);
+        assert_eq!(read["explain"]["database_cache_hit"], false);
+    }
+
+    #[tokio::test]
+    async fn detailed_list_matches_the_stats_of_each_database() {
+        let server = TestServer::new();
+        for db_name in ["notes", "ghost"] {
+            server.auth_service.create_database(CreateDbRequest {
+                username: "alice".to_string(),
+                password: "secret".to_string(),
+                db_name: db_name.to_string(),
+                public: false,
+            }).unwrap();
+        }
+        server.sarych("people/post", &[], Some(json!({"name": "Ada"}))).await;
+        server.sarych("people/post", &[], Some(json!({"name": "Grace"}))).await;
+        server.sarych("notes/post", &[], Some(json!({"text": "hello"}))).await;
+        fs::remove_file(server.db_manager.get_db_path("alice", "ghost").unwrap()).unwrap();
+
+        let response = server.send(warp::test::request().path("/api/databases/detailed?username=alice&password=secret")).await;
+
+        assert_eq!(response.status(), 200);
+        let databases = json_body(&response)["databases"].clone();
+        for (i, db_name) in ["people", "notes"].into_iter().enumerate() {
+            let stats = server.db_manager.get_stats("alice", db_name).unwrap();
+            assert_eq!(databases[i]["namedb"], db_name);
+            assert_eq!(databases[i]["total_records"], stats["total_records"]);
+            assert_eq!(databases[i]["size_bytes"], stats["size_bytes"]);
+        }
+        assert_eq!(databases[2]["namedb"], "ghost");
+        assert_eq!(databases[2]["total_records"], Value::Null);
+        assert!(databases[2]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn exists_counts_matches_without_returning_them() {
+        let server = TestServer::new();
+        for name in ["Ada", "Adam", "Grace"] {
+            server.sarych("people/post", &[], Some(json!({"_id": name.to_lowercase(), "name": name}))).await;
+        }
+
+        let found = json_body(&server.sarych("people/exists?query=Ada", &[], None).await);
+        let counted = json_body(&server.sarych("people/exists?query=Ada", &[("maxResults", "10")], None).await);
+        let by_id = json_body(&server.sarych("people/exists", &[("getById", "grace")], None).await);
+        let missing = json_body(&server.sarych("people/exists?query=Linus", &[], None).await);
+
+        // One match is enough to answer unless maxResults asks for more
+        assert_eq!((found["exists"].clone(), found["count"].clone()), (json!(true), json!(1)));
+        assert_eq!(counted["count"], 2);
+        assert_eq!(by_id["exists"], true);
+        assert_eq!((missing["exists"].clone(), missing["count"].clone()), (json!(false), json!(0)));
+        for response in [&found, &counted, &by_id, &missing] {
+            assert!(response.get("results").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn version_and_health_report_the_crate_version() {
+        let server = TestServer::new();
+
+        let version = json_body(&server.send(warp::test::request().path("/api/version")).await);
+        let health = json_body(&server.send(warp::test::request().path("/health")).await);
+
+        assert_eq!(version["service"], "SarychDB");
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["git_commit"], GIT_COMMIT);
+        assert!(chrono::DateTime::parse_from_rfc3339(version["built_at"].as_str().unwrap()).is_ok());
+        assert_eq!(health["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn preflight_allows_every_api_header_and_is_cacheable() {
+        let server = TestServer::new();
+        let requested = SarychHeaders::NAMES.join(", ");
+
+        let response = server.send(warp::test::request()
+            .method("OPTIONS")
+            .path("/sarych")
+            .header("origin", "https://app.example")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", requested.as_str())).await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["access-control-max-age"], cors_max_age().as_secs().to_string().as_str());
+        let allowed: std::collections::HashSet<String> = response.headers()["access-control-allow-headers"].to_str().unwrap()
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+        for name in SarychHeaders::NAMES {
+            assert!(allowed.contains(&name.to_lowercase()), "{} is not allowed", name);
+        }
+        let unknown = server.send(warp::test::request()
+            .method("OPTIONS")
+            .path("/sarych")
+            .header("origin", "https://app.example")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "x-not-an-api-header")).await;
+        assert_eq!(unknown.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn nodes_header_sets_the_split_up_to_the_cap() {
+        let server = TestServer::new();
+        let records = (0..2 * MAX_SEARCH_NODES).map(|n| json!({"name": format!("person{}", n)})).collect();
+        server.db_manager.import_records("alice", "people", records, false).unwrap();
+        let nodes = |response: &Value| response["explain"]["nodes"].clone();
+
+        let three = json_body(&server.sarych("people/get?query=person", &[("explain", "true"), ("nodes", "3")], None).await);
+        let too_many = json_body(&server.sarych("people/get?query=person", &[("explain", "true"), ("nodes", "100000")], None).await);
+        let zero = server.sarych("people/get?query=person", &[("nodes", "0")], None).await;
+
+        assert_eq!(nodes(&three), 3);
+        assert_eq!(three["count"], 2 * MAX_SEARCH_NODES);
+        // Two records per chunk once the count is capped, one each if it were not
+        assert_eq!(nodes(&too_many), MAX_SEARCH_NODES);
+        assert_eq!(zero.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn each_successful_write_appends_one_audit_line() {
+        let server = TestServer::new();
+        let audit = || async {
+            json_body(&server.send(warp::test::request().path("/api/audit?username=alice&password=secret&limit=100")).await)["entries"].clone()
+        };
+
+        server.sarych("people/post", &[], Some(json!({"_id": "ada", "name": "Ada"}))).await;
+        server.sarych("people/post", &[], Some(json!({"_id": "grace", "name": "Grace"}))).await;
+        server.sarych("people/put?query=Ada", &[], Some(json!({"born": 1815}))).await;
+        // Reads and failed writes leave no trace
+        server.sarych("people/get", &[], None).await;
+        server.sarych("people/post", &[], Some(json!({"_id": "ada"}))).await;
+        assert_eq!(audit().await.as_array().unwrap().len(), 3);
+
+        server.sarych("people/delete?query=Grace", &[], None).await;
+
+        let entries = audit().await;
+        let lines: Vec<(&str, u64)> = entries.as_array().unwrap().iter()
+            .map(|entry| (entry["operation"].as_str().unwrap(), entry["affected_count"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(lines, [("post", 1), ("post", 1), ("put", 1), ("delete", 1)]);
+        assert_eq!(entries[3]["user"], "alice");
+        assert_eq!(entries[3]["database"], "people");
+        assert_eq!(entries[3]["query"], "Grace");
+    }
+
+    #[tokio::test]
+    async fn a_custom_id_field_is_used_to_insert_get_and_update() {
+        let server = TestServer::new();
+        let settings = server.sarych("people/settings", &[], Some(json!({"id_field": "sku"}))).await;
+        assert_eq!(settings.status(), 200);
+
+        server.sarych("people/post", &[], Some(json!({"sku": "A-1", "name": "Tent"}))).await;
+        server.sarych("people/post", &[], Some(json!({"name": "Torch"}))).await;
+        let duplicate = server.sarych("people/post", &[], Some(json!({"sku": "A-1", "name": "Copy"}))).await;
+        let updated = server.sarych("people/put", &[("idUpdate", "A-1")], Some(json!({"price": 120}))).await;
+        let found = json_body(&server.sarych("people/get", &[("getById", "A-1")], None).await);
+
+        assert_eq!(duplicate.status(), 409);
+        assert_eq!(updated.status(), 200);
+        assert_eq!(found["count"], 1);
+        assert_eq!(found["results"][0]["name"], "Tent");
+        assert_eq!(found["results"][0]["price"], 120);
+        let all = json_body(&server.sarych("people/get", &[], None).await)["results"].clone();
+        let torch = all.as_array().unwrap().iter().find(|record| record["name"] == "Torch").unwrap();
+        assert!(uuid::Uuid::parse_str(torch["sku"].as_str().unwrap()).is_ok());
+        assert!(all.as_array().unwrap().iter().all(|record| record.get("_id").is_none()));
+    }
+
+    #[tokio::test]
+    async fn flatten_header_flattens_the_returned_page_of_get_and_list() {
+        let server = TestServer::new();
+        server.sarych("people/post", &[], Some(json!({"_id": "ada", "address": {"city": "London"}, "tags": ["math"]}))).await;
+        server.sarych("people/post", &[], Some(json!({"_id": "grace", "address": {"city": "New York"}}))).await;
+
+        let got = json_body(&server.sarych("people/get", &[("flatten", "true"), ("getById", "ada")], None).await);
+        let listed = json_body(&server.sarych("people/list", &[("flatten", "true"), ("sortBy", "address.city"), ("limit", "1"), ("page", "2")], None).await);
+        let nested = json_body(&server.sarych("people/get", &[("getById", "ada")], None).await);
+
+        assert_eq!(got["results"][0]["address.city"], "London");
+        assert_eq!(got["results"][0]["tags.0"], "math");
+        assert_eq!(listed["data"].as_array().unwrap().len(), 1);
+        assert_eq!(listed["data"][0]["address.city"], "New York");
+        assert!(listed["data"][0].get("address").is_none());
+        assert_eq!(nested["results"][0]["address"], json!({"city": "London"}));
+    }
+}